@@ -0,0 +1,68 @@
+//! Run with `cargo bench --bench call`.
+
+use evm::types::{Address, Environment, State, Transaction};
+use ruint::{aliases::U256, uint};
+use std::{hint::black_box, time::Instant};
+
+const ITERATIONS: u32 = 100;
+
+/// CALL `callee` with `size` bytes of arguments.
+fn call_with_args(callee: &Address, size: u32) -> Vec<u8> {
+    // PUSH1 0 PUSH1 0
+    let mut code = vec![0x60, 0x00, 0x60, 0x00, 0x63];
+    code.extend(size.to_be_bytes());
+    // PUSH1 0 PUSH1 0 PUSH20 callee GAS CALL
+    code.extend([0x60, 0x00, 0x60, 0x00, 0x73]);
+    code.extend(&<U256 as From<&Address>>::from(callee).to_be_bytes::<0x20>()[0x0C..]);
+    code.extend([0x5A, 0xF1]);
+    code
+}
+
+fn run(bytecode: &[u8], callee: &Address) -> bool {
+    let caller = Address::default();
+    let contract: Address = uint!(0x000000000000000000000000000000000000dead_U160).into();
+    let zero = U256::ZERO;
+    // CALLDATASIZE
+    let state = State::builder()
+        .deploy(contract.clone(), bytecode.to_vec())
+        .deploy(callee.clone(), vec![0x36])
+        .build();
+    let mut env = Environment::new(
+        &caller,
+        &[],
+        &caller,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        state,
+        &zero,
+    );
+    let transaction = Transaction::new(
+        zero,
+        U256::MAX,
+        caller.clone(),
+        Some(contract),
+        zero,
+        vec![],
+    );
+    transaction.process(&mut env).success
+}
+
+fn main() {
+    let callee: Address = uint!(0x000000000000000000000000000000000000beef_U160).into();
+    for size in [0x400, 0x10000] {
+        let code = call_with_args(&callee, size);
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            assert!(black_box(run(black_box(&code), &callee)));
+        }
+        println!(
+            "CALL with {:>6} bytes of arguments: {:?}/iter",
+            size,
+            start.elapsed() / ITERATIONS
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! Run with `cargo bench --bench interpreter`.
+//!
+//! Representative workloads, to compare the interpreter before and after a change.
+//!
+//! The crate has no benchmark dependency, so like the `mcopy` and `call` benches this one
+//! times a fixed number of iterations with `Instant` instead of using criterion. The
+//! workloads are plain functions returning bytecode, they can be moved to a
+//! `criterion_group!` unchanged once criterion is added as a dev-dependency.
+
+use evm::types::{Address, Environment, State, Transaction};
+use ruint::{aliases::U256, uint};
+use std::{hint::black_box, time::Instant};
+
+const ITERATIONS: u32 = 20;
+
+/// Decrement a counter from `count` to zero.
+fn arithmetic_loop(count: u16) -> Vec<u8> {
+    let mut code = vec![0x61];
+    code.extend(count.to_be_bytes());
+    // JUMPDEST PUSH1 1 SWAP1 SUB DUP1 PUSH1 3 JUMPI STOP
+    code.extend([0x5B, 0x60, 0x01, 0x90, 0x03, 0x80, 0x60, 0x03, 0x57, 0x00]);
+    code
+}
+
+/// Copy 4 KiB of memory `count` times.
+fn memory_copy_loop(count: u16) -> Vec<u8> {
+    let mut code = vec![0x61];
+    code.extend(count.to_be_bytes());
+    // JUMPDEST PUSH2 0x1000 PUSH1 0 PUSH2 0x1000 MCOPY
+    code.extend([0x5B, 0x61, 0x10, 0x00, 0x60, 0x00, 0x61, 0x10, 0x00, 0x5E]);
+    // PUSH1 1 SWAP1 SUB DUP1 PUSH1 3 JUMPI STOP
+    code.extend([0x60, 0x01, 0x90, 0x03, 0x80, 0x60, 0x03, 0x57, 0x00]);
+    code
+}
+
+/// Call itself twice with the depth from the calldata minus one, until it is zero.
+fn call_tree() -> Vec<u8> {
+    // PUSH1 0 CALLDATALOAD DUP1 ISZERO PUSH1 0x2C JUMPI
+    let mut code = vec![0x60, 0x00, 0x35, 0x80, 0x15, 0x60, 0x2C, 0x57];
+    // PUSH1 1 SWAP1 SUB PUSH1 0 MSTORE
+    code.extend([0x60, 0x01, 0x90, 0x03, 0x60, 0x00, 0x52]);
+    for _ in 0..2 {
+        // PUSH1 0 PUSH1 0 PUSH1 0x20 PUSH1 0 PUSH1 0 ADDRESS GAS CALL POP
+        code.extend([
+            0x60, 0x00, 0x60, 0x00, 0x60, 0x20, 0x60, 0x00, 0x60, 0x00, 0x30, 0x5A, 0xF1, 0x50,
+        ]);
+    }
+    // STOP JUMPDEST STOP
+    code.extend([0x00, 0x5B, 0x00]);
+    code
+}
+
+/// Process a transaction to a contract with `bytecode`, like the tests of `main.rs`.
+fn run(bytecode: &[u8], data: Vec<u8>) -> bool {
+    let caller = Address::default();
+    let contract: Address = uint!(0x000000000000000000000000000000000000dead_U160).into();
+    let zero = U256::ZERO;
+    let state = State::builder()
+        .deploy(contract.clone(), bytecode.to_vec())
+        .build();
+    let mut env = Environment::new(
+        &caller,
+        &[],
+        &caller,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        state,
+        &zero,
+    );
+    let transaction = Transaction::new(zero, U256::MAX, caller.clone(), Some(contract), zero, data);
+    transaction.process(&mut env).success
+}
+
+fn bench(name: &str, bytecode: &[u8], data: Vec<u8>) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert!(black_box(run(black_box(bytecode), data.clone())));
+    }
+    println!("{:<36}: {:?}/iter", name, start.elapsed() / ITERATIONS);
+}
+
+fn main() {
+    bench(
+        "arithmetic loop of 10000 iterations",
+        &arithmetic_loop(10000),
+        vec![],
+    );
+    bench(
+        "1000 copies of 4 KiB of memory",
+        &memory_copy_loop(1000),
+        vec![],
+    );
+    let depth = U256::from(10).to_be_bytes::<0x20>().to_vec();
+    bench("call tree of depth 10", &call_tree(), depth);
+}
@@ -0,0 +1,63 @@
+//! Run with `cargo bench --bench mcopy`.
+
+use evm::types::{Account, Address, Environment, State, Transaction};
+use ruint::{aliases::U256, uint};
+use std::{collections::HashMap, hint::black_box, time::Instant};
+
+const ITERATIONS: u32 = 100;
+
+/// Copy `size` bytes from offset 0 to offset 1, overlapping the whole region.
+fn overlapping_mcopy(size: u32) -> Vec<u8> {
+    let mut code = vec![0x63];
+    code.extend(size.to_be_bytes());
+    // PUSH1 0 PUSH1 1 MCOPY
+    code.extend([0x60, 0x00, 0x60, 0x01, 0x5E]);
+    code
+}
+
+fn run(bytecode: &[u8]) -> bool {
+    let caller = Address::default();
+    let contract: Address = uint!(0x000000000000000000000000000000000000dead_U160).into();
+    let zero = U256::ZERO;
+    let state = State::new(HashMap::from([(
+        contract.clone(),
+        Account::new(None, Some(bytecode.into())),
+    )]));
+    let mut env = Environment::new(
+        &caller,
+        &[],
+        &caller,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        &zero,
+        state,
+        &zero,
+    );
+    let transaction = Transaction::new(
+        zero,
+        U256::MAX,
+        caller.clone(),
+        Some(contract),
+        zero,
+        vec![],
+    );
+    transaction.process(&mut env).success
+}
+
+fn main() {
+    for size in [0x400, 0x10000, 0x100000] {
+        let code = overlapping_mcopy(size);
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            assert!(black_box(run(black_box(&code))));
+        }
+        println!(
+            "overlapping MCOPY of {:>8} bytes: {:?}/iter",
+            size,
+            start.elapsed() / ITERATIONS
+        );
+    }
+}
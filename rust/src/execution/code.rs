@@ -1,3 +1,5 @@
+use super::eof::EofContainer;
+use crate::types::{Bytes, Spec, SpecConfig};
 use ruint::aliases::U256;
 use thiserror::Error;
 
@@ -6,10 +8,13 @@ pub(super) struct Code {
     bytecode: Vec<u8>,
     opcodes: Vec<Option<Opcode>>,
     pc: usize,
+    /// The offset of the opcode being executed.
+    current_op_offset: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub(super) enum Opcode {
+/// The instructions of the virtual machine.
+pub enum Opcode {
     STOP,
     ADD,
     MUL,
@@ -62,6 +67,8 @@ pub(super) enum Opcode {
     CHAINID,
     BASEFEE,
     SELFBALANCE,
+    BLOBHASH,
+    BLOBBASEFEE,
     POP,
     MLOAD,
     MSTORE,
@@ -74,9 +81,20 @@ pub(super) enum Opcode {
     MSIZE,
     GAS,
     JUMPDEST,
-    PUSH(U256),
+    TLOAD,
+    TSTORE,
+    MCOPY,
+    /// `PUSH(n, value)` pushes the `n` bytes long immediate `value`.
+    PUSH(usize, U256),
     DUP(usize),
     SWAP(usize),
+    /// `DUPN(n)` duplicates the `n + 1`th stack item (EIP-663).
+    DUPN(u8),
+    /// `SWAPN(n)` swaps the top of the stack with the `n + 2`th item (EIP-663).
+    SWAPN(u8),
+    /// `EXCHANGE(nm)` swaps the stack items `n + 1` and `n + m + 2` below the top,
+    /// with `n` and `m` the nibbles of the immediate byte (EIP-663).
+    EXCHANGE(u8),
     LOG(usize),
     CREATE,
     CALL,
@@ -86,19 +104,282 @@ pub(super) enum Opcode {
     REVERT,
     INVALID,
     SELFDESTRUCT,
+    /// A debugging breakpoint on the unassigned 0xB0, only enabled in debug mode.
+    BREAKPOINT,
+}
+
+impl Opcode {
+    /// Decode a byte into its opcode and the size of its immediate data.
+    /// `PUSH` opcodes are decoded with a zero value.
+    pub fn from_byte(byte: u8) -> (Opcode, usize) {
+        use Opcode::*;
+        let opcode = match byte {
+            0x00 => STOP,
+            0x01 => ADD,
+            0x02 => MUL,
+            0x03 => SUB,
+            0x04 => DIV,
+            0x05 => SDIV,
+            0x06 => MOD,
+            0x07 => SMOD,
+            0x08 => ADDMOD,
+            0x09 => MULMOD,
+            0x0A => EXP,
+            0x0B => SIGNEXTEND,
+            0x10 => LT,
+            0x11 => GT,
+            0x12 => SLT,
+            0x13 => SGT,
+            0x14 => EQ,
+            0x15 => ISZERO,
+            0x16 => AND,
+            0x17 => OR,
+            0x18 => XOR,
+            0x19 => NOT,
+            0x1A => BYTE,
+            0x1B => SHL,
+            0x1C => SHR,
+            0x1D => SAR,
+            0x20 => SHA3,
+            0x30 => ADDRESS,
+            0x31 => BALANCE,
+            0x32 => ORIGIN,
+            0x33 => CALLER,
+            0x34 => CALLVALUE,
+            0x35 => CALLDATALOAD,
+            0x36 => CALLDATASIZE,
+            0x37 => CALLDATACOPY,
+            0x38 => CODESIZE,
+            0x39 => CODECOPY,
+            0x3A => GASPRICE,
+            0x3B => EXTCODESIZE,
+            0x3C => EXTCODECOPY,
+            0x3D => RETURNDATASIZE,
+            0x3E => RETURNDATACOPY,
+            0x3F => EXTCODEHASH,
+            0x40 => BLOCKHASH,
+            0x41 => COINBASE,
+            0x42 => TIMESTAMP,
+            0x43 => NUMBER,
+            0x44 => DIFFICULTY,
+            0x45 => GASLIMIT,
+            0x46 => CHAINID,
+            0x47 => SELFBALANCE,
+            0x48 => BASEFEE,
+            0x49 => BLOBHASH,
+            0x4A => BLOBBASEFEE,
+            0x50 => POP,
+            0x51 => MLOAD,
+            0x52 => MSTORE,
+            0x53 => MSTORE8,
+            0x54 => SLOAD,
+            0x55 => SSTORE,
+            0x56 => JUMP,
+            0x57 => JUMPI,
+            0x58 => PC,
+            0x59 => MSIZE,
+            0x5A => GAS,
+            0x5B => JUMPDEST,
+            0x5C => TLOAD,
+            0x5D => TSTORE,
+            0x5E => MCOPY,
+            0x5F..=0x7F => {
+                // 0 <= n <= 32, PUSH0 pushes a zero.
+                let n: usize = (byte - 0x5F).into();
+                PUSH(n, U256::ZERO)
+            }
+            0x80..=0x8F => {
+                // 1 <= n <= 16
+                let n: usize = (byte - 0x7F).into();
+                DUP(n)
+            }
+            0x90..=0x9F => {
+                // 1 <= n <= 16
+                let n: usize = (byte - 0x8F).into();
+                SWAP(n)
+            }
+            0xA0..=0xA4 => {
+                // 0 <= n <= 4
+                let n: usize = (byte - 0xA0).into();
+                LOG(n)
+            }
+            0xB0 => BREAKPOINT,
+            0xE6 => DUPN(0),
+            0xE7 => SWAPN(0),
+            0xE8 => EXCHANGE(0),
+            0xF0 => CREATE,
+            0xF1 => CALL,
+            0xF3 => RETURN,
+            0xF4 => DELEGATECALL,
+            0xFA => STATICCALL,
+            0xFD => REVERT,
+            0xFF => SELFDESTRUCT,
+            _ => INVALID,
+        };
+
+        let immediate_size = match opcode {
+            PUSH(n, _) => n,
+            DUPN(_) | SWAPN(_) | EXCHANGE(_) => 1,
+            _ => 0,
+        };
+        (opcode, immediate_size)
+    }
+
+    /// Encode the opcode into its byte, ignoring any immediate data.
+    pub fn to_byte(&self) -> u8 {
+        use Opcode::*;
+        match self {
+            STOP => 0x00,
+            ADD => 0x01,
+            MUL => 0x02,
+            SUB => 0x03,
+            DIV => 0x04,
+            SDIV => 0x05,
+            MOD => 0x06,
+            SMOD => 0x07,
+            ADDMOD => 0x08,
+            MULMOD => 0x09,
+            EXP => 0x0A,
+            SIGNEXTEND => 0x0B,
+            LT => 0x10,
+            GT => 0x11,
+            SLT => 0x12,
+            SGT => 0x13,
+            EQ => 0x14,
+            ISZERO => 0x15,
+            AND => 0x16,
+            OR => 0x17,
+            XOR => 0x18,
+            NOT => 0x19,
+            BYTE => 0x1A,
+            SHL => 0x1B,
+            SHR => 0x1C,
+            SAR => 0x1D,
+            SHA3 => 0x20,
+            ADDRESS => 0x30,
+            BALANCE => 0x31,
+            ORIGIN => 0x32,
+            CALLER => 0x33,
+            CALLVALUE => 0x34,
+            CALLDATALOAD => 0x35,
+            CALLDATASIZE => 0x36,
+            CALLDATACOPY => 0x37,
+            CODESIZE => 0x38,
+            CODECOPY => 0x39,
+            GASPRICE => 0x3A,
+            EXTCODESIZE => 0x3B,
+            EXTCODECOPY => 0x3C,
+            RETURNDATASIZE => 0x3D,
+            RETURNDATACOPY => 0x3E,
+            EXTCODEHASH => 0x3F,
+            BLOCKHASH => 0x40,
+            COINBASE => 0x41,
+            TIMESTAMP => 0x42,
+            NUMBER => 0x43,
+            DIFFICULTY => 0x44,
+            GASLIMIT => 0x45,
+            CHAINID => 0x46,
+            SELFBALANCE => 0x47,
+            BASEFEE => 0x48,
+            BLOBHASH => 0x49,
+            BLOBBASEFEE => 0x4A,
+            POP => 0x50,
+            MLOAD => 0x51,
+            MSTORE => 0x52,
+            MSTORE8 => 0x53,
+            SLOAD => 0x54,
+            SSTORE => 0x55,
+            JUMP => 0x56,
+            JUMPI => 0x57,
+            PC => 0x58,
+            MSIZE => 0x59,
+            GAS => 0x5A,
+            JUMPDEST => 0x5B,
+            TLOAD => 0x5C,
+            TSTORE => 0x5D,
+            MCOPY => 0x5E,
+            CREATE => 0xF0,
+            CALL => 0xF1,
+            RETURN => 0xF3,
+            DELEGATECALL => 0xF4,
+            STATICCALL => 0xFA,
+            REVERT => 0xFD,
+            SELFDESTRUCT => 0xFF,
+            // 1 <= n <= 32
+            PUSH(n, _) => 0x5F + *n as u8,
+            // 1 <= n <= 16
+            DUP(n) => 0x7F + *n as u8,
+            // 1 <= n <= 16
+            SWAP(n) => 0x8F + *n as u8,
+            // 0 <= n <= 4
+            LOG(n) => 0xA0 + *n as u8,
+            DUPN(_) => 0xE6,
+            SWAPN(_) => 0xE7,
+            EXCHANGE(_) => 0xE8,
+            INVALID => 0xFE,
+            BREAKPOINT => 0xB0,
+        }
+    }
+}
+
+impl Opcode {
+    /// Whether the fork configured by `config` introduced the opcode.
+    pub fn is_enabled(&self, config: &SpecConfig) -> bool {
+        use Opcode::*;
+        match self {
+            DELEGATECALL => config.delegatecall,
+            RETURNDATASIZE | RETURNDATACOPY | STATICCALL | REVERT => config.byzantium_opcodes,
+            SHL | SHR | SAR => config.bitwise_shifts,
+            EXTCODEHASH => config.extcodehash,
+            CHAINID => config.chain_id,
+            SELFBALANCE => config.trie_repricing,
+            BASEFEE => config.base_fee,
+            PUSH(0, _) => config.push0,
+            TLOAD | TSTORE => config.transient_storage,
+            MCOPY => config.mcopy,
+            BLOBHASH | BLOBBASEFEE => config.blobs,
+            DUPN(_) | SWAPN(_) | EXCHANGE(_) => config.eof,
+            _ => true,
+        }
+    }
 }
 
 impl Code {
-    pub fn new(bytecode: &[u8]) -> Code {
+    pub fn new(bytecode: &[u8], spec: Spec) -> Code {
+        // Only the code section of an EOF container is executed.
+        // An invalid container is kept as is, its 0xEF prefix is an INVALID opcode.
+        let container = match SpecConfig::for_fork(spec).eof && EofContainer::is_eof(bytecode) {
+            true => EofContainer::decode(bytecode).ok(),
+            false => None,
+        };
+        let eof = container.is_some();
+        let bytecode = container.map_or(bytecode, |container| container.code());
+
         Code {
             bytecode: bytecode.to_owned(),
-            opcodes: Code::opcodes(bytecode),
+            opcodes: Code::opcodes(bytecode, spec, eof),
             pc: 0,
+            current_op_offset: 0,
         }
     }
 
-    pub(super) fn pc(&self) -> usize {
-        self.pc
+    pub(super) fn current_op_offset(&self) -> usize {
+        self.current_op_offset
+    }
+
+    /// The next opcode to execute with its offset, without executing it.
+    pub(super) fn peek(&self) -> (usize, Opcode) {
+        let mut pc = self.pc;
+
+        // Get the next opcode by filtering the empty push data slots.
+        loop {
+            match self.opcodes.get(pc) {
+                // STOP if there are no opcode to execute.
+                None => break (pc, Opcode::STOP),
+                Some(Some(op)) => break (pc, op.clone()),
+                Some(None) => pc += 1,
+            }
+        }
     }
 
     pub(super) fn size(&self) -> usize {
@@ -125,125 +406,47 @@ impl Code {
         }
     }
 
-    pub(crate) fn load(&self, offset: usize, size: usize) -> Vec<u8> {
-        let mut bytes = vec![0x00; size];
-        for n in 0..size {
-            let b = self.bytecode.get(offset + n).unwrap_or(&0x00);
-            bytes[n] = *b;
-        }
-        bytes
+    pub(crate) fn load(&self, offset: usize, size: usize) -> Box<[u8]> {
+        Bytes::new(&self.bytecode).load_padded(offset, size)
     }
 
-    fn opcodes(bytecode: &[u8]) -> Vec<Option<Opcode>> {
+    /// The opcodes indexed by their offset in the bytecode, `None` for push data.
+    /// The opcodes not yet introduced in `spec` are INVALID, like the EOF only opcodes when
+    /// the bytecode is not the code section of an `eof` container.
+    pub(super) fn opcodes(bytecode: &[u8], spec: Spec, eof: bool) -> Vec<Option<Opcode>> {
+        let config = SpecConfig::for_fork(spec);
         let mut opcodes = vec![None; bytecode.len()];
         let mut pc = 0;
 
         while pc < opcodes.len() {
-            let byte = bytecode[pc];
-            let mut counter = pc + 1;
-
-            use Opcode::*;
-            let opcode = match byte {
-                0x00 => STOP,
-                0x01 => ADD,
-                0x02 => MUL,
-                0x03 => SUB,
-                0x04 => DIV,
-                0x05 => SDIV,
-                0x06 => MOD,
-                0x07 => SMOD,
-                0x08 => ADDMOD,
-                0x09 => MULMOD,
-                0x0A => EXP,
-                0x0B => SIGNEXTEND,
-                0x10 => LT,
-                0x11 => GT,
-                0x12 => SLT,
-                0x13 => SGT,
-                0x14 => EQ,
-                0x15 => ISZERO,
-                0x16 => AND,
-                0x17 => OR,
-                0x18 => XOR,
-                0x19 => NOT,
-                0x1A => BYTE,
-                0x1B => SHL,
-                0x1C => SHR,
-                0x1D => SAR,
-                0x20 => SHA3,
-                0x30 => ADDRESS,
-                0x31 => BALANCE,
-                0x32 => ORIGIN,
-                0x33 => CALLER,
-                0x34 => CALLVALUE,
-                0x35 => CALLDATALOAD,
-                0x36 => CALLDATASIZE,
-                0x37 => CALLDATACOPY,
-                0x38 => CODESIZE,
-                0x39 => CODECOPY,
-                0x3A => GASPRICE,
-                0x3B => EXTCODESIZE,
-                0x3C => EXTCODECOPY,
-                0x3D => RETURNDATASIZE,
-                0x3E => RETURNDATACOPY,
-                0x3F => EXTCODEHASH,
-                0x40 => BLOCKHASH,
-                0x41 => COINBASE,
-                0x42 => TIMESTAMP,
-                0x43 => NUMBER,
-                0x44 => DIFFICULTY,
-                0x45 => GASLIMIT,
-                0x46 => CHAINID,
-                0x47 => SELFBALANCE,
-                0x48 => BASEFEE,
-                0x50 => POP,
-                0x51 => MLOAD,
-                0x52 => MSTORE,
-                0x53 => MSTORE8,
-                0x54 => SLOAD,
-                0x55 => SSTORE,
-                0x56 => JUMP,
-                0x57 => JUMPI,
-                0x58 => PC,
-                0x59 => MSIZE,
-                0x5A => GAS,
-                0x5B => JUMPDEST,
-                0x60..=0x7F => {
-                    // 1 <= n <= 32
-                    let n: usize = (byte - 0x5F).into();
+            let (opcode, immediate_size) = match Opcode::from_byte(bytecode[pc]) {
+                // An opcode not yet introduced has no immediate data.
+                (opcode, _) if !opcode.is_enabled(&config) => (Opcode::INVALID, 0),
+                // Legacy code keeps the JUMPDEST analysis of the deployed contracts.
+                (Opcode::DUPN(_) | Opcode::SWAPN(_) | Opcode::EXCHANGE(_), _) if !eof => {
+                    (Opcode::INVALID, 0)
+                }
+                decoded => decoded,
+            };
+            let counter = pc + 1;
+            // A missing immediate byte is zero.
+            let immediate = bytecode.get(counter).copied().unwrap_or_default();
+
+            let opcode = match opcode {
+                Opcode::PUSH(n, _) => {
                     // Check for bad bytecode length.
                     let bytes = &bytecode[counter..std::cmp::min(counter + n, bytecode.len())];
-                    // The end of the number in the bytecode.
-                    counter += n;
-                    PUSH(U256::try_from_be_slice(&bytes).expect("safe"))
-                }
-                0x80..=0x8F => {
-                    // 1 <= n <= 16
-                    let n: usize = (byte - 0x7F).into();
-                    DUP(n)
-                }
-                0x90..=0x9F => {
-                    // 1 <= n <= 16
-                    let n: usize = (byte - 0x8F).into();
-                    SWAP(n)
+                    Opcode::PUSH(n, U256::try_from_be_slice(bytes).expect("safe"))
                 }
-                0xA0..=0xA4 => {
-                    // 0 <= n <= 4
-                    let n: usize = (byte - 0xA0).into();
-                    LOG(n)
-                }
-                0xF0 => CREATE,
-                0xF1 => CALL,
-                0xF3 => RETURN,
-                0xF4 => DELEGATECALL,
-                0xFA => STATICCALL,
-                0xFD => REVERT,
-                0xFF => SELFDESTRUCT,
-                0xFE | _ => INVALID,
+                Opcode::DUPN(_) => Opcode::DUPN(immediate),
+                Opcode::SWAPN(_) => Opcode::SWAPN(immediate),
+                Opcode::EXCHANGE(_) => Opcode::EXCHANGE(immediate),
+                opcode => opcode,
             };
 
             opcodes[pc] = Some(opcode);
-            pc = counter;
+            // The end of the immediate data in the bytecode.
+            pc = counter + immediate_size;
         }
 
         opcodes
@@ -276,25 +479,11 @@ impl Iterator for Code {
         //    self.opcodes
         //);
 
-        let mut pc = self.pc;
-
-        // Get the next opcode by filtering the empty push data slots.
-        let opcode = loop {
-            let o = self
-                .opcodes
-                .get(pc)
-                // STOP if there are no opcode to execute.
-                .unwrap_or(&Some(Opcode::STOP));
-
-            pc += 1;
-
-            if let Some(op) = o {
-                break op.clone();
-            }
-        };
+        let (offset, opcode) = self.peek();
+        self.current_op_offset = offset;
 
         // Increment the pc.
-        self.pc = pc;
+        self.pc = offset + 1;
 
         log::trace!("result: opcode={:02X?}, pc={:?}", opcode, self.pc);
         Some(opcode)
@@ -317,8 +506,107 @@ mod tests {
     #[test]
     fn should_iterate_over_bytecode() {
         let raw = [0x00, 0xFE];
-        let mut code = Code::new(&raw);
+        let mut code = Code::new(&raw, Spec::default());
+        assert_eq!(Some(Opcode::STOP), code.next());
+        assert_eq!(Some(Opcode::INVALID), code.next());
+    }
+
+    #[test]
+    fn should_round_trip_opcode_bytes() {
+        for byte in 0x00..=0xFF {
+            let (opcode, immediate_size) = Opcode::from_byte(byte);
+            match opcode {
+                // Undefined bytes are all decoded as INVALID.
+                Opcode::INVALID => assert_eq!(0xFE, opcode.to_byte()),
+                _ => assert_eq!(byte, opcode.to_byte()),
+            }
+            match byte {
+                0x60..=0x7F => assert_eq!(usize::from(byte - 0x5F), immediate_size),
+                0xE6..=0xE8 => assert_eq!(1, immediate_size),
+                _ => assert_eq!(0, immediate_size),
+            }
+        }
+    }
+
+    #[test]
+    fn should_decode_push_immediate_data() {
+        let raw = [0x61, 0x12, 0x34, 0x00];
+        let mut code = Code::new(&raw, Spec::default());
+        assert_eq!(Some(Opcode::PUSH(2, U256::from(0x1234))), code.next());
+        assert_eq!(Some(Opcode::STOP), code.next());
+    }
+
+    #[test]
+    fn should_decode_opcodes_of_the_spec() {
+        // PUSH1 1 PUSH1 1 SHL
+        let raw = [0x60, 0x01, 0x60, 0x01, 0x1B];
+
+        let mut code = Code::new(&raw, Spec::Frontier);
+        assert_eq!(Some(Opcode::INVALID), code.nth(2));
+
+        let mut code = Code::new(&raw, Spec::Constantinople);
+        assert_eq!(Some(Opcode::SHL), code.nth(2));
+
+        // STATICCALL TLOAD
+        let raw = [0xFA, 0x5C];
+        let mut code = Code::new(&raw, Spec::SpuriousDragon);
+        assert_eq!(Some(Opcode::INVALID), code.next());
+        assert_eq!(Some(Opcode::INVALID), code.next());
+
+        let mut code = Code::new(&raw, Spec::Shanghai);
+        assert_eq!(Some(Opcode::STATICCALL), code.next());
+        assert_eq!(Some(Opcode::INVALID), code.next());
+
+        // PUSH0
+        let raw = [0x5F];
+        let mut code = Code::new(&raw, Spec::Paris);
+        assert_eq!(Some(Opcode::INVALID), code.next());
+
+        let mut code = Code::new(&raw, Spec::Shanghai);
+        assert_eq!(Some(Opcode::PUSH(0, U256::ZERO)), code.next());
+    }
+
+    #[test]
+    fn should_decode_the_eip663_opcodes_in_eof_code() {
+        // DUPN 20 SWAPN 0x5B EXCHANGE
+        let raw = [0xE6, 0x14, 0xE7, 0x5B, 0xE8];
+        let opcodes = |code: Code| code.opcodes.into_iter().flatten().collect::<Vec<_>>();
+
+        let code = Code::new(&EofContainer::encode(&raw), Spec::Prague);
+        assert_eq!(
+            vec![Opcode::DUPN(20), Opcode::SWAPN(0x5B), Opcode::EXCHANGE(0)],
+            opcodes(code)
+        );
+
+        // In legacy code the immediate bytes are opcodes, whatever the spec.
+        use Opcode::*;
+        for spec in [Spec::Cancun, Spec::Prague] {
+            let code = Code::new(&raw, spec);
+            assert_eq!(vec![INVALID, EQ, INVALID, JUMPDEST, INVALID], opcodes(code));
+        }
+        // JUMPDEST is still a valid destination.
+        let mut code = Code::new(&raw, Spec::Prague);
+        assert!(code.jump_to(U256::from(3)).is_ok());
+    }
+
+    #[test]
+    fn should_only_decode_eof_from_prague() {
+        let raw = hex::decode("EF000101000402000100030400000000800001600100").unwrap();
+
+        let mut code = Code::new(&raw, Spec::Prague);
+        assert_eq!(3, code.size());
+        assert_eq!(Some(Opcode::PUSH(1, U256::from(1))), code.next());
         assert_eq!(Some(Opcode::STOP), code.next());
+
+        let mut code = Code::new(&raw, Spec::Cancun);
+        assert_eq!(raw.len(), code.size());
+        assert_eq!(Some(Opcode::INVALID), code.next());
+    }
+
+    #[test]
+    fn should_not_execute_invalid_eof() {
+        let raw = hex::decode("EF0001010004020001000304000000008000016001").unwrap();
+        let mut code = Code::new(&raw, Spec::Prague);
         assert_eq!(Some(Opcode::INVALID), code.next());
     }
 }
@@ -0,0 +1,212 @@
+use thiserror::Error;
+
+/// The EOF container prefix (EIP-3540).
+const MAGIC: [u8; 2] = [0xEF, 0x00];
+const VERSION: u8 = 0x01;
+
+const KIND_TYPES: u8 = 0x01;
+const KIND_CODE: u8 = 0x02;
+const KIND_DATA: u8 = 0x04;
+const TERMINATOR: u8 = 0x00;
+
+/// Size of a code section metadata in the types section.
+const TYPE_SIZE: usize = 0x04;
+
+#[derive(Debug)]
+/// An EVM Object Format container (EIP-3540).
+/// Only the headers are validated, the sections content is not.
+/// The types and data sections are not used by the interpreter.
+pub(super) struct EofContainer<'a> {
+    code_sections: Vec<&'a [u8]>,
+}
+
+impl<'a> EofContainer<'a> {
+    pub(super) fn is_eof(bytecode: &[u8]) -> bool {
+        bytecode.starts_with(&MAGIC)
+    }
+
+    pub(super) fn decode(bytecode: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader(bytecode);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(EofError::InvalidMagic);
+        }
+        if reader.u8()? != VERSION {
+            return Err(EofError::InvalidVersion);
+        }
+
+        // Headers.
+        reader.expect_kind(KIND_TYPES)?;
+        let types_size = reader.u16()?;
+
+        reader.expect_kind(KIND_CODE)?;
+        let num_code_sections = reader.u16()?;
+        if num_code_sections == 0 {
+            return Err(EofError::NoCodeSection);
+        }
+        if types_size != num_code_sections * TYPE_SIZE {
+            return Err(EofError::InvalidTypesSize);
+        }
+        let code_sizes = (0..num_code_sections)
+            .map(|_| match reader.u16()? {
+                0 => Err(EofError::EmptyCodeSection),
+                size => Ok(size),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        reader.expect_kind(KIND_DATA)?;
+        let data_size = reader.u16()?;
+
+        reader.expect_kind(TERMINATOR)?;
+
+        // Body.
+        let body_size = types_size + code_sizes.iter().sum::<usize>() + data_size;
+        if reader.0.len() != body_size {
+            return Err(EofError::InvalidBodySize);
+        }
+        reader.take(types_size)?;
+        let code_sections = code_sizes
+            .into_iter()
+            .map(|size| reader.take(size))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { code_sections })
+    }
+
+    /// The entry point code section.
+    pub(super) fn code(&self) -> &'a [u8] {
+        self.code_sections[0]
+    }
+}
+
+#[cfg(test)]
+impl<'a> EofContainer<'a> {
+    /// A container with `code` as its only code section and no data.
+    pub(super) fn encode(code: &[u8]) -> Vec<u8> {
+        let size = u16::try_from(code.len()).expect("safe").to_be_bytes();
+        [
+            &MAGIC[..],
+            &[VERSION, KIND_TYPES, 0x00, 0x04, KIND_CODE, 0x00, 0x01],
+            &size,
+            &[KIND_DATA, 0x00, 0x00, TERMINATOR],
+            // No inputs, non returning, a maximum stack height of 1024.
+            &[0x00, 0x80, 0x04, 0x00],
+            code,
+        ]
+        .concat()
+    }
+}
+
+/// Consumes the container bytes from the front.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8]> {
+        if self.0.len() < size {
+            return Err(EofError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(size);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Result<usize> {
+        self.take(2)
+            .map(|b| usize::from(u16::from_be_bytes([b[0], b[1]])))
+    }
+
+    fn expect_kind(&mut self, kind: u8) -> Result<()> {
+        if self.u8()? != kind {
+            return Err(EofError::InvalidSectionKind(kind));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum EofError {
+    InvalidMagic,
+    InvalidVersion,
+    InvalidSectionKind(u8),
+    NoCodeSection,
+    EmptyCodeSection,
+    InvalidTypesSize,
+    InvalidBodySize,
+    Truncated,
+}
+
+pub(super) type Result<T> = std::result::Result<T, EofError>;
+
+impl std::fmt::Display for EofError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EofError::InvalidMagic => write!(f, "invalid EOF magic"),
+            EofError::InvalidVersion => write!(f, "invalid EOF version"),
+            EofError::InvalidSectionKind(kind) => {
+                write!(f, "expected EOF section kind {:#04X}", kind)
+            }
+            EofError::NoCodeSection => write!(f, "no EOF code section"),
+            EofError::EmptyCodeSection => write!(f, "empty EOF code section"),
+            EofError::InvalidTypesSize => write!(f, "invalid EOF types section size"),
+            EofError::InvalidBodySize => write!(f, "invalid EOF body size"),
+            EofError::Truncated => write!(f, "truncated EOF container"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // types: 1 section, code: 1 section of 3 bytes, data: 2 bytes.
+    const HEADER: &str = "EF0001010004020001000304000200";
+    const BODY: &str = "00800001600100AABB";
+
+    #[test]
+    fn should_decode_container() {
+        let raw = hex::decode(format!("{HEADER}{BODY}")).unwrap();
+        assert!(EofContainer::is_eof(&raw));
+
+        let container = EofContainer::decode(&raw).unwrap();
+        assert_eq!(&[0x60, 0x01, 0x00], container.code());
+
+        let raw = EofContainer::encode(&[0x60, 0x01, 0x00]);
+        assert_eq!(
+            &[0x60, 0x01, 0x00],
+            EofContainer::decode(&raw).unwrap().code()
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_headers() {
+        let decode = |s: &str| EofContainer::decode(&hex::decode(s).unwrap()).map(|_| ());
+
+        assert_eq!(Err(EofError::InvalidMagic), decode("EF01"));
+        assert_eq!(Err(EofError::InvalidVersion), decode("EF0002"));
+        assert_eq!(
+            Err(EofError::InvalidSectionKind(KIND_TYPES)),
+            decode("EF000102")
+        );
+        assert_eq!(
+            Err(EofError::NoCodeSection),
+            decode("EF00010100040200000400000000")
+        );
+        assert_eq!(
+            Err(EofError::InvalidTypesSize),
+            decode("EF0001010008020001000304000200")
+        );
+        assert_eq!(
+            Err(EofError::InvalidSectionKind(TERMINATOR)),
+            decode("EF0001010004020001000304000201")
+        );
+        assert_eq!(
+            Err(EofError::InvalidBodySize),
+            decode(&format!("{HEADER}{BODY}00"))
+        );
+        assert_eq!(Err(EofError::Truncated), decode("EF00010100"));
+    }
+}
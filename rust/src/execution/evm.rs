@@ -1,12 +1,56 @@
 use ruint::aliases::U256;
-use std::fmt::Display;
+use std::{fmt::Display, ops::Deref};
 use thiserror::Error;
 
 use super::code::*;
+use super::gas::*;
 use super::memory::*;
 use super::stack::*;
 use crate::types::*;
 
+/// The maximum depth of a call frame, deeper calls and creations fail.
+pub(super) const CALL_DEPTH_LIMIT: usize = 1024;
+
+#[derive(Debug)]
+/// The environment of a frame, a staticcall frame and its children only borrow it and
+/// cannot get a mutable state.
+pub(super) enum FrameEnv<'c, 'a> {
+    Mutable(&'c mut Environment<'a>),
+    Static(&'c Environment<'a>),
+}
+
+impl<'c, 'a> Deref for FrameEnv<'c, 'a> {
+    type Target = Environment<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            FrameEnv::Mutable(env) => env,
+            FrameEnv::Static(env) => env,
+        }
+    }
+}
+
+impl<'c, 'a> FrameEnv<'c, 'a> {
+    fn state_mut(&mut self) -> Option<&mut State> {
+        match self {
+            FrameEnv::Mutable(env) => Some(env.state_mut()),
+            FrameEnv::Static(_) => None,
+        }
+    }
+
+    /// Process a `message` sent by the frame, one frame deeper.
+    pub(super) fn process(&mut self, message: Message<'_, '_>) -> EVMResult {
+        let depth = self.depth();
+        self.set_depth(depth + 1);
+        let result = match self {
+            FrameEnv::Mutable(env) => Message::process(message, env),
+            FrameEnv::Static(env) => Message::process_static(message, env),
+        };
+        self.set_depth(depth);
+        result
+    }
+}
+
 #[derive(Debug)]
 /// The internal state of the virtual machine.
 pub(crate) struct EVM<'a, 'b, 'c, 'd>
@@ -14,14 +58,19 @@ where
     'a: 'c,
     'b: 'd,
 {
-    pub(super) env: &'c mut Environment<'a>,
+    pub(super) env: FrameEnv<'c, 'a>,
     pub(super) message: &'d Message<'b, 'd>,
     pub(super) stack: Stack,
     pub(super) memory: Memory,
     pub(super) code: Code,
+    pub(super) gas: Gas,
     pub(super) logs: Vec<Log>,
     pub(super) result: Option<Result<(U256, U256)>>,
+    /// Why a successful execution halted, a STOP when not set.
+    pub(super) halt_reason: Option<HaltReason>,
     pub(super) last_inner_call: Option<EVMResult>,
+    /// The executed opcodes with their pc, `None` when not recorded.
+    pub(super) trace: Option<Vec<(usize, Opcode)>>,
 }
 
 impl<'a, 'b, 'c, 'd> EVM<'a, 'b, 'c, 'd>
@@ -30,57 +79,121 @@ where
     'b: 'd,
 {
     pub fn new(env: &'c mut Environment<'a>, message: &'d Message<'b, 'd>) -> EVM<'a, 'b, 'c, 'd> {
+        match message.is_staticcall() {
+            true => Self::with_env(FrameEnv::Static(env), message),
+            false => Self::with_env(FrameEnv::Mutable(env), message),
+        }
+    }
+
+    pub(super) fn with_env(
+        env: FrameEnv<'c, 'a>,
+        message: &'d Message<'b, 'd>,
+    ) -> EVM<'a, 'b, 'c, 'd> {
         match message {
             Message::Call { target, .. } | Message::Staticcall { target, .. } => {
-                let code = Code::new(env.state().get_account(target).code().clone());
+                let code = Code::new(env.state().get_account(target).code().clone(), env.spec());
+                let stack_limit = env.stack_limit();
+                let trace = env.record_trace().then(Vec::new);
 
                 Self {
                     env,
                     message,
-                    stack: Stack::new(),
+                    stack: Stack::new(stack_limit),
                     memory: Memory::new(),
                     code,
+                    gas: Gas::new(message.gas()),
                     logs: vec![],
                     result: None,
+                    halt_reason: None,
                     last_inner_call: None,
+                    trace,
                 }
             }
             Message::Delegatecall { delegate, .. } => {
-                let code = Code::new(env.state().get_account(delegate).code().clone());
+                let code = Code::new(env.state().get_account(delegate).code().clone(), env.spec());
+                let stack_limit = env.stack_limit();
+                let trace = env.record_trace().then(Vec::new);
 
                 Self {
                     env,
                     message,
-                    stack: Stack::new(),
+                    stack: Stack::new(stack_limit),
                     memory: Memory::new(),
                     code,
+                    gas: Gas::new(message.gas()),
                     logs: vec![],
                     result: None,
+                    halt_reason: None,
                     last_inner_call: None,
+                    trace,
                 }
             }
             Message::Create { target, .. } => {
-                let code = Code::new(env.state().get_account(target).code().clone());
+                let code = Code::new(env.state().get_account(target).code().clone(), env.spec());
+                let stack_limit = env.stack_limit();
+                let trace = env.record_trace().then(Vec::new);
 
                 Self {
                     env,
                     message,
-                    stack: Stack::new(),
+                    stack: Stack::new(stack_limit),
                     memory: Memory::new(),
                     code,
+                    gas: Gas::new(message.gas()),
                     logs: vec![],
                     result: None,
+                    halt_reason: None,
                     last_inner_call: None,
+                    trace,
                 }
             }
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+/// The virtual machine before executing an opcode.
+pub struct Step {
+    /// The offset of the opcode in the code.
+    pub pc: usize,
+    pub opcode: Opcode,
+    pub stack_depth: usize,
+}
+
+/// The stepping of a frame, without the lifetimes of its virtual machine.
+trait Stepping {
+    fn run_until(&mut self, predicate: &mut dyn FnMut(&Step) -> bool) -> bool;
+}
+
+impl<'a, 'b, 'c, 'd> Stepping for EVM<'a, 'b, 'c, 'd>
+where
+    'a: 'c,
+    'b: 'd,
+{
+    fn run_until(&mut self, predicate: &mut dyn FnMut(&Step) -> bool) -> bool {
+        EVM::run_until(self, predicate)
+    }
+}
+
+/// The transaction frame given to `Transaction::process_stepping`, paused before its next opcode.
+pub struct Stepper<'e>(&'e mut dyn Stepping);
+
+impl<'e> Stepper<'e> {
+    /// Execute the opcodes until `predicate` is true for the next one, which is not executed,
+    /// or until a BREAKPOINT is executed in debug mode.
+    /// Returns false if the execution halted first, the execution can be resumed otherwise.
+    pub fn run_until(&mut self, mut predicate: impl FnMut(&Step) -> bool) -> bool {
+        self.0.run_until(&mut predicate)
+    }
+}
+
 #[derive(Error, Debug, Clone)]
 pub enum EVMError {
     Revert(U256, U256),
     StateModificationDisallowed,
+    StepLimitExceeded,
+    /// The designated INVALID opcode, or an opcode not enabled.
+    InvalidOpcode,
     #[error(transparent)]
     StackError(#[from] StackError),
     #[error(transparent)]
@@ -89,6 +202,8 @@ pub enum EVMError {
     MemoryError(#[from] MemoryError),
     #[error(transparent)]
     StateError(#[from] StateError),
+    #[error(transparent)]
+    GasError(#[from] GasError),
 }
 
 impl<'a> Display for EVMError {
@@ -98,10 +213,13 @@ impl<'a> Display for EVMError {
             EVMError::StateModificationDisallowed => {
                 write!(f, "Cannot modify state in a staticcall")
             }
+            EVMError::StepLimitExceeded => write!(f, "Step limit exceeded"),
+            EVMError::InvalidOpcode => write!(f, "Invalid opcode"),
             EVMError::StackError(e) => e.fmt(f),
             EVMError::CodeError(e) => e.fmt(f),
             EVMError::MemoryError(e) => e.fmt(f),
             EVMError::StateError(e) => e.fmt(f),
+            EVMError::GasError(e) => e.fmt(f),
         }
     }
 }
@@ -109,11 +227,19 @@ impl<'a> Display for EVMError {
 type Result<T> = std::result::Result<T, EVMError>;
 
 impl<'a, 'b, 'c, 'd> EVM<'a, 'b, 'c, 'd> {
-    pub fn execute(mut self) -> EVMResult {
+    pub fn execute(self) -> EVMResult {
+        self.execute_stepping(None)
+    }
+
+    /// Like `execute`, the bytecode is first executed by `step` then to its end.
+    pub(super) fn execute_stepping(
+        mut self,
+        step: Option<&mut dyn FnMut(Stepper<'_>)>,
+    ) -> EVMResult {
         log::trace!("execute(): execute the bytecode");
 
-        // State snapshot.
-        let env = self.env.state().clone();
+        // State checkpoint, a staticcall frame does not modify the state.
+        let checkpoint = self.env.state_mut().map(State::checkpoint);
 
         // Send Eth.
         if *self.message.value() != U256::ZERO {
@@ -127,55 +253,180 @@ impl<'a, 'b, 'c, 'd> EVM<'a, 'b, 'c, 'd> {
                 Message::Delegatecall { .. } => {}
                 // Send ETH to target's account.
                 Message::Call { .. } | Message::Create { .. } => {
-                    self.env
-                        .state_mut()
-                        .send_eth(
-                            self.message.caller(),
-                            self.message.target(),
-                            self.message.value(),
-                        )
-                        .expect("not handled");
+                    let message = self.message;
+                    if let Err(e) = self.state_mut().and_then(|state| {
+                        Ok(state.send_eth(message.caller(), message.target(), message.value())?)
+                    }) {
+                        // The call fails before executing any code, its gas is returned.
+                        self.result = Some(Err(e));
+                        self.close(checkpoint);
+                        let mut result: EVMResult = self.into();
+                        result.gas_used = 0;
+                        return result;
+                    }
                 }
             }
         }
 
-        // Iterate over bytecode.
-        let mut iter = self.into_iter();
-        while let Some(_) = iter.next() {}
-
-        // Restore previous state snapshot if the call reverted.
-        if let Some(Err(_)) = &self.result {
-            self.env.set_state(env);
+        let precompile = match self.env.precompile(self.message.code_address()).cloned() {
+            // The custom precompiles are not metered.
+            Some(precompile) => Some((precompile, 0)),
+            None => {
+                super::precompile::builtin(self.message.code_address(), self.message.data().size())
+            }
+        };
+        if let Some((precompile, cost)) = precompile {
+            // Execute the precompiled contract instead of the account code.
+            self.result = Some(match self.gas.charge(cost) {
+                // Nothing is returned without enough gas.
+                Err(e) => Err(e.into()),
+                Ok(_) => {
+                    let (success, output) = precompile(self.message.data().into());
+                    let size = U256::from(output.len());
+                    match self.memory.store(0, output.len(), &output) {
+                        Err(e) => Err(e.into()),
+                        Ok(_) if success => {
+                            self.halt_reason = Some(HaltReason::Return);
+                            Ok((U256::ZERO, size))
+                        }
+                        Ok(_) => Err(EVMError::Revert(U256::ZERO, size)),
+                    }
+                }
+            });
+        } else {
+            if let Some(step) = step {
+                step(Stepper(&mut self));
+            }
+            // Iterate over bytecode.
+            let mut iter = self.into_iter();
+            while let Some(_) = iter.next() {}
         }
 
+        self.close(checkpoint);
+
         log::trace!("execution completed");
         self.into()
     }
 }
 
+impl<'a, 'b, 'c, 'd> EVM<'a, 'b, 'c, 'd>
+where
+    'a: 'c,
+    'b: 'd,
+{
+    /// The state read by the opcodes.
+    pub(super) fn state(&self) -> ReadOnlyState<'_> {
+        self.env.state().into()
+    }
+
+    /// The state modified by the opcodes, a staticcall frame only borrows the environment.
+    pub(super) fn state_mut(&mut self) -> Result<&mut State> {
+        self.env
+            .state_mut()
+            .ok_or(EVMError::StateModificationDisallowed)
+    }
+
+    /// Restore the state at `checkpoint` if the call failed, or commit it.
+    fn close(&mut self, checkpoint: Option<CheckpointId>) {
+        let failed = matches!(self.result, Some(Err(_)));
+        if let (Some(checkpoint), Some(state)) = (checkpoint, self.env.state_mut()) {
+            match failed {
+                true => state.revert_to(checkpoint),
+                false => state.commit(checkpoint),
+            }
+            .expect("safe");
+        }
+    }
+
+    /// Execute the opcodes until `predicate` is true for the next one, which is not executed,
+    /// or until a BREAKPOINT is executed in debug mode.
+    /// Returns false if the execution halted first, the execution can be resumed otherwise.
+    pub fn run_until(&mut self, mut predicate: impl FnMut(&Step) -> bool) -> bool {
+        while self.result.is_none() {
+            let (pc, opcode) = self.code.peek();
+            let step = Step {
+                pc,
+                opcode,
+                stack_depth: self.stack.len(),
+            };
+            if predicate(&step) {
+                return true;
+            }
+            let breakpoint = self.env.debug() && step.opcode == Opcode::BREAKPOINT;
+            if (&mut *self).next().is_none() {
+                break;
+            }
+            if breakpoint {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Why the execution halted.
+pub enum HaltReason {
+    /// A STOP, a SELFDESTRUCT or the end of the code.
+    Stop,
+    Return,
+    Revert,
+    /// An exceptional halt, like running out of gas.
+    Error,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct EVMResult {
     pub(super) stack: StackResult,
     pub(super) return_data: Box<[u8]>,
+    /// The memory of the frame when it halted.
+    pub(super) memory: Box<[u8]>,
     pub(super) logs: Box<[LogResult]>,
     pub(super) status: bool,
+    pub(super) gas_used: u64,
+    /// The refunds of a successful execution, discarded with its state otherwise.
+    pub(super) gas_refund: i64,
+    pub(super) trace: Box<[(usize, Opcode)]>,
+    pub(super) halt_reason: HaltReason,
+    /// Set by a successful creation.
+    pub(super) created_address: Option<Address>,
 }
 
 impl<'a, 'b, 'c, 'd> From<EVM<'a, 'b, 'c, 'd>> for EVMResult {
     fn from(evm: EVM<'a, 'b, 'c, 'd>) -> Self {
+        // Only a RETURN or a REVERT returns data, an exceptional halt does not.
         let (offset, size) = match evm.result {
             Some(Ok((o, s))) => (o, s),
             Some(Err(EVMError::Revert(o, s))) => (o, s),
             _ => (U256::ZERO, U256::ZERO),
         };
+        let halt_reason = match evm.result {
+            Some(Ok(_)) => evm.halt_reason.unwrap_or(HaltReason::Stop),
+            Some(Err(EVMError::Revert(_, _))) => HaltReason::Revert,
+            Some(Err(_)) | None => HaltReason::Error,
+        };
+        // RETURN and REVERT checked the bounds of the returned region.
         let return_data = evm
             .memory
-            .load(offset.saturating_to(), size.saturating_to());
+            .load(offset.saturating_to(), size.saturating_to())
+            .expect("safe");
+        let status = evm.result.map_or(false, |r| r.is_ok());
+        // An exceptional halt consumes all the gas of the frame, unlike a REVERT.
+        let mut gas = evm.gas;
+        if halt_reason == HaltReason::Error {
+            gas.exhaust();
+        }
         Self {
             stack: evm.stack.into(),
             return_data,
+            memory: evm.memory.into_bytes(),
             logs: evm.logs.into_iter().map(From::from).collect(),
-            status: evm.result.map_or(false, |r| r.is_ok()),
+            status,
+            gas_used: gas.used(),
+            gas_refund: if status { gas.refund() } else { 0 },
+            trace: evm.trace.unwrap_or_default().into(),
+            halt_reason,
+            created_address: None,
         }
     }
 }
@@ -189,6 +440,10 @@ impl EVMResult {
         &self.return_data
     }
 
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
     pub fn logs(&self) -> &Box<[LogResult]> {
         &self.logs
     }
@@ -196,4 +451,47 @@ impl EVMResult {
     pub fn status(&self) -> bool {
         self.status
     }
+
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    pub fn gas_refund(&self) -> i64 {
+        self.gas_refund
+    }
+
+    pub fn trace(&self) -> &[(usize, Opcode)] {
+        &self.trace
+    }
+
+    pub fn halt_reason(&self) -> HaltReason {
+        self.halt_reason
+    }
+
+    pub fn created_address(&self) -> Option<&Address> {
+        self.created_address.as_ref()
+    }
+
+    /// Charge the deposit of the code returned by a creation with a `limit` gas.
+    /// The creation fails and consumes all its gas when it cannot pay.
+    pub(super) fn charge_code_deposit(&mut self, limit: &U256) {
+        if !self.status || *limit == U256::MAX {
+            return;
+        }
+        let size = self.return_data.len() as u64;
+        let gas_used = self
+            .gas_used
+            .saturating_add(G_CODE_DEPOSIT.saturating_mul(size));
+        if U256::from(gas_used) <= *limit {
+            self.gas_used = gas_used;
+        } else {
+            self.status = false;
+            self.return_data = Box::new([]);
+            self.logs = Box::new([]);
+            self.gas_used = limit.saturating_to();
+            // The refunds are discarded with the state of the failed creation.
+            self.gas_refund = 0;
+            self.halt_reason = HaltReason::Error;
+        }
+    }
 }
@@ -0,0 +1,511 @@
+use super::code::Opcode;
+use crate::types::{Spec, SpecConfig};
+use ruint::aliases::U256;
+use thiserror::Error;
+
+pub(super) const G_BASE: u64 = 2;
+pub(super) const G_VERYLOW: u64 = 3;
+pub(super) const G_MEMORY: u64 = 3;
+pub(super) const G_COPY: u64 = 3;
+pub(super) const G_QUAD_COEFF_DIV: u64 = 512;
+pub(super) const G_LOG: u64 = 375;
+pub(super) const G_LOG_TOPIC: u64 = 375;
+pub(super) const G_LOG_DATA: u64 = 8;
+pub(super) const G_EXP: u64 = 10;
+pub(super) const G_NEW_ACCOUNT: u64 = 25000;
+pub(super) const G_CODE_DEPOSIT: u64 = 200;
+pub(super) const G_SELFDESTRUCT: u64 = 5000;
+pub(super) const G_COLD_ACCOUNT_ACCESS: u64 = 2600;
+pub(super) const G_SLOAD: u64 = 800;
+pub(super) const G_WARM_STORAGE_READ: u64 = 100;
+pub(super) const G_COLD_SLOAD: u64 = 2100;
+pub(super) const G_SSTORE_SET: u64 = 20000;
+pub(super) const G_SSTORE_RESET: u64 = 5000;
+pub(super) const G_CALL_STIPEND: u64 = 2300;
+pub(super) const G_IDENTITY: u64 = 15;
+pub(super) const G_IDENTITY_WORD: u64 = 3;
+pub(super) const R_SELFDESTRUCT: u64 = 24000;
+pub(super) const R_SCLEAR: u64 = 15000;
+
+#[derive(Debug, Clone)]
+/// Gas accounting of an execution frame.
+pub(super) struct Gas {
+    /// `None` when the execution is not metered.
+    limit: Option<u64>,
+    used: u64,
+    /// The refunds of the frame and its successful children.
+    /// Negative when the frame takes back refunds granted by its parent (EIP-2200).
+    refund: i64,
+}
+
+impl Gas {
+    /// A `U256::MAX` gas limit disables metering.
+    pub(super) fn new(limit: &U256) -> Self {
+        let limit = if *limit == U256::MAX {
+            None
+        } else {
+            Some(limit.saturating_to())
+        };
+        Self {
+            limit,
+            used: 0,
+            refund: 0,
+        }
+    }
+
+    pub(super) fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// Consume all the gas of the frame, like an exceptional halt.
+    pub(super) fn exhaust(&mut self) {
+        if let Some(limit) = self.limit {
+            self.used = limit;
+        }
+    }
+
+    pub(super) fn refund(&self) -> i64 {
+        self.refund
+    }
+
+    pub(super) fn add_refund(&mut self, amount: i64) {
+        self.refund = self.refund.saturating_add(amount);
+    }
+
+    /// The remaining gas, `U256::MAX` when not metered.
+    pub(super) fn remaining(&self) -> U256 {
+        self.limit
+            .map_or(U256::MAX, |limit| U256::from(limit - self.used))
+    }
+
+    /// The gas limit of a child frame requesting `requested` gas, taken from the frame.
+    /// Since EIP-150 the request is capped to all but one 64th of the remaining gas, before it
+    /// the frame runs out of gas when it cannot pay for the request.
+    pub(super) fn forward(&mut self, requested: U256, spec: Spec) -> Result<U256> {
+        match self.limit {
+            // A child of a not metered frame is not metered either.
+            None => Ok(U256::MAX),
+            Some(limit) => {
+                let available = limit - self.used;
+                let forwarded = match SpecConfig::for_fork(spec).all_but_one_64th {
+                    true => requested.min(U256::from(available - available / 64)),
+                    false => requested,
+                };
+                self.charge(forwarded.saturating_to())?;
+                Ok(forwarded)
+            }
+        }
+    }
+
+    /// Take back the gas `forwarded` to a child frame which used `used` gas.
+    pub(super) fn reclaim(&mut self, forwarded: &U256, used: u64) {
+        match self.limit {
+            None => self.used = self.used.saturating_add(used),
+            Some(_) => {
+                let returned = forwarded.saturating_to::<u64>().saturating_sub(used);
+                self.used -= returned;
+            }
+        }
+    }
+
+    pub(super) fn charge(&mut self, amount: u64) -> Result<()> {
+        let used = self.used.saturating_add(amount);
+        match self.limit {
+            Some(limit) if used > limit => {
+                // All the gas is consumed.
+                self.used = limit;
+                Err(GasError::OutOfGas)
+            }
+            _ => {
+                self.used = used;
+                Ok(())
+            }
+        }
+    }
+
+    /// Charge the memory expansion needed to access `size` bytes at `offset`.
+    pub(super) fn charge_memory_expansion(
+        &mut self,
+        memory_size: usize,
+        offset: usize,
+        size: usize,
+    ) -> Result<()> {
+        if size == 0 {
+            return Ok(());
+        }
+        let new_size = offset.saturating_add(size);
+        if new_size <= memory_size {
+            return Ok(());
+        }
+        self.charge(memory_cost(new_size).saturating_sub(memory_cost(memory_size)))
+    }
+}
+
+/// The total cost of a memory of `size` bytes.
+pub(super) fn memory_cost(size: usize) -> u64 {
+    let words = u64::try_from(size.div_ceil(0x20)).unwrap_or(u64::MAX);
+    words
+        .saturating_mul(G_MEMORY)
+        .saturating_add(words.saturating_mul(words) / G_QUAD_COEFF_DIV)
+}
+
+/// The cost of an opcode known before its execution.
+/// The opcodes not listed are not metered yet.
+pub(super) fn static_cost(opcode: &Opcode) -> u64 {
+    use Opcode::*;
+    match opcode {
+        ADDRESS
+        | ORIGIN
+        | CALLER
+        | CALLVALUE
+        | CALLDATASIZE
+        | CODESIZE
+        | GASPRICE
+        | RETURNDATASIZE
+        | COINBASE
+        | TIMESTAMP
+        | NUMBER
+        | DIFFICULTY
+        | GASLIMIT
+        | CHAINID
+        | BASEFEE
+        | BLOBBASEFEE
+        | POP
+        | PC
+        | MSIZE
+        | GAS
+        | PUSH(0, _) => G_BASE,
+        PUSH(_, _) | BLOBHASH => G_VERYLOW,
+        _ => 0,
+    }
+}
+
+/// The cost of the opcodes reading another account.
+/// Accounts are not tracked in an access list yet, only the `warm` coinbase is not cold.
+pub(super) fn account_access_cost(opcode: &Opcode, warm: bool, spec: Spec) -> u64 {
+    use Opcode::*;
+    let config = SpecConfig::for_fork(spec);
+    match opcode {
+        _ if config.access_lists && warm => G_WARM_STORAGE_READ,
+        _ if config.access_lists => G_COLD_ACCOUNT_ACCESS,
+        BALANCE | EXTCODEHASH if config.trie_repricing => 700,
+        EXTCODEHASH => 400,
+        BALANCE if config.io_repricing => 400,
+        _ if config.io_repricing => 700,
+        _ => 20,
+    }
+}
+
+/// The cost of a LOG with `topics` topics and `size` bytes of data,
+/// memory expansion excluded.
+pub(super) fn log_cost(topics: usize, size: usize) -> u64 {
+    G_LOG
+        .saturating_add(G_LOG_TOPIC * topics as u64)
+        .saturating_add(G_LOG_DATA.saturating_mul(size as u64))
+}
+
+/// The cost of an EXP, which grows with the bytes of the exponent.
+pub(super) fn exp_cost(exponent: &U256, spec: Spec) -> u64 {
+    let byte_cost = match SpecConfig::for_fork(spec).exp_repricing {
+        true => 50,
+        false => 10,
+    };
+    G_EXP + byte_cost * exponent.byte_len() as u64
+}
+
+/// The cost of an MCOPY of `size` bytes, memory expansion excluded.
+pub(super) fn copy_cost(size: usize) -> u64 {
+    let words = u64::try_from(size.div_ceil(0x20)).unwrap_or(u64::MAX);
+    G_VERYLOW.saturating_add(G_COPY.saturating_mul(words))
+}
+
+/// The cost of the IDENTITY precompile copying `size` bytes.
+pub(super) fn identity_cost(size: usize) -> u64 {
+    let words = u64::try_from(size.div_ceil(0x20)).unwrap_or(u64::MAX);
+    G_IDENTITY.saturating_add(G_IDENTITY_WORD.saturating_mul(words))
+}
+
+/// The cost of a SELFDESTRUCT sending `amount` to a beneficiary, `empty` if it has no account.
+pub(super) fn selfdestruct_cost(empty: bool, warm: bool, amount: &U256, spec: Spec) -> u64 {
+    let config = SpecConfig::for_fork(spec);
+    if !config.io_repricing {
+        return 0;
+    }
+    // The beneficiary is cold unless `warm`, like the other accessed accounts.
+    let access = match config.access_lists && !warm {
+        true => G_COLD_ACCOUNT_ACCESS,
+        false => 0,
+    };
+    // Since EIP-161, only sending a balance creates the beneficiary account.
+    let new_account = empty && (*amount != U256::ZERO || !config.state_clearing);
+    G_SELFDESTRUCT + access + if new_account { G_NEW_ACCOUNT } else { 0 }
+}
+
+/// The cost and the refund of an SSTORE writing `new` over `current` in a slot which held
+/// `original` at the start of the transaction.
+/// The refund is negative when the SSTORE takes back a refund granted earlier (EIP-2200).
+pub(super) fn sstore_cost(original: &U256, current: &U256, new: &U256, spec: Spec) -> (u64, i64) {
+    let config = SpecConfig::for_fork(spec);
+    let clear_refund = sstore_clear_refund(spec) as i64;
+    if !config.net_gas_metering {
+        let cost = match *current == U256::ZERO && *new != U256::ZERO {
+            true => G_SSTORE_SET,
+            false => G_SSTORE_RESET,
+        };
+        let refund = match *current != U256::ZERO && *new == U256::ZERO {
+            true => clear_refund,
+            false => 0,
+        };
+        return (cost, refund);
+    }
+
+    // Slots are not tracked in an access list yet, they are always cold (EIP-2929).
+    let (cold, sload, reset) = match config.access_lists {
+        true => (
+            G_COLD_SLOAD,
+            G_WARM_STORAGE_READ,
+            G_SSTORE_RESET - G_COLD_SLOAD,
+        ),
+        false => (0, G_SLOAD, G_SSTORE_RESET),
+    };
+    // A no-op.
+    if current == new {
+        return (cold + sload, 0);
+    }
+    // The first write of the slot in the transaction.
+    if original == current {
+        let cost = match *original == U256::ZERO {
+            true => G_SSTORE_SET,
+            false => reset,
+        };
+        let refund = match *new == U256::ZERO {
+            true => clear_refund,
+            false => 0,
+        };
+        return (cold + cost, refund);
+    }
+
+    // A dirty slot, the refunds of the previous writes are adjusted.
+    let mut refund = 0;
+    if *original != U256::ZERO {
+        if *current == U256::ZERO {
+            refund -= clear_refund;
+        } else if *new == U256::ZERO {
+            refund += clear_refund;
+        }
+    }
+    if original == new {
+        refund += match *original == U256::ZERO {
+            true => G_SSTORE_SET - sload,
+            false => reset - sload,
+        } as i64;
+    }
+    (cold + sload, refund)
+}
+
+/// The refund of an SSTORE clearing a slot, reduced by EIP-3529.
+pub(super) fn sstore_clear_refund(spec: Spec) -> u64 {
+    match SpecConfig::for_fork(spec).eip3529_refunds {
+        true => 4800,
+        false => R_SCLEAR,
+    }
+}
+
+/// The gas refunded to a transaction which used `gas_used`, destroyed `destroyed` accounts
+/// and accumulated `counter` refunds.
+/// The refund is capped to half of the gas used, a fifth and no SELFDESTRUCT refund since
+/// EIP-3529.
+pub(crate) fn refund(destroyed: usize, counter: i64, gas_used: u64, spec: Spec) -> u64 {
+    let (selfdestruct_refund, quotient) = match SpecConfig::for_fork(spec).eip3529_refunds {
+        true => (0, 5),
+        false => (R_SELFDESTRUCT.saturating_mul(destroyed as u64), 2),
+    };
+    selfdestruct_refund
+        .saturating_add(u64::try_from(counter).unwrap_or_default())
+        .min(gas_used / quotient)
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum GasError {
+    OutOfGas,
+}
+
+pub(super) type Result<T> = std::result::Result<T, GasError>;
+
+impl std::fmt::Display for GasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GasError::OutOfGas => write!(f, "out of gas"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_not_meter_max_gas() {
+        let mut gas = Gas::new(&U256::MAX);
+        assert!(gas.charge(u64::MAX).is_ok());
+        assert_eq!(U256::MAX, gas.remaining());
+    }
+
+    #[test]
+    fn should_run_out_of_gas() {
+        let mut gas = Gas::new(&U256::from(10));
+        assert!(gas.charge(10).is_ok());
+        assert_eq!(U256::ZERO, gas.remaining());
+        assert!(matches!(gas.charge(1), Err(GasError::OutOfGas)));
+        assert_eq!(10, gas.used());
+    }
+
+    #[test]
+    fn should_cap_forwarded_gas() {
+        let mut gas = Gas::new(&U256::from(100_000));
+        gas.charge(36_000).unwrap();
+        let forward = |gas: &mut Gas, requested| gas.forward(requested, Spec::Berlin).unwrap();
+        // The forwarded gas is taken from the frame, one 64th of the remaining gas is kept.
+        assert_eq!(U256::from(63_000), forward(&mut gas, U256::MAX));
+        assert_eq!(99_000, gas.used());
+        assert_eq!(U256::from(10), forward(&mut gas, U256::from(10)));
+        assert_eq!(
+            U256::MAX,
+            forward(&mut Gas::new(&U256::MAX), U256::from(10))
+        );
+
+        // Before EIP-150 the whole request is forwarded if it can be paid.
+        let mut gas = Gas::new(&U256::from(100));
+        assert_eq!(
+            U256::from(100),
+            gas.forward(U256::from(100), Spec::Frontier).unwrap()
+        );
+        assert!(matches!(
+            gas.forward(U256::from(1), Spec::Frontier),
+            Err(GasError::OutOfGas)
+        ));
+    }
+
+    #[test]
+    fn should_charge_account_access_per_fork() {
+        let cost = |opcode, spec| account_access_cost(&opcode, false, spec);
+        assert_eq!(20, cost(Opcode::EXTCODESIZE, Spec::Frontier));
+        assert_eq!(700, cost(Opcode::EXTCODESIZE, Spec::TangerineWhistle));
+        assert_eq!(400, cost(Opcode::BALANCE, Spec::TangerineWhistle));
+        assert_eq!(400, cost(Opcode::EXTCODEHASH, Spec::Constantinople));
+        assert_eq!(700, cost(Opcode::BALANCE, Spec::Istanbul));
+        assert_eq!(2600, cost(Opcode::EXTCODEHASH, Spec::Berlin));
+        assert_eq!(
+            G_WARM_STORAGE_READ,
+            account_access_cost(&Opcode::BALANCE, true, Spec::Berlin)
+        );
+        assert_eq!(
+            700,
+            account_access_cost(&Opcode::BALANCE, true, Spec::Istanbul)
+        );
+    }
+
+    #[test]
+    fn should_compute_memory_cost() {
+        assert_eq!(0, memory_cost(0));
+        assert_eq!(3, memory_cost(1));
+        assert_eq!(3, memory_cost(0x20));
+        // 3 * 1024 + 1024² / 512
+        assert_eq!(5120, memory_cost(0x8000));
+    }
+
+    #[test]
+    fn should_charge_selfdestruct_a_new_beneficiary() {
+        let one = U256::from(1);
+        assert_eq!(5000, selfdestruct_cost(false, false, &one, Spec::Istanbul));
+        assert_eq!(30000, selfdestruct_cost(true, false, &one, Spec::Istanbul));
+        assert_eq!(
+            5000,
+            selfdestruct_cost(true, false, &U256::ZERO, Spec::Istanbul)
+        );
+        assert_eq!(
+            30000,
+            selfdestruct_cost(true, false, &U256::ZERO, Spec::TangerineWhistle)
+        );
+        assert_eq!(32600, selfdestruct_cost(true, false, &one, Spec::Berlin));
+        assert_eq!(30000, selfdestruct_cost(true, true, &one, Spec::Berlin));
+        assert_eq!(0, selfdestruct_cost(true, false, &one, Spec::Homestead));
+    }
+
+    #[test]
+    fn should_cap_the_selfdestruct_refund() {
+        assert_eq!(24000, refund(1, 0, 100_000, Spec::Berlin));
+        assert_eq!(30000, refund(2, 0, 60_000, Spec::Berlin));
+        assert_eq!(0, refund(0, 0, 100_000, Spec::Berlin));
+        assert_eq!(0, refund(1, 0, 100_000, Spec::London));
+    }
+
+    #[test]
+    fn should_cap_the_refund_counter() {
+        assert_eq!(15000, refund(0, 15000, 100_000, Spec::Berlin));
+        assert_eq!(39000, refund(1, 15000, 100_000, Spec::Berlin));
+        assert_eq!(4800, refund(0, 4800, 100_000, Spec::London));
+        assert_eq!(2000, refund(0, 4800, 10_000, Spec::London));
+    }
+
+    #[test]
+    fn should_charge_sstore_from_the_original_value() {
+        let [zero, one, two] = [0, 1, 2].map(U256::from);
+        let cost = |original, current, new, spec| sstore_cost(original, current, new, spec);
+
+        // Before EIP-2200, only the current value matters.
+        assert_eq!((20000, 0), cost(&one, &zero, &one, Spec::Petersburg));
+        assert_eq!((5000, 15000), cost(&zero, &one, &zero, Spec::Petersburg));
+        assert_eq!((5000, 0), cost(&one, &one, &one, Spec::Petersburg));
+
+        // A no-op, a first write and a dirty write.
+        assert_eq!((800, 0), cost(&one, &one, &one, Spec::Istanbul));
+        assert_eq!((20000, 0), cost(&zero, &zero, &one, Spec::Istanbul));
+        assert_eq!((5000, 15000), cost(&one, &one, &zero, Spec::Istanbul));
+        assert_eq!((800, 0), cost(&one, &two, &two, Spec::Istanbul));
+
+        // Re-dirtying a cleared slot takes its refund back.
+        assert_eq!((800, -15000), cost(&one, &zero, &two, Spec::Istanbul));
+        // Restoring the original value refunds the first write.
+        assert_eq!((800, 19200), cost(&zero, &one, &zero, Spec::Istanbul));
+        assert_eq!(
+            (800, -15000 + 4200),
+            cost(&one, &zero, &one, Spec::Istanbul)
+        );
+
+        // The slots are cold since Berlin, the refund is reduced since London.
+        assert_eq!((2200, 0), cost(&one, &one, &one, Spec::Berlin));
+        assert_eq!((5000, 15000), cost(&one, &one, &zero, Spec::Berlin));
+        assert_eq!((5000, 4800), cost(&one, &one, &zero, Spec::London));
+        assert_eq!((2200, -4800 + 2800), cost(&one, &zero, &one, Spec::London));
+    }
+
+    #[test]
+    fn should_ignore_a_negative_refund_counter() {
+        assert_eq!(0, refund(0, -4800, 100_000, Spec::London));
+    }
+
+    #[test]
+    fn should_reclaim_the_gas_unused_by_a_child() {
+        let mut gas = Gas::new(&U256::from(100));
+        gas.charge(10).unwrap();
+        let forwarded = gas.forward(U256::from(50), Spec::Berlin).unwrap();
+        assert_eq!(60, gas.used());
+        gas.reclaim(&forwarded, 20);
+        assert_eq!(30, gas.used());
+
+        // A not metered frame adds the gas used by its children.
+        let mut gas = Gas::new(&U256::MAX);
+        let forwarded = gas.forward(U256::from(50), Spec::Berlin).unwrap();
+        gas.reclaim(&forwarded, 20);
+        assert_eq!(20, gas.used());
+    }
+
+    #[test]
+    fn should_charge_exp_per_exponent_byte() {
+        assert_eq!(10, exp_cost(&U256::ZERO, Spec::Cancun));
+        assert_eq!(60, exp_cost(&U256::from(0xFF), Spec::Cancun));
+        assert_eq!(110, exp_cost(&U256::from(0x100), Spec::Cancun));
+        assert_eq!(10 + 10 * 32, exp_cost(&U256::MAX, Spec::Frontier));
+    }
+}
@@ -5,6 +5,9 @@ use thiserror::Error;
 
 use crate::types::Bytesize;
 
+/// The maximum memory size, far above what the gas of a block can pay for.
+pub(super) const MAX_SIZE: usize = 0x100_0000;
+
 #[derive(Debug, Clone)]
 pub(super) struct Memory {
     mem: RefCell<Vec<u8>>,
@@ -19,18 +22,43 @@ impl Memory {
 }
 
 impl Memory {
+    /// The size of the accessed memory, in words of 32 bytes whatever the allocated length.
     pub(super) fn size(&self) -> usize {
+        self.len().div_ceil(0x20) * 0x20
+    }
+
+    /// The bytes of the memory, up to its word-aligned size.
+    pub(super) fn into_bytes(self) -> Box<[u8]> {
+        let size = self.size();
+        let mut mem = self.mem.into_inner();
+        mem.resize(size, 0x00);
+        mem.into_boxed_slice()
+    }
+
+    /// The allocated length.
+    fn len(&self) -> usize {
         self.mem.borrow().len()
     }
 
+    /// The end of the `size` bytes at `offset`, if it fits in the maximum memory size.
+    pub(super) fn end(offset: usize, size: usize) -> Result<usize> {
+        if size == 0 {
+            return Ok(0);
+        }
+        match offset.checked_add(size) {
+            Some(end) if end <= MAX_SIZE => Ok(end),
+            _ => Err(MemoryError::SizeOverflow),
+        }
+    }
+
     fn expand_mem(&self) {
-        let length = self.mem.borrow().len();
+        let length = self.len();
         self.mem
             .borrow_mut()
             .resize(length + usize::from(Bytesize::MAX) + 1, 0x00);
     }
 
-    pub(super) fn load(&self, offset: usize, size: usize) -> Box<[u8]> {
+    pub(super) fn load(&self, offset: usize, size: usize) -> Result<Box<[u8]>> {
         log::trace!(
             "load(): mem={:02X?}, offset={:02X?}, size={:02X?}",
             self.mem,
@@ -38,30 +66,35 @@ impl Memory {
             size
         );
 
-        let max = offset + size;
-        let value = if max == 0 {
-            Box::new([])
-        } else {
-            // Expand memory if needed.
-            while self.size() < max {
-                self.expand_mem();
-            }
-
-            // Load from memory.
-            let r = Ref::map(self.mem.borrow(), |r| r.get(offset..max).expect("safe"));
-            r.to_owned().into_boxed_slice()
-        };
+        let value = Box::from(&*self.slice(offset, size)?);
 
         log::trace!("result: mem={:02X?}, value={:02X?}", self.mem, value);
-        value
+        Ok(value)
     }
 
-    pub(super) fn load_u256(&self, offset: usize) -> U256 {
-        let b = self.load(offset, 0x20);
-        U256::try_from_be_slice(&b).expect("safe")
+    /// Borrow `size` bytes at `offset` without copying them.
+    /// The memory cannot be modified until the returned slice is dropped.
+    pub(super) fn slice(&self, offset: usize, size: usize) -> Result<Ref<'_, [u8]>> {
+        let max = Memory::end(offset, size)?;
+        if max == 0 {
+            return Ok(Ref::map(self.mem.borrow(), |_| &[]));
+        }
+        // Expand memory if needed.
+        while self.len() < max {
+            self.expand_mem();
+        }
+
+        Ok(Ref::map(self.mem.borrow(), |r| {
+            r.get(offset..max).expect("safe")
+        }))
     }
 
-    pub(super) fn store(&mut self, offset: usize, size: usize, value: &[u8]) {
+    pub(super) fn load_u256(&self, offset: usize) -> Result<U256> {
+        let b = self.load(offset, 0x20)?;
+        Ok(U256::try_from_be_slice(&b).expect("safe"))
+    }
+
+    pub(super) fn store(&mut self, offset: usize, size: usize, value: &[u8]) -> Result<()> {
         log::trace!(
             "store(): mem={:02X?}, offset={:02X?}, size={:02X?}, value={:02X?}",
             self.mem,
@@ -70,10 +103,10 @@ impl Memory {
             value
         );
 
-        let max = offset + size;
+        let max = Memory::end(offset, size)?;
         if max != 0 {
             // Expand memory if needed.
-            while self.size() < max {
+            while self.len() < max {
                 self.expand_mem();
             }
 
@@ -85,15 +118,41 @@ impl Memory {
         }
 
         log::trace!("result: mem={:02X?}", self.mem);
+        Ok(())
     }
 
-    pub(super) fn store_u256(&mut self, offset: usize, value: U256) {
+    pub(super) fn store_u256(&mut self, offset: usize, value: U256) -> Result<()> {
         self.store(offset, 0x20, &value.to_be_bytes::<0x20>())
     }
 
-    pub(super) fn store_u8(&mut self, offset: usize, value: u8) {
+    pub(super) fn store_u8(&mut self, offset: usize, value: u8) -> Result<()> {
         self.store(offset, 0x01, &[value; 0x01])
     }
+
+    /// Copy `size` bytes from `src` to `dest`, the regions may overlap.
+    pub(super) fn copy_within(&mut self, dest: usize, src: usize, size: usize) -> Result<()> {
+        log::trace!(
+            "copy_within(): mem={:02X?}, dest={:02X?}, src={:02X?}, size={:02X?}",
+            self.mem,
+            dest,
+            src,
+            size
+        );
+
+        let max = std::cmp::max(Memory::end(dest, size)?, Memory::end(src, size)?);
+        if max != 0 {
+            // Expand memory if needed.
+            while self.len() < max {
+                self.expand_mem();
+            }
+
+            // Copy in memory.
+            self.mem.get_mut().copy_within(src..src + size, dest);
+        }
+
+        log::trace!("result: mem={:02X?}", self.mem);
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -107,17 +166,138 @@ impl From<Memory> for MemoryResult {
     }
 }
 
-//pub(super) type Result<T> = std::result::Result<T, MemoryError>;
+pub(super) type Result<T> = std::result::Result<T, MemoryError>;
 
 #[derive(Error, Debug, Clone)]
 pub enum MemoryError {
     OffsetHigherThanSize,
+    SizeOverflow,
 }
 
 impl std::fmt::Display for MemoryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             MemoryError::OffsetHigherThanSize => write!(f, "offset higher than size"),
+            MemoryError::SizeOverflow => write!(f, "memory size overflow"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_copy_within_overlapping_regions() {
+        let mut memory = Memory::new();
+        memory.store(0, 4, &[0x01, 0x02, 0x03, 0x04]).unwrap();
+
+        // Forward.
+        memory.copy_within(1, 0, 4).unwrap();
+        assert_eq!(
+            &[0x01, 0x01, 0x02, 0x03, 0x04],
+            &memory.load(0, 5).unwrap()[..]
+        );
+
+        // Backward.
+        memory.copy_within(0, 1, 4).unwrap();
+        assert_eq!(
+            &[0x01, 0x02, 0x03, 0x04, 0x04],
+            &memory.load(0, 5).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn should_expand_memory_on_copy_within() {
+        let mut memory = Memory::new();
+        memory.store_u8(0, 0xFF).unwrap();
+
+        memory.copy_within(0x40, 0, 1).unwrap();
+        assert_eq!(0x60, memory.size());
+        assert_eq!(&[0xFF], &memory.load(0x40, 1).unwrap()[..]);
+
+        // Copying nothing does not expand memory.
+        memory.copy_within(0x100, 0x200, 0).unwrap();
+        assert_eq!(0x60, memory.size());
+    }
+
+    #[test]
+    fn should_reject_copies_beyond_the_maximum_size() {
+        let mut memory = Memory::new();
+        for (dest, src) in [
+            (0, usize::MAX),
+            (usize::MAX, 0),
+            (MAX_SIZE, 0),
+            (0, MAX_SIZE),
+        ] {
+            assert!(matches!(
+                memory.copy_within(dest, src, 1),
+                Err(MemoryError::SizeOverflow)
+            ));
         }
+        assert_eq!(0, memory.size());
+
+        // Copying nothing never fails.
+        assert!(memory.copy_within(usize::MAX, usize::MAX, 0).is_ok());
+    }
+
+    #[test]
+    fn should_borrow_a_slice_of_memory() {
+        let mut memory = Memory::new();
+        memory.store(0, 2, &[0x01, 0x02]).unwrap();
+
+        assert_eq!(&[0x02, 0x00], &*memory.slice(1, 2).unwrap());
+        assert!(memory.slice(0, 0).unwrap().is_empty());
+        // The slice expands memory like a load.
+        assert_eq!(&[0x00], &*memory.slice(0x40, 1).unwrap());
+        assert_eq!(0x60, memory.size());
+    }
+
+    #[test]
+    fn should_reject_loads_beyond_the_maximum_size() {
+        let memory = Memory::new();
+        assert!(matches!(
+            memory.load_u256(usize::MAX),
+            Err(MemoryError::SizeOverflow)
+        ));
+        assert!(matches!(
+            memory.slice(MAX_SIZE, 1),
+            Err(MemoryError::SizeOverflow)
+        ));
+        assert_eq!(0, memory.size());
+
+        // Loading nothing never fails.
+        assert!(memory.load(usize::MAX, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn size_should_be_word_aligned() {
+        let mut memory = Memory::new();
+        memory.store_u8(0, 0xFF).unwrap();
+        assert_eq!(0x20, memory.size());
+
+        // Whatever the allocated length.
+        memory.mem.get_mut().truncate(1);
+        assert_eq!(0x20, memory.size());
+        memory.mem.get_mut().resize(0x21, 0x00);
+        assert_eq!(0x40, memory.size());
+    }
+
+    #[test]
+    fn should_reject_stores_beyond_the_maximum_size() {
+        let mut memory = Memory::new();
+        assert!(matches!(
+            memory.store_u256(usize::MAX - 0x10, U256::MAX),
+            Err(MemoryError::SizeOverflow)
+        ));
+        assert!(matches!(
+            memory.store_u8(MAX_SIZE, 0xFF),
+            Err(MemoryError::SizeOverflow)
+        ));
+        assert_eq!(0, memory.size());
+
+        // Storing nothing never fails.
+        assert!(memory.store(usize::MAX, 0, &[]).is_ok());
+        assert!(memory.store_u8(MAX_SIZE - 1, 0xFF).is_ok());
     }
 }
@@ -1,47 +1,110 @@
 mod code;
+mod eof;
 mod evm;
+mod gas;
 mod memory;
+mod precompile;
 mod stack;
+mod validate;
 
 use crate::types::*;
-use code::*;
+pub use code::Opcode;
 pub(super) use evm::*;
+pub use evm::{HaltReason, Step, Stepper};
+pub(crate) use gas::refund;
 use memory::*;
 use ruint::aliases::U256;
-use sha3::Digest;
+pub use validate::*;
+
+/// An opcode popping `a` then `b` and pushing the result of `body`.
+macro_rules! binop {
+    ($evm:ident, |$a:ident, $b:ident| $body:expr) => {
+        $evm.stack
+            .pop()
+            .and_then(|$a| $evm.stack.pop().map(|$b| ($a, $b)))
+            .map(|($a, $b)| $body)
+            .and_then(|c| $evm.stack.push(c))
+            .map_err(EVMError::StackError)
+    };
+}
+
+/// An opcode popping `a` and pushing the result of `body`.
+macro_rules! unop {
+    ($evm:ident, |$a:ident| $body:expr) => {
+        $evm.stack
+            .pop()
+            .map(|$a| $body)
+            .and_then(|c| $evm.stack.push(c))
+            .map_err(EVMError::StackError)
+    };
+}
+
+/// The opcodes calling an account, only CALL pops a value to send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CallKind {
+    Call,
+    Delegatecall,
+    Staticcall,
+}
 
 impl<'a, 'b> Message<'a, 'b>
 where
     'a: 'b,
 {
     pub(crate) fn process<'c, 'd>(self, env: &'d mut Environment<'c>) -> EVMResult
+    where
+        'c: 'd,
+        'c: 'a,
+    {
+        self.process_stepping(env, None)
+    }
+
+    /// Like `process`, the bytecode of the frame is first executed by `step`.
+    pub(crate) fn process_stepping<'c, 'd>(
+        self,
+        env: &'d mut Environment<'c>,
+        step: Option<&mut dyn FnMut(Stepper<'_>)>,
+    ) -> EVMResult
     where
         'c: 'd,
         'c: 'a,
     {
         match self {
+            // Executes a staticcall to an account.
+            Message::Staticcall { .. } => {
+                let evm = EVM::with_env(FrameEnv::Static(env), &self);
+                evm.execute_stepping(step)
+            }
             // Executes a call to an account.
             Message::Call { .. } |
             // Executes a delegatecall to an account.
-            Message::Delegatecall { .. } |
-            // Executes a staticcall to an account.
-            Message::Staticcall { .. } => {
+            Message::Delegatecall { .. } => {
                 // Execute code.
                 let evm = EVM::new(env, &self);
-                EVM::execute(evm)
+                evm.execute_stepping(step)
             }
             // Create a smart contract account.
             Message::Create { .. } => {
+                // State checkpoint, the initialization code is not kept if the creation fails.
+                let checkpoint = env.state_mut().checkpoint();
+
                 // Set target's code to the initialization code.
                 let init_code = self.data().into();
-                env.state_mut().update_account(self.target(), |_| Ok(Account::new(None, Some(init_code)))).expect("safe");
+                env.state_mut().update_account(self.target(), |a| Ok(a.set_code(init_code)?)).expect("safe");
 
                 // Execute code.
                 let evm = EVM::new(env, &self);
-                let result = EVM::execute(evm);
+                let mut result = evm.execute_stepping(step);
+                result.charge_code_deposit(self.gas());
 
-                // Set target's code to the returned data.
-                env.state_mut().update_account(self.target(), |a| Ok(Account::new(Some(*a.balance()), Some(result.return_data().clone())))).expect("safe");
+                // Deploy the returned runtime code.
+                if result.status() {
+                    env.state_mut().update_account(self.target(), |a| Ok(a.set_code(result.return_data().clone())?)).expect("safe");
+                    env.state_mut().commit(checkpoint).expect("safe");
+                    result.created_address = Some(self.target().clone());
+                } else {
+                    env.state_mut().revert_to(checkpoint).expect("safe");
+                }
 
                 result
             }
@@ -49,6 +112,20 @@ where
     }
 }
 
+impl<'a, 'b> Message<'a, 'b>
+where
+    'a: 'b,
+{
+    /// Executes a staticcall, which cannot modify the state of the `env` it only borrows.
+    pub(super) fn process_static<'c>(self, env: &Environment<'c>) -> EVMResult
+    where
+        'c: 'a,
+    {
+        let evm = EVM::with_env(FrameEnv::Static(env), &self);
+        EVM::execute(evm)
+    }
+}
+
 impl<'a, 'b, 'c, 'd> Iterator for &mut EVM<'a, 'b, 'c, 'd>
 where
     'a: 'c,
@@ -58,523 +135,216 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         log::trace!("next(): get the next opcode");
+
+        // The steps are counted over all the frames of the transaction.
+        if !self.env.take_step() {
+            self.result = Some(Err(EVMError::StepLimitExceeded));
+            // Stop.
+            return None;
+        }
+
+        let opcode = self.code.next().expect("safe");
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push((self.code.current_op_offset(), opcode.clone()));
+        }
+        #[cfg(feature = "trace-steps")]
+        log::trace!(
+            "step: pc={:#06X}, opcode={:02X?}, stack={:?}, memory_size={}, gas_used={}",
+            self.code.current_op_offset(),
+            opcode,
+            self.stack
+                .iter()
+                .map(|v| format!("{:#X}", v))
+                .collect::<Vec<_>>(),
+            self.memory.size(),
+            self.gas.used()
+        );
+        if let Err(e) = self.gas.charge(gas::static_cost(&opcode)) {
+            self.result = Some(Err(e.into()));
+            // Stop.
+            return None;
+        }
+
+        let res = self.execute_opcode(opcode);
+        match res {
+            // The opcodes halting the execution set its result.
+            Ok(()) if self.result.is_none() => Some(()),
+            Ok(()) => None,
+            Err(e) => {
+                self.result = Some(Err(e));
+                // Stop.
+                None
+            }
+        }
+    }
+}
+
+impl<'a, 'b, 'c, 'd> EVM<'a, 'b, 'c, 'd>
+where
+    'a: 'c,
+    'b: 'd,
+{
+    /// Execute `opcode`, the execution halts if it fails or sets the result.
+    fn execute_opcode(&mut self, opcode: Opcode) -> std::result::Result<(), EVMError> {
         use Opcode::*;
 
-        match self.code.next().expect("safe") {
+        match opcode {
             STOP => {
                 self.result = Some(Ok((U256::ZERO, U256::ZERO)));
                 // Stop.
-                None
-            }
-            ADD => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| {
-                    // Add must overflow.
-                    let (c, _) = a.overflowing_add(b);
-                    c
-                })
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            MUL => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| {
-                    // Mul must overflow.
-                    let (c, _) = a.overflowing_mul(b);
-                    c
-                })
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SUB => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| {
-                    // Sub must overflow.
-                    let (c, _) = a.overflowing_sub(b);
-                    c
-                })
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            DIV => match self
-                    .stack
-                    .pop()
-                    .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                    .map(|(a, b)|
-                         // If denominator is zero, result is 0.
-                         if b == U256::ZERO { b } else { a / b }
-                    )
-                    .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SDIV => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| {
-                    // Assume a and b are signed.
-                    Int256::from_raw_u256(a) / Int256::from_raw_u256(b)
-                })
-                .and_then(|c| self.stack.push(c.to_raw_u256()))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            MOD => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)|
-                        // If denominator is zero, result is 0.
-                        if b == U256::ZERO { b } else { a % b }
-                )
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SMOD => {
-                let res = self
-                    .stack
-                    .pop()
-                    .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                    .map(|(a, b)| {
-                        // Assume a and b are signed.
-                        Int256::from_raw_u256(a) % Int256::from_raw_u256(b)
-                    })
-                    .and_then(|c| self.stack.push(c.to_raw_u256()));
-
-                match res {
-                    Ok(_) => Some(()),
-                    Err(e) => {
-                        self.result = Some(Err(EVMError::StackError(e)));
-                        // Stop.
-                        None
-                    }
-                }
+                Ok(())
             }
-            ADDMOD => match self
+            ADD => binop!(self, |a, b| {
+                // Add must overflow.
+                let (c, _) = a.overflowing_add(b);
+                c
+            }),
+            MUL => binop!(self, |a, b| {
+                // Mul must overflow.
+                let (c, _) = a.overflowing_mul(b);
+                c
+            }),
+            SUB => binop!(self, |a, b| {
+                // Sub must overflow.
+                let (c, _) = a.overflowing_sub(b);
+                c
+            }),
+            DIV => binop!(self, |a, b| {
+                // If denominator is zero, result is 0.
+                if b == U256::ZERO {
+                    b
+                } else {
+                    a / b
+                }
+            }),
+            SDIV => binop!(self, |a, b| {
+                // Assume a and b are signed.
+                (Int256::from_raw_u256(a) / Int256::from_raw_u256(b)).to_raw_u256()
+            }),
+            MOD => binop!(self, |a, b| {
+                // If denominator is zero, result is 0.
+                if b == U256::ZERO {
+                    b
+                } else {
+                    a % b
+                }
+            }),
+            SMOD => binop!(self, |a, b| {
+                // Assume a and b are signed.
+                (Int256::from_raw_u256(a) % Int256::from_raw_u256(b)).to_raw_u256()
+            }),
+            ADDMOD => self
                 .stack
                 .pop()
                 .and_then(|a| self.stack.pop().map(|b| (a, b)))
                 .and_then(|(a, b)| self.stack.pop().map(|n| (a, b, n)))
                 .map(|(a, b, n)| a.add_mod(b, n))
                 .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            MULMOD => match self
+                .map_err(EVMError::StackError),
+            MULMOD => self
                 .stack
                 .pop()
                 .and_then(|a| self.stack.pop().map(|b| (a, b)))
                 .and_then(|(a, b)| self.stack.pop().map(|n| (a, b, n)))
                 .map(|(a, b, n)| a.mul_mod(b, n))
                 .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            EXP => match self
+                .map_err(EVMError::StackError),
+            EXP => self
                 .stack
                 .pop()
                 .and_then(|a| self.stack.pop().map(|e| (a, e)))
-                .map(|(a, e)| {
+                .map_err(EVMError::StackError)
+                .and_then(|(a, e)| {
+                    // Charge before computing the power of a large exponent.
+                    self.gas.charge(gas::exp_cost(&e, self.env.spec()))?;
                     let (n, _) = a.overflowing_pow(e);
-                    n
-                })
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SIGNEXTEND => match self
-                .stack
-                .pop()
-                .and_then(|b| self.stack.pop().map(|x| (b, x)))
-                .map(|(b, x)| {
-                    // x assumed to be signed.
-                    IntN::from_raw_u256(x, b.saturating_to()).sign_extend()
-                })
-                .and_then(|c| self.stack.push(c.to_raw_u256()))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            LT => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| a < b)
-                .and_then(|c| self.stack.push(U256::from(c as u8)))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            GT => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| a > b)
-                .and_then(|c| self.stack.push(c as u8))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SLT => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)|
-                         // a and b assumed to be signed.
-                         Int256::from_raw_u256(a) < Int256::from_raw_u256(b))
-                .and_then(|c| self.stack.push(c as u8))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SGT => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)|
-                         // a and b assumed to be signed.
-                         Int256::from_raw_u256(a) > Int256::from_raw_u256(b))
-                .and_then(|c| self.stack.push(c as u8))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            EQ => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| a == b)
-                .and_then(|c| self.stack.push(c as u8))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            AND => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| a & b)
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            OR => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| a | b)
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            XOR => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(a, b)| a ^ b)
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            ISZERO => match self
-                .stack
-                .pop()
-                .map(|a| a == U256::ZERO)
-                .and_then(|c| self.stack.push(c as u8))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            NOT => match self
-                .stack
-                .pop()
-                .map(|a| !a)
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            BYTE => match self
-                .stack
-                .pop()
-                .and_then(|i| self.stack.pop().map(|x| (i, x)))
-                .map(|(i, x)| {
-                    if i > Bytesize::MAX.into() {
-                        0x00
-                    } else {
-                        x.to_be_bytes::<0x20>()[usize::from(i.saturating_to::<Bytesize>())]
-                    }
+                    Ok(n)
                 })
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SHL => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(shift, value)| value << shift.saturating_to::<usize>())
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SHR => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(shift, value)| value >> shift.saturating_to::<usize>())
-                .and_then(|c| self.stack.push(c))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SAR => match self
-                .stack
-                .pop()
-                .and_then(|a| self.stack.pop().map(|b| (a, b)))
-                .map(|(shift, value)|
-                    // value assumed to be signed.
-                    Int256::from_raw_u256(value) >> shift.into())
-                .and_then(|c| self.stack.push(c.to_raw_u256()))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SHA3 => match self
+                .and_then(|c| self.stack.push(c).map_err(EVMError::StackError)),
+            SIGNEXTEND => binop!(self, |b, x| match Bytesize::try_from(&b) {
+                // x assumed to be signed.
+                Ok(size) => IntN::from_raw_u256(x, size).sign_extend().to_raw_u256(),
+                // x is already 32 bytes long, nothing to extend.
+                Err(_) => x,
+            }),
+            LT => binop!(self, |a, b| (a < b) as u8),
+            GT => binop!(self, |a, b| (a > b) as u8),
+            SLT => binop!(self, |a, b| {
+                // a and b assumed to be signed.
+                (Int256::from_raw_u256(a) < Int256::from_raw_u256(b)) as u8
+            }),
+            SGT => binop!(self, |a, b| {
+                // a and b assumed to be signed.
+                (Int256::from_raw_u256(a) > Int256::from_raw_u256(b)) as u8
+            }),
+            EQ => binop!(self, |a, b| (a == b) as u8),
+            AND => binop!(self, |a, b| a & b),
+            OR => binop!(self, |a, b| a | b),
+            XOR => binop!(self, |a, b| a ^ b),
+            ISZERO => unop!(self, |a| (a == U256::ZERO) as u8),
+            NOT => unop!(self, |a| !a),
+            BYTE => binop!(self, |i, x| {
+                usize::try_from(i)
+                    .ok()
+                    .and_then(|i| x.to_be_bytes::<0x20>().get(i).copied())
+                    // Out of range bytes are 0.
+                    .unwrap_or(0x00)
+            }),
+            SHL => binop!(self, |shift, value| value << shift.saturating_to::<usize>()),
+            SHR => binop!(self, |shift, value| value >> shift.saturating_to::<usize>()),
+            SAR => binop!(self, |shift, value| {
+                // value assumed to be signed.
+                (Int256::from_raw_u256(value) >> shift.into()).to_raw_u256()
+            }),
+            SHA3 => self
                 .stack
                 .pop()
                 .and_then(|offset| self.stack.pop().map(|size| (offset, size)))
                 .map_err(EVMError::StackError)
-                .map(|(offset, size)| {
+                .and_then(|(offset, size)| {
                     let offset = offset.saturating_to();
                     let size = size.saturating_to();
-                    self.memory.load(offset, size)
+                    Ok(self.memory.load(offset, size)?)
                 })
-                .map(|value| {
-                    let mut hasher = sha3::Keccak256::new();
-                    hasher.update(value.to_vec());
-                    hasher.finalize()
-                })
-                .map(|hash| U256::try_from_be_slice(&hash[..]).expect("safe"))
-                .and_then(|c| self.stack.push(c).map_err(EVMError::StackError))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            ADDRESS => match self
+                .map(|value| keccak256_u256(&value))
+                .and_then(|c| self.stack.push(c).map_err(EVMError::StackError)),
+            ADDRESS => self
                 .stack
                 .push(<U256 as From<&Address>>::from(self.message.target()))
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            BALANCE => match self
+                .map_err(EVMError::StackError),
+            BALANCE => self
                 .stack
                 .pop()
-                .map(|addr| self.env.state().get_account(&addr.into()).balance())
-                .and_then(|balance| self.stack.push(*balance))
+                .map(Address::from)
                 .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            ORIGIN => match self
+                .and_then(|addr| {
+                    self.charge_account_access(&BALANCE, &addr)?;
+                    let balance = *self.state().get_account(&addr).balance();
+                    self.stack.push(balance).map_err(EVMError::StackError)
+                }),
+            ORIGIN => self
                 .stack
                 .push(<U256 as From<&Address>>::from(self.env.caller()))
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CALLER => match self
+                .map_err(EVMError::StackError),
+            CALLER => self
                 .stack
                 .push(<U256 as From<&Address>>::from(self.message.caller()))
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CALLVALUE => match self
+                .map_err(EVMError::StackError),
+            CALLVALUE => self
                 .stack
                 .push(*self.message.value())
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CALLDATALOAD => match self
+                .map_err(EVMError::StackError),
+            CALLDATALOAD => self
                 .stack
                 .pop()
                 .map(|i| self.message.data().load_word(i.saturating_to()))
                 .and_then(|data| self.stack.push(U256::from_be_bytes(data)))
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CALLDATASIZE => match self
+                .map_err(EVMError::StackError),
+            CALLDATASIZE => self
                 .stack
                 .push(self.message.data().size())
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CALLDATACOPY => match self
+                .map_err(EVMError::StackError),
+            CALLDATACOPY => self
                 .stack
                 .pop()
                 .and_then(|dest_offset| self.stack.pop().map(|offset| (dest_offset, offset)))
@@ -582,33 +352,24 @@ where
                     self.stack.pop().map(|size| (dest_offset, offset, size))
                 })
                 .map_err(EVMError::StackError)
-                .map(|(dest_offset, offset, size)| {
+                .and_then(|(dest_offset, offset, size)| {
                     let dest_offset = dest_offset.saturating_to::<usize>();
                     let offset = offset.saturating_to::<usize>();
                     let size = size.saturating_to::<usize>();
 
-                    self.memory.store(
+                    // Check the bounds before loading the data.
+                    Memory::end(dest_offset, size)?;
+                    Ok(self.memory.store(
                         dest_offset,
                         size,
                         self.message.data().load(offset, size).as_ref(),
-                    )
-                }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CODESIZE => match self.stack.push(self.code.size()) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            CODECOPY => match self
+                    )?)
+                }),
+            CODESIZE => self
+                .stack
+                .push(self.code.size())
+                .map_err(EVMError::StackError),
+            CODECOPY => self
                 .stack
                 .pop()
                 .and_then(|dest_offset| self.stack.pop().map(|offset| (dest_offset, offset)))
@@ -616,45 +377,34 @@ where
                     self.stack.pop().map(|size| (dest_offset, offset, size))
                 })
                 .map_err(EVMError::StackError)
-                .map(|(dest_offset, offset, size)| {
+                .and_then(|(dest_offset, offset, size)| {
                     let dest_offset = dest_offset.saturating_to();
                     let offset = offset.saturating_to();
                     let size = size.saturating_to();
 
-                    self.memory
-                        .store(dest_offset, size, self.code.load(offset, size).as_ref())
-                }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            GASPRICE => match self
+                    // Check the bounds before loading the code.
+                    Memory::end(dest_offset, size)?;
+                    Ok(self.memory.store(
+                        dest_offset,
+                        size,
+                        self.code.load(offset, size).as_ref(),
+                    )?)
+                }),
+            GASPRICE => self
                 .stack
                 .push(*self.env.gas_price())
+                .map_err(EVMError::StackError),
+            EXTCODESIZE => self
+                .stack
+                .pop()
+                .map(Address::from)
                 .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            EXTCODESIZE => match self.stack.pop().map(Address::from).and_then(|addr| {
-                self.stack
-                    .push(self.env.state().get_account(&addr).code().len())
-            }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            EXTCODECOPY => match self
+                .and_then(|addr| {
+                    self.charge_account_access(&EXTCODESIZE, &addr)?;
+                    let size = self.state().get_account(&addr).code().len();
+                    self.stack.push(size).map_err(EVMError::StackError)
+                }),
+            EXTCODECOPY => self
                 .stack
                 .pop()
                 .map(Address::from)
@@ -668,22 +418,19 @@ where
                         .map(|size| (addr, dest_offset, offset, size))
                 })
                 .map_err(EVMError::StackError)
-                .map(|(addr, dest_offset, offset, size)| {
+                .and_then(|(addr, dest_offset, offset, size)| {
+                    self.charge_account_access(&EXTCODECOPY, &addr)?;
                     let dest_offset = dest_offset.saturating_to();
                     let offset = offset.saturating_to();
                     let size = size.saturating_to();
-                    let code = Code::new(self.env.state().get_account(&addr).code());
-
-                    self.memory
-                        .store(dest_offset, size, code.load(offset, size).as_ref())
-                }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
+
+                    // Check the bounds before loading the code.
+                    Memory::end(dest_offset, size)?;
+                    // The code is copied as bytes, without analysing its opcodes.
+                    let code = self.state().get_account(&addr).code();
+                    let bytes = Bytes::new(code).load_padded(offset, size);
+                    Ok(self.memory.store(dest_offset, size, &bytes)?)
+                }),
             RETURNDATASIZE => {
                 let size = self
                     .last_inner_call
@@ -692,17 +439,9 @@ where
                     .clone()
                     .unwrap_or_default();
 
-                let res = self.stack.push(size).map_err(EVMError::StackError);
-                match res {
-                    Ok(_) => Some(()),
-                    Err(e) => {
-                        self.result = Some(Err(e));
-                        // Stop.
-                        None
-                    }
-                }
+                self.stack.push(size).map_err(EVMError::StackError)
             }
-            RETURNDATACOPY => match Ok(())
+            RETURNDATACOPY => Ok(())
                 .and_then(|_| {
                     Ok((
                         self.stack.pop()?.saturating_to::<usize>(),
@@ -714,224 +453,116 @@ where
                 .and_then(|(dest_offset, offset, size)| {
                     if let Some(call) = &self.last_inner_call {
                         let return_data = &call.return_data;
-                        // Check `offset` is less than `return_data`.len().
-                        if offset + size > return_data.len() {
-                            return Err(EVMError::MemoryError(MemoryError::OffsetHigherThanSize));
+                        // Reading past the end of the return data fails (EIP-211).
+                        match offset.checked_add(size) {
+                            Some(end) if end <= return_data.len() => {}
+                            _ => {
+                                return Err(EVMError::MemoryError(
+                                    MemoryError::OffsetHigherThanSize,
+                                ))
+                            }
                         }
 
-                        self.memory.store(dest_offset, size, return_data.as_ref());
+                        let data = Bytes::new(return_data).load_padded(offset, size);
+                        self.memory.store(dest_offset, size, &data)?;
                     }
                     Ok(())
-                }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            EXTCODEHASH => match self
+                }),
+            EXTCODEHASH => self
                 .stack
                 .pop()
-                .map(|addr| self.env.state().get_account(&addr.into()).code_hash())
-                .and_then(|hash| self.stack.push(hash))
+                .map(Address::from)
                 .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            BLOCKHASH => match self
+                .and_then(|addr| {
+                    self.charge_account_access(&EXTCODEHASH, &addr)?;
+                    let hash = self.state().get_account(&addr).code_hash();
+                    self.stack.push(hash).map_err(EVMError::StackError)
+                }),
+            BLOCKHASH => self
                 .stack
                 .pop()
                 .map(|number| self.env.block_hash(number.saturating_to::<usize>()))
                 .and_then(|c| self.stack.push(c.clone()))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            COINBASE => match self
+                .map_err(EVMError::StackError),
+            COINBASE => self
                 .stack
                 .push(<U256 as From<&Address>>::from(self.env.coinbase()))
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            TIMESTAMP => match self
+                .map_err(EVMError::StackError),
+            TIMESTAMP => self
                 .stack
-                .push(*self.env.time())
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            NUMBER => match self
+                .push(self.env.time_value())
+                .map_err(EVMError::StackError),
+            NUMBER => self
                 .stack
-                .push(*self.env.number())
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            DIFFICULTY => match self
+                .push(self.env.number_value())
+                .map_err(EVMError::StackError),
+            DIFFICULTY => self
                 .stack
-                .push(*self.env.difficulty())
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            GASLIMIT => match self
+                // PREVRANDAO since the merge, the difficulty when it is not given.
+                .push(match self.env.prevrandao() {
+                    Some(prevrandao) if SpecConfig::for_fork(self.env.spec()).prevrandao => {
+                        *prevrandao
+                    }
+                    _ => *self.env.difficulty(),
+                })
+                .map_err(EVMError::StackError),
+            GASLIMIT => self
                 .stack
                 .push(*self.env.gas_limit())
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CHAINID => match self
+                .map_err(EVMError::StackError),
+            CHAINID => self
                 .stack
-                .push(*self.env.chain_id())
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            BASEFEE => match self
+                .push(self.env.chain_id_value())
+                .map_err(EVMError::StackError),
+            BASEFEE => self
                 .stack
-                .push(*self.env.base_fee_per_gas())
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            SELFBALANCE => match self
+                .push(self.env.base_fee_per_gas_value())
+                .map_err(EVMError::StackError),
+            BLOBHASH => self
                 .stack
-                .push(
-                    self.env
-                        .state()
-                        .get_account(self.message.target())
-                        .balance()
-                        .clone(),
-                )
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            POP => match self.stack.pop().map(|_| ()) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            MLOAD => match self
+                .pop()
+                .map(|index| *self.env.blob_versioned_hash(index.saturating_to::<usize>()))
+                .and_then(|hash| self.stack.push(hash))
+                .map_err(EVMError::StackError),
+            BLOBBASEFEE => self
+                .stack
+                .push(*self.env.blob_base_fee())
+                .map_err(EVMError::StackError),
+            SELFBALANCE => self
+                .stack
+                .push(*self.state().get_account(self.message.target()).balance())
+                .map_err(EVMError::StackError),
+            POP => self.stack.pop().map(|_| ()).map_err(EVMError::StackError),
+            MLOAD => self
                 .stack
                 .pop()
-                .map(|offset| self.memory.load_u256(offset.saturating_to()))
-                .and_then(|value| self.stack.push(value))
                 .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            MSTORE => match self
+                .and_then(|offset| Ok(self.memory.load_u256(offset.saturating_to())?))
+                .and_then(|value| self.stack.push(value).map_err(EVMError::StackError)),
+            MSTORE => self
                 .stack
                 .pop()
                 .and_then(|offset| self.stack.pop().map(|b| (offset, b)))
                 .map_err(EVMError::StackError)
-                .map(|(offset, b)| self.memory.store_u256(offset.saturating_to(), b))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            MSTORE8 => match self
+                .and_then(|(offset, b)| Ok(self.memory.store_u256(offset.saturating_to(), b)?)),
+            MSTORE8 => self
                 .stack
                 .pop()
                 .and_then(|offset| self.stack.pop().map(|b| (offset, b)))
                 .map_err(EVMError::StackError)
-                .map(|(offset, b)| {
-                    self.memory
-                        .store_u8(offset.saturating_to(), b.saturating_to())
-                }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            SLOAD => match self
+                .and_then(|(offset, b)| {
+                    // Only the lowest byte is stored.
+                    Ok(self
+                        .memory
+                        .store_u8(offset.saturating_to(), b.wrapping_to())?)
+                }),
+            SLOAD => self
                 .stack
                 .pop()
-                .map(|key| {
-                    self.env
-                        .state()
-                        .get_account(self.message.target())
-                        .load(&key)
-                        .clone()
-                })
+                .map(|key| *self.state().get_account(self.message.target()).load(&key))
                 .and_then(|v| self.stack.push(v))
-                .map_err(EVMError::StackError)
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            SSTORE => match (if self.message.is_staticcall() {
+                .map_err(EVMError::StackError),
+            SSTORE => (if self.message.is_staticcall() {
                 Err(EVMError::StateModificationDisallowed)
             } else {
                 Ok(())
@@ -943,36 +574,34 @@ where
                     .map_err(EVMError::StackError)
                     .map(|value| (key, value))
             })
-            .map(|(key, value)| {
-                self.env
-                    .state_mut()
-                    .update_account(self.message.target(), |mut account| {
-                        account.store(key, value);
-                        Ok(account)
-                    })
-                    .expect("safe")
-            }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            JUMP => match self
+            .and_then(|(key, value)| {
+                let message = self.message;
+                let spec = self.env.spec();
+                // An SSTORE cannot spend the stipend given to a transfer (EIP-2200).
+                if SpecConfig::for_fork(spec).net_gas_metering
+                    && self.gas.remaining() <= U256::from(gas::G_CALL_STIPEND)
+                {
+                    return Err(EVMError::GasError(gas::GasError::OutOfGas));
+                }
+                let state = self.state();
+                let current = *state.get_account(message.target()).load(&key);
+                let original = state.original_load(message.target(), &key);
+                // Slots are not tracked in an access list, every SSTORE pays the cold surcharge
+                // from Berlin on, even for a slot already loaded or stored in the transaction,
+                // while SLOAD is charged nothing for it.
+                let (cost, refund) = gas::sstore_cost(&original, &current, &value, spec);
+                self.gas.charge(cost)?;
+                self.gas.add_refund(refund);
+
+                self.state_mut()?.store(message.target(), key, value);
+                Ok(())
+            }),
+            JUMP => self
                 .stack
                 .pop()
                 .map_err(EVMError::StackError)
-                .and_then(|counter| self.code.jump_to(counter).map_err(EVMError::CodeError))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            JUMPI => match self
+                .and_then(|counter| self.code.jump_to(counter).map_err(EVMError::CodeError)),
+            JUMPI => self
                 .stack
                 .pop()
                 .and_then(|counter| self.stack.pop().map(|b| (counter, b)))
@@ -983,64 +612,81 @@ where
                     } else {
                         Ok(())
                     }
-                }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            PC => match self.stack.push(self.code.pc() - 1) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            MSIZE => match self.stack.push(self.memory.size()) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            GAS => match self.stack.push(U256::MAX) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            JUMPDEST => Some(()),
-            PUSH(n) => match self.stack.push(n) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            DUP(n) => match self.stack.dup(n) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            SWAP(n) => match self.stack.swap(n) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(EVMError::StackError(e)));
-                    // Stop.
-                    None
-                }
-            },
-            LOG(n) => match (if self.message.is_staticcall() {
+                }),
+            PC => self
+                .stack
+                .push(self.code.current_op_offset())
+                .map_err(EVMError::StackError),
+            MSIZE => self
+                .stack
+                .push(self.memory.size())
+                .map_err(EVMError::StackError),
+            GAS => self
+                .stack
+                .push(self.gas.remaining())
+                .map_err(EVMError::StackError),
+            JUMPDEST => Ok(()),
+            TLOAD => self
+                .stack
+                .pop()
+                .map(|key| self.state().transient_load(self.message.target(), &key))
+                .and_then(|v| self.stack.push(v))
+                .map_err(EVMError::StackError),
+            TSTORE => (if self.message.is_staticcall() {
+                Err(EVMError::StateModificationDisallowed)
+            } else {
+                Ok(())
+            })
+            .and_then(|_| self.stack.pop().map_err(EVMError::StackError))
+            .and_then(|key| {
+                self.stack
+                    .pop()
+                    .map_err(EVMError::StackError)
+                    .map(|value| (key, value))
+            })
+            .and_then(|(key, value)| {
+                let message = self.message;
+                self.state_mut()
+                    .map(|state| state.transient_store(message.target(), key, value))
+            }),
+            MCOPY => self
+                .stack
+                .pop()
+                .and_then(|dest_offset| self.stack.pop().map(|offset| (dest_offset, offset)))
+                .and_then(|(dest_offset, offset)| {
+                    self.stack.pop().map(|size| (dest_offset, offset, size))
+                })
+                .map_err(EVMError::StackError)
+                .and_then(|(dest_offset, offset, size)| {
+                    let dest_offset = dest_offset.saturating_to::<usize>();
+                    let offset = offset.saturating_to::<usize>();
+                    let size = size.saturating_to::<usize>();
+
+                    // Both regions are accessed, the furthest one expands memory.
+                    self.gas.charge(gas::copy_cost(size))?;
+                    self.gas.charge_memory_expansion(
+                        self.memory.size(),
+                        dest_offset.max(offset),
+                        size,
+                    )?;
+                    Ok(self.memory.copy_within(dest_offset, offset, size)?)
+                }),
+            PUSH(_, n) => self.stack.push(n).map_err(EVMError::StackError),
+            DUP(n) => self.stack.dup(n).map_err(EVMError::StackError),
+            SWAP(n) => self.stack.swap(n).map_err(EVMError::StackError),
+            DUPN(n) => self
+                .stack
+                .dup(usize::from(n) + 1)
+                .map_err(EVMError::StackError),
+            SWAPN(n) => self
+                .stack
+                .swap(usize::from(n) + 1)
+                .map_err(EVMError::StackError),
+            EXCHANGE(nm) => self
+                .stack
+                .exchange(usize::from(nm >> 4) + 1, usize::from(nm & 0x0F) + 1)
+                .map_err(EVMError::StackError),
+            LOG(n) => (if self.message.is_staticcall() {
                 Err(EVMError::StateModificationDisallowed)
             } else {
                 Ok(())
@@ -1058,8 +704,13 @@ where
                     .map_err(EVMError::StackError)
             })
             .and_then(|(offset, size)| {
+                self.gas
+                    .charge_memory_expansion(self.memory.size(), offset, size)
+                    .and_then(|_| self.gas.charge(gas::log_cost(n, size)))
+                    .map_err(EVMError::GasError)?;
+
                 let address = self.message.target().clone();
-                let data = self.memory.load(offset, size).to_vec();
+                let data = self.memory.load(offset, size)?.to_vec();
 
                 let res = match n {
                     0 => Ok(Log::log0(address, data)),
@@ -1090,15 +741,8 @@ where
                 let log = res.map_err(EVMError::StackError)?;
                 self.logs.push(log);
                 Ok(())
-            }) {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CREATE => match (if self.message.is_staticcall() {
+            }),
+            CREATE => (if self.message.is_staticcall() {
                 Err(EVMError::StateModificationDisallowed)
             } else {
                 Ok(())
@@ -1110,18 +754,23 @@ where
                 let size = size.saturating_to();
 
                 // Instanciate a new EVM.
-                let nonce = self.env.state().get_account(self.message.target()).nonce();
-                let bytes = self.memory.load(offset, size);
+                let nonce = *self.state().get_account(self.message.target()).nonce();
+                let bytes = self.memory.load(offset, size)?;
                 let data = Calldata::new(&bytes);
-                let message = Message::create(
-                    self.message.target(),
-                    &nonce,
-                    self.message.gas(),
-                    &value,
-                    &data,
-                );
+                let gas = self.gas.forward(self.gas.remaining(), self.env.spec())?;
+
+                // Beyond the maximum depth the creation fails without executing.
+                let depth = self.env.depth();
+                if depth >= CALL_DEPTH_LIMIT {
+                    self.gas.reclaim(&gas, 0);
+                    self.last_inner_call = None;
+                    return Ok(U256::ZERO);
+                }
+
+                let message = Message::create(self.message.target(), &nonce, &gas, &value, &data);
                 let target = message.target().clone();
-                let result = Message::process(message, self.env);
+                let result = self.env.process(message);
+                self.gas.reclaim(&gas, result.gas_used());
 
                 let res = match &result {
                     // Call succeded.
@@ -1150,254 +799,45 @@ where
 
                 Ok(res)
             })
-            .and_then(|res| self.stack.push(res).map_err(EVMError::StackError))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            CALL => match (if self.message.is_staticcall() {
-                Err(EVMError::StateModificationDisallowed)
-            } else {
-                Ok(())
-            })
-            .and_then(|_| {
-                let args = {
-                    Ok((
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                    ))
-                };
-                let (gas, address, value, args_offset, args_size, ret_offset, ret_size) =
-                    args.map_err(EVMError::StackError)?;
-                let target = address.into();
-                let args_offset = args_offset.saturating_to();
-                let args_size = args_size.saturating_to();
-                let ret_offset = ret_offset.saturating_to();
-                let ret_size = ret_size.saturating_to();
-
-                // Instanciate a new EVM.
-                let bytes = self.memory.load(args_offset, args_size);
-                let data = Calldata::new(&bytes);
-                let message = Message::call(self.message.target(), &target, &gas, &value, &data);
-                let result = Message::process(message, self.env);
-
-                let status = match &result {
-                    // Call succeded.
-                    EVMResult {
-                        return_data,
-                        logs,
-                        status: true,
-                        ..
-                    } => {
-                        // Copy the returned data to memory.
-                        self.memory.store(ret_offset, ret_size, return_data);
-                        // Add result logs to logs.
-                        self.logs.append(
-                            &mut logs
-                                .into_iter()
-                                .map(|l| l.clone().into())
-                                .collect::<Vec<Log>>(),
-                        );
-                        // Continue.
-                        true
-                    }
-                    // Call failed.
-                    EVMResult {
-                        return_data,
-                        status: false,
-                        ..
-                    } => {
-                        // Copy returned revert data into memory.
-                        self.memory.store(ret_offset, ret_size, return_data);
-                        // Revert.
-                        false
-                    }
-                };
-
-                // Store call.
-                self.last_inner_call = Some(result.clone());
-
-                Ok(status)
-            })
-            .and_then(|status| self.stack.push(status as u8).map_err(EVMError::StackError))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            RETURN => match self
+            .and_then(|res| self.stack.push(res).map_err(EVMError::StackError)),
+            CALL => self.call(CallKind::Call),
+            RETURN => self
                 .stack
                 .pop()
                 .and_then(|offset| self.stack.pop().map(|size| (offset, size)))
                 .map_err(EVMError::StackError)
-            {
-                Ok((offset, size)) => {
-                    self.result = Some(Ok((offset, size)));
-                    // Stop.
-                    None
-                }
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            DELEGATECALL => match (if self.message.is_staticcall() {
-                Err(EVMError::StateModificationDisallowed)
-            } else {
-                Ok(())
-            })
-            .and_then(|_| {
-                let args = {
-                    Ok((
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                        self.stack.pop()?,
-                    ))
-                };
-                let (gas, address, args_offset, args_size, ret_offset, ret_size) =
-                    args.map_err(EVMError::StackError)?;
-                let target = address.into();
-                let args_offset = args_offset.saturating_to();
-                let args_size = args_size.saturating_to();
-                let ret_offset = ret_offset.saturating_to();
-                let ret_size = ret_size.saturating_to();
-
-                // Instanciate a new EVM.
-                let bytes = self.memory.load(args_offset, args_size);
-                let data = Calldata::new(&bytes);
-                let message = Message::delegatecall(&self.message, &target, &gas, &data);
-                let result = Message::process(message, self.env);
-
-                let status = match &result {
-                    // Call succeded.
-                    EVMResult {
-                        return_data,
-                        logs,
-                        status: true,
-                        ..
-                    } => {
-                        // Copy the returned data to memory.
-                        self.memory.store(ret_offset, ret_size, return_data);
-                        // Add result logs to logs.
-                        self.logs.append(
-                            &mut logs
-                                .into_iter()
-                                .map(|l| l.clone().into())
-                                .collect::<Vec<Log>>(),
-                        );
-                        // Continue.
-                        true
-                    }
-                    // Call failed.
-                    EVMResult {
-                        return_data,
-                        status: false,
-                        ..
-                    } => {
-                        // Copy returned revert data into memory.
-                        self.memory.store(ret_offset, ret_size, return_data);
-                        // Revert.
-                        false
-                    }
-                };
-
-                // Store call.
-                self.last_inner_call = Some(result.clone());
-
-                Ok(status)
-            })
-            .and_then(|status| self.stack.push(status as u8).map_err(EVMError::StackError))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            STATICCALL => match Ok(())
-                .and_then(|_| {
-                    let args = {
-                        Ok((
-                            self.stack.pop()?,
-                            self.stack.pop()?,
-                            self.stack.pop()?,
-                            self.stack.pop()?,
-                            self.stack.pop()?,
-                            self.stack.pop()?,
-                        ))
-                    };
-                    let (gas, address, args_offset, args_size, ret_offset, ret_size) =
-                        args.map_err(EVMError::StackError)?;
-                    let target = address.into();
-                    let args_offset = args_offset.saturating_to();
-                    let args_size = args_size.saturating_to();
-                    let ret_offset = ret_offset.saturating_to();
-                    let ret_size = ret_size.saturating_to();
-
-                    // Instanciate a new EVM.
-                    let bytes = self.memory.load(args_offset, args_size);
-                    let data = Calldata::new(&bytes);
-                    let message = Message::staticcall(self.message.target(), &target, &gas, &data);
-                    let result = Message::process(message, self.env);
-
-                    // Copy the returned data to memory.
-                    self.memory
-                        .store(ret_offset, ret_size, result.return_data());
-
-                    // Store call.
-                    self.last_inner_call = Some(result.clone());
-
-                    Ok(result.status())
+                .and_then(|(offset, size)| {
+                    // The returned region is read from memory.
+                    self.gas.charge_memory_expansion(
+                        self.memory.size(),
+                        offset.saturating_to(),
+                        size.saturating_to(),
+                    )?;
+                    Memory::end(offset.saturating_to(), size.saturating_to())?;
+                    Ok((offset, size))
                 })
-                .and_then(|status| self.stack.push(status as u8).map_err(EVMError::StackError))
-            {
-                Ok(_) => Some(()),
-                Err(e) => {
-                    self.result = Some(Err(e));
+                .map(|(offset, size)| {
+                    self.result = Some(Ok((offset, size)));
+                    self.halt_reason = Some(HaltReason::Return);
                     // Stop.
-                    None
-                }
-            },
-            REVERT => match self
+                }),
+            DELEGATECALL => self.call(CallKind::Delegatecall),
+            STATICCALL => self.call(CallKind::Staticcall),
+            REVERT => self
                 .stack
                 .pop()
                 .and_then(|offset| self.stack.pop().map(|size| (offset, size)))
                 .map_err(EVMError::StackError)
-            {
-                Ok((offset, size)) => {
-                    self.result = Some(Err(EVMError::Revert(offset, size)));
-                    // Stop.
-                    None
-                }
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
-            INVALID => {
-                self.result = Some(Err(EVMError::Revert(U256::ZERO, U256::ZERO)));
-                // Stop.
-                None
-            }
-            SELFDESTRUCT => match (if self.message.is_staticcall() {
+                .and_then(|(offset, size)| {
+                    // The reverted region is read from memory.
+                    Memory::end(offset.saturating_to(), size.saturating_to())?;
+                    Err(EVMError::Revert(offset, size))
+                }),
+            INVALID => Err(EVMError::InvalidOpcode),
+            // The stepping stops after a breakpoint, a full execution ignores it.
+            BREAKPOINT if self.env.debug() => Ok(()),
+            BREAKPOINT => Err(EVMError::InvalidOpcode),
+            SELFDESTRUCT => (if self.message.is_staticcall() {
                 Err(EVMError::StateModificationDisallowed)
             } else {
                 Ok(())
@@ -1410,30 +850,1971 @@ where
             })
             .and_then(|addr| {
                 let amount = self
-                    .env
                     .state()
                     .get_account(self.message.target())
                     .balance()
                     .clone();
+                let empty = matches!(self.state().get_account(&addr), Account::Empty);
+                let warm = self.is_warm(&addr);
+                self.gas.charge(gas::selfdestruct_cost(
+                    empty,
+                    warm,
+                    &amount,
+                    self.env.spec(),
+                ))?;
 
-                let state = self.env.state_mut();
+                let message = self.message;
+                let state = self.state_mut()?;
 
                 state
-                    .send_eth(self.message.target(), &addr, &amount)
-                    .and_then(|_| state.delete_account(self.message.target()))
+                    .send_eth(message.target(), &addr, &amount)
+                    .and_then(|_| state.delete_account(message.target()))
                     .map_err(EVMError::StateError)
-            }) {
-                Ok(_) => {
-                    self.result = Some(Ok((U256::ZERO, U256::ZERO)));
-                    // Stop.
-                    None
-                }
-                Err(e) => {
-                    self.result = Some(Err(e));
-                    // Stop.
-                    None
-                }
-            },
+            })
+            .map(|_| {
+                self.result = Some(Ok((U256::ZERO, U256::ZERO)));
+                // Stop.
+            }),
         }
     }
+
+    /// Whether the account at `address` is warm, only the coinbase is since EIP-3651.
+    fn is_warm(&self, address: &Address) -> bool {
+        SpecConfig::for_fork(self.env.spec()).warm_coinbase && address == self.env.coinbase()
+    }
+
+    /// Charge the access of `opcode` to the account at `address`.
+    fn charge_account_access(
+        &mut self,
+        opcode: &Opcode,
+        address: &Address,
+    ) -> std::result::Result<(), EVMError> {
+        let cost = gas::account_access_cost(opcode, self.is_warm(address), self.env.spec());
+        Ok(self.gas.charge(cost)?)
+    }
+
+    /// Execute a CALL, a DELEGATECALL or a STATICCALL and push whether it succeeded.
+    fn call(&mut self, kind: CallKind) -> std::result::Result<(), EVMError> {
+        // Only a STATICCALL can be made from a staticcall.
+        if kind != CallKind::Staticcall && self.message.is_staticcall() {
+            return Err(EVMError::StateModificationDisallowed);
+        }
+
+        let gas = self.stack.pop()?;
+        let target = Address::from(self.stack.pop()?);
+        let value = match kind {
+            CallKind::Call => self.stack.pop()?,
+            CallKind::Delegatecall | CallKind::Staticcall => U256::ZERO,
+        };
+        let args_offset = self.stack.pop()?.saturating_to();
+        let args_size = self.stack.pop()?.saturating_to();
+        let ret_offset = self.stack.pop()?.saturating_to();
+        let ret_size = self.stack.pop()?.saturating_to();
+
+        // Calling an empty address creates its account, since EIP-161 only when sending value.
+        // The precompiles always exist.
+        if kind == CallKind::Call
+            && (value != U256::ZERO || !SpecConfig::for_fork(self.env.spec()).state_clearing)
+            && !target.is_precompile()
+            && matches!(self.state().get_account(&target), Account::Empty)
+        {
+            self.gas.charge(gas::G_NEW_ACCOUNT)?;
+        }
+        let gas = self.gas.forward(gas, self.env.spec())?;
+
+        // Beyond the maximum depth the call fails without executing, its gas is returned.
+        let depth = self.env.depth();
+        if depth >= CALL_DEPTH_LIMIT {
+            self.gas.reclaim(&gas, 0);
+            self.last_inner_call = None;
+            return self.stack.push(0).map_err(EVMError::StackError);
+        }
+
+        // Instanciate a new EVM, the arguments are read from memory without a copy.
+        let result = {
+            let bytes = self.memory.slice(args_offset, args_size)?;
+            let data = Calldata::new(&bytes);
+            let message = match kind {
+                CallKind::Call => {
+                    Message::call(self.message.target(), &target, &gas, &value, &data)
+                }
+                CallKind::Delegatecall => Message::delegatecall(self.message, &target, &gas, &data),
+                CallKind::Staticcall => {
+                    Message::staticcall(self.message.target(), &target, &gas, &data)
+                }
+            };
+            self.env.process(message)
+        };
+        // The gas unused by the child is returned, only a success keeps its refunds.
+        self.gas.reclaim(&gas, result.gas_used());
+        self.gas.add_refund(result.gas_refund());
+
+        // Copy the returned data, or the revert data, to memory.
+        self.memory
+            .store(ret_offset, ret_size, result.return_data())?;
+        if result.status() {
+            // Add result logs to logs.
+            self.logs
+                .extend(result.logs().iter().map(|l| l.clone().into()));
+        }
+
+        // Store call.
+        let status = result.status();
+        self.last_inner_call = Some(result);
+
+        self.stack.push(status as u8).map_err(EVMError::StackError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestResult;
+    use ruint::uint;
+    use std::collections::{BTreeMap, HashMap};
+
+    fn contract() -> Address {
+        uint!(0x000000000000000000000000000000000000dead_U160).into()
+    }
+
+    /// Call `f` with an environment where `contract()` has the code `bytecode`.
+    fn with_env<R>(
+        bytecode: &[u8],
+        accounts: Vec<(Address, Account)>,
+        f: impl FnOnce(&mut Environment) -> R,
+    ) -> R {
+        let caller = Address::default();
+        let zero = U256::ZERO;
+        let mut accounts = HashMap::from_iter(accounts);
+        // The contract balance can be given with the accounts.
+        let balance = accounts.get(&contract()).map(|a| *a.balance());
+        accounts.insert(contract(), Account::new(balance, Some(bytecode.into())));
+        let mut env = Environment::new(
+            &caller,
+            &[],
+            &caller,
+            &zero,
+            &zero,
+            &zero,
+            &zero,
+            &zero,
+            &zero,
+            State::new(accounts),
+            &zero,
+        );
+        f(&mut env)
+    }
+
+    /// `PUSH20 address`.
+    fn push_address(address: &Address) -> Vec<u8> {
+        let word = <U256 as From<&Address>>::from(address).to_be_bytes::<0x20>();
+        [&[0x73], &word[0x0C..]].concat()
+    }
+
+    fn transaction() -> Transaction {
+        Transaction::new(
+            U256::ZERO,
+            U256::MAX,
+            Address::default(),
+            Some(contract()),
+            U256::ZERO,
+            vec![],
+        )
+    }
+
+    fn run(bytecode: &[u8]) -> TestResult {
+        with_env(bytecode, vec![], |env| transaction().process(env))
+    }
+
+    fn execute(bytecode: &[u8], gas: U256) -> EVMResult {
+        with_env(bytecode, vec![], |env| {
+            let caller = Address::default();
+            let target = contract();
+            let data = Calldata::new(&[]);
+            let message = Message::call(&caller, &target, &gas, &U256::ZERO, &data);
+            Message::process(message, env)
+        })
+    }
+
+    #[test]
+    fn pc_should_push_the_offset_of_the_current_opcode() {
+        // PC as the first opcode.
+        assert_eq!(&[U256::ZERO], run(&[0x58]).stack.as_ref());
+        // PC PC
+        assert_eq!(
+            &[U256::from(1), U256::ZERO],
+            run(&[0x58, 0x58]).stack.as_ref()
+        );
+        // JUMPDEST PC
+        assert_eq!(&[U256::from(1)], run(&[0x5B, 0x58]).stack.as_ref());
+        // PUSH2 0x0000 PC
+        assert_eq!(
+            &[U256::from(3), U256::ZERO],
+            run(&[0x61, 0x00, 0x00, 0x58]).stack.as_ref()
+        );
+        // PUSH1 4 JUMP INVALID JUMPDEST PC
+        assert_eq!(
+            &[U256::from(5)],
+            run(&[0x60, 0x04, 0x56, 0xFE, 0x5B, 0x58]).stack.as_ref()
+        );
+    }
+
+    #[test]
+    fn difficulty_should_push_prevrandao_since_the_merge() {
+        let difficulty = |spec: Spec| {
+            with_env(&[0x44], vec![], |env| {
+                env.set_spec(spec);
+                env.set_prevrandao(Some(U256::from(0x2A)));
+                transaction().process(env).stack[0]
+            })
+        };
+        assert_eq!(U256::ZERO, difficulty(Spec::London));
+        assert_eq!(U256::from(0x2A), difficulty(Spec::Paris));
+    }
+
+    #[test]
+    fn blobbasefee_should_push_the_blob_base_fee_since_cancun() {
+        let blob_base_fee = |spec: Spec| {
+            with_env(&[0x4A], vec![], |env| {
+                env.set_spec(spec);
+                env.set_blob_base_fee(U256::from(0x2A));
+                transaction().process(env)
+            })
+        };
+        let result = blob_base_fee(Spec::Cancun);
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::from(0x2A)]);
+        assert!(!blob_base_fee(Spec::Shanghai).success);
+    }
+
+    #[test]
+    fn blobhash_should_push_the_versioned_hash_at_the_index() {
+        // PUSH1 2 BLOBHASH PUSH1 1 BLOBHASH PUSH1 0 BLOBHASH
+        let code = hex::decode("600249600149600049").unwrap();
+        let blob_hash = |spec: Spec| {
+            with_env(&code, vec![], |env| {
+                env.set_spec(spec);
+                env.set_blob_versioned_hashes(vec![U256::from(0x01AA), U256::from(0x01BB)]);
+                transaction().process(env)
+            })
+        };
+        let result = blob_hash(Spec::Cancun);
+        assert!(result.success);
+        assert_eq!(
+            result.stack.as_ref(),
+            &[U256::from(0x01AA), U256::from(0x01BB), U256::ZERO]
+        );
+        assert!(!blob_hash(Spec::Shanghai).success);
+    }
+
+    #[test]
+    fn dupn_should_reach_beyond_the_16th_item_in_eof_code() {
+        // PUSH1 1 .. PUSH1 21
+        let mut code = (1..=21).flat_map(|i| [0x60, i]).collect::<Vec<_>>();
+        // DUPN 20 SWAPN 0 EXCHANGE 0x01 STOP
+        code.extend([0xE6, 0x14, 0xE7, 0x00, 0xE8, 0x01, 0x00]);
+        let execute = |code: &[u8], spec: Spec| {
+            with_env(code, vec![], |env| {
+                env.set_spec(spec);
+                transaction().process(env)
+            })
+        };
+        let container = eof::EofContainer::encode(&code);
+
+        let result = execute(&container, Spec::Prague);
+        assert!(result.success);
+        assert_eq!(22, result.stack.len());
+        assert_eq!(&[21, 19, 20, 1, 18].map(U256::from), &result.stack[..5]);
+        assert!(!execute(&container, Spec::Cancun).success);
+
+        // Legacy code sees INVALID.
+        let result = execute(&code, Spec::Prague);
+        assert!(!result.success);
+        assert_eq!(21, result.stack.len());
+    }
+
+    #[test]
+    fn signextend_should_be_a_noop_from_32_bytes() {
+        // PUSH1 0xFF PUSH1 0x20 SIGNEXTEND
+        let result = run(&hex::decode("60FF60200B").unwrap());
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::from(0xFF)]);
+
+        // PUSH1 0xFF PUSH32 0xFF..FF SIGNEXTEND
+        let mut code = hex::decode("60FF7F").unwrap();
+        code.extend([0xFF; 0x20]);
+        code.push(0x0B);
+        let result = run(&code);
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::from(0xFF)]);
+    }
+
+    #[test]
+    fn signextend_should_extend_from_the_last_byte() {
+        // PUSH1 0xFF PUSH1 0x00 SIGNEXTEND
+        let result = run(&hex::decode("60FF60000B").unwrap());
+        assert_eq!(result.stack.as_ref(), &[U256::MAX]);
+        // PUSH32 0x80..00 PUSH1 0x1F SIGNEXTEND
+        let mut code = vec![0x7F, 0x80];
+        code.extend([0x00; 0x1F]);
+        code.extend([0x60, 0x1F, 0x0B]);
+        let result = run(&code);
+        assert_eq!(
+            result.stack.as_ref(),
+            &[Int256::max_negative_value().to_raw_u256()]
+        );
+    }
+
+    #[test]
+    fn mstore8_should_store_the_lowest_byte() {
+        // PUSH2 0x1234 PUSH1 0 MSTORE8 PUSH1 0 MLOAD PUSH1 0xF8 SHR
+        let result = run(&hex::decode("61123460005360005160F81C").unwrap());
+        assert_eq!(&[U256::from(0x34)], result.stack.as_ref());
+    }
+
+    #[test]
+    fn byte_should_read_from_the_most_significant_byte() {
+        let byte = |i: U256| {
+            // PUSH32 0x0102..20 PUSH32 i BYTE
+            let mut code = vec![0x7F];
+            code.extend(1..=0x20_u8);
+            code.push(0x7F);
+            code.extend(i.to_be_bytes::<0x20>());
+            code.push(0x1A);
+            run(&code).stack[0]
+        };
+        assert_eq!(U256::from(0x01), byte(U256::ZERO));
+        assert_eq!(U256::from(0x20), byte(U256::from(31)));
+        assert_eq!(U256::ZERO, byte(U256::from(32)));
+        assert_eq!(U256::ZERO, byte(U256::MAX));
+    }
+
+    #[test]
+    fn msize_should_be_word_aligned() {
+        // PUSH1 0xFF PUSH1 0 MSTORE8 MSIZE
+        let result = run(&hex::decode("60FF60005359").unwrap());
+        assert!(result.success);
+        assert_eq!(&[U256::from(0x20)], result.stack.as_ref());
+    }
+
+    #[test]
+    fn mcopy_should_copy_overlapping_memory() {
+        // PUSH32 0x0102..20 PUSH1 0 MSTORE
+        let mut code = vec![0x7F];
+        code.extend(1..=0x20);
+        code.extend(hex::decode("600052").unwrap());
+        // PUSH1 0x20 PUSH1 0 PUSH1 1 MCOPY PUSH1 0 MLOAD PUSH1 0x20 MLOAD MSIZE
+        code.extend(hex::decode("602060006001 5E 600051 602051 59".replace(' ', "")).unwrap());
+        let result = run(&code);
+
+        let mut word = [0x00; 0x20];
+        word[0] = 0x01;
+        (1..0x20).for_each(|i| word[i] = i as u8);
+        assert!(result.success);
+        assert_eq!(
+            result.stack.as_ref(),
+            &[
+                U256::from(0x40),
+                U256::from(0x20) << 248,
+                U256::from_be_bytes(word)
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_reads_should_fail_beyond_the_maximum_memory_size() {
+        let max = [0x7F].into_iter().chain([0xFF; 0x20]).collect::<Vec<_>>();
+        // PUSH32 0xFF..FF MLOAD
+        // PUSH1 1 PUSH32 0xFF..FF SHA3
+        // PUSH1 1 PUSH32 0xFF..FF RETURN
+        // PUSH1 1 PUSH32 0xFF..FF REVERT
+        for code in [
+            [&max[..], &[0x51]].concat(),
+            [&[0x60, 0x01], &max[..], &[0x20]].concat(),
+            [&[0x60, 0x01], &max[..], &[0xF3]].concat(),
+            [&[0x60, 0x01], &max[..], &[0xFD]].concat(),
+        ] {
+            let result = run(&code);
+            assert!(!result.success);
+            assert_eq!(HaltReason::Error, result.halt_reason);
+        }
+    }
+
+    #[test]
+    fn mcopy_should_fail_beyond_the_maximum_memory_size() {
+        // PUSH1 1 PUSH32 0xFF..FF PUSH1 0 MCOPY
+        let mut code = vec![0x60, 0x01, 0x7F];
+        code.extend([0xFF; 0x20]);
+        code.extend([0x60, 0x00, 0x5E]);
+        let result = run(&code);
+        assert!(!result.success);
+        assert_eq!(HaltReason::Error, result.halt_reason);
+    }
+
+    #[test]
+    fn mcopy_should_consume_gas() {
+        // PUSH1 0x21 PUSH1 0 PUSH1 0x20
+        let setup = hex::decode("602160006020").unwrap();
+        let with_mcopy = [&setup[..], &[0x5E]].concat();
+        let gas = U256::from(1_000_000);
+
+        let reference = execute(&setup, gas);
+        let result = execute(&with_mcopy, gas);
+        assert!(result.status());
+        // 3 + 3 * 2 words copied + 9 for 3 words of memory.
+        let mcopy_cost = 18;
+        assert_eq!(reference.gas_used() + mcopy_cost, result.gas_used());
+
+        let result = execute(
+            &with_mcopy,
+            U256::from(reference.gas_used() + mcopy_cost - 1),
+        );
+        assert!(!result.status());
+    }
+
+    #[test]
+    fn balance_of_the_coinbase_should_be_warm_since_shanghai() {
+        let gas = U256::from(1_000_000);
+        // The coinbase of the test environment is the zero address.
+        let coinbase = [&push_address(&Address::default())[..], &[0x31]].concat();
+        let other = [&push_address(&other())[..], &[0x31]].concat();
+
+        let balance_gas = |code: &[u8], spec| {
+            with_env(code, vec![], |env| {
+                env.set_spec(spec);
+                let caller = Address::default();
+                let target = contract();
+                let data = Calldata::new(&[]);
+                let message = Message::call(&caller, &target, &gas, &U256::ZERO, &data);
+                Message::process(message, env).gas_used()
+            })
+        };
+        assert_eq!(3 + 100, balance_gas(&coinbase, Spec::Shanghai));
+        assert_eq!(3 + 2600, balance_gas(&other, Spec::Shanghai));
+        assert_eq!(3 + 2600, balance_gas(&coinbase, Spec::Paris));
+    }
+
+    #[test]
+    fn log_should_consume_gas() {
+        // PUSH1 4 PUSH1 3 PUSH1 2 PUSH1 1 PUSH1 0x40 PUSH1 0
+        let setup = hex::decode("600460036002600160406000").unwrap();
+        let with_log = [&setup[..], &[0xA4]].concat();
+        let gas = U256::from(1_000_000);
+
+        let reference = execute(&setup, gas);
+        let result = execute(&with_log, gas);
+        assert!(result.status());
+        assert_eq!(1, result.logs().len());
+        // 375 + 4 * 375 topics + 8 * 64 bytes + 6 for 2 words of memory.
+        let log_cost = 2393;
+        assert_eq!(reference.gas_used() + log_cost, result.gas_used());
+
+        // Just enough gas.
+        let result = execute(&with_log, U256::from(reference.gas_used() + log_cost));
+        assert!(result.status());
+        // Not enough gas.
+        let result = execute(&with_log, U256::from(reference.gas_used() + log_cost - 1));
+        assert!(!result.status());
+        assert!(result.logs().is_empty());
+    }
+
+    #[test]
+    fn exp_should_run_out_of_gas_before_computing_the_power() {
+        // PUSH32 0xFF..FF PUSH1 3 EXP
+        let mut code = vec![0x7F];
+        code.extend([0xFF; 0x20]);
+        code.extend([0x60, 0x03, 0x0A]);
+        // 2 PUSH and 10 + 50 * 32 for the EXP.
+        let cost = 3 + 3 + 10 + 50 * 0x20;
+
+        let result = execute(&code, U256::from(cost));
+        assert!(result.status());
+        assert_eq!(cost, result.gas_used());
+
+        let result = execute(&code, U256::from(cost - 1));
+        assert!(!result.status());
+    }
+
+    /// `PUSH32 b PUSH32 a opcode`.
+    fn compare(opcode: u8, a: U256, b: U256) -> U256 {
+        let mut code = vec![0x7F];
+        code.extend(b.to_be_bytes::<0x20>());
+        code.push(0x7F);
+        code.extend(a.to_be_bytes::<0x20>());
+        code.push(opcode);
+        run(&code).stack[0]
+    }
+
+    #[test]
+    fn slt_and_sgt_should_compare_signed_boundaries() {
+        let (slt, sgt) = (0x12, 0x13);
+        let min_negative = Int256::max_negative_value().to_raw_u256();
+        let negative_one = U256::MAX;
+        let one = U256::from(1);
+        let max_positive = U256::MAX >> 1;
+
+        for (a, b) in [
+            (min_negative, negative_one),
+            (negative_one, U256::ZERO),
+            (U256::ZERO, one),
+            (one, max_positive),
+            (min_negative, max_positive),
+        ] {
+            assert_eq!(one, compare(slt, a, b));
+            assert_eq!(U256::ZERO, compare(slt, b, a));
+            assert_eq!(one, compare(sgt, b, a));
+            assert_eq!(U256::ZERO, compare(sgt, a, b));
+        }
+        assert_eq!(U256::ZERO, compare(slt, max_positive, min_negative));
+        assert_eq!(U256::ZERO, compare(slt, min_negative, min_negative));
+        assert_eq!(U256::ZERO, compare(sgt, min_negative, min_negative));
+    }
+
+    #[test]
+    fn call_should_execute_custom_precompile() {
+        let precompile: Address = uint!(0x0000000000000000000000000000000000000100_U160).into();
+        // PUSH1 0xAB PUSH1 0 MSTORE8
+        let mut code = hex::decode("60AB600053").unwrap();
+        // PUSH1 2 PUSH1 0x20 PUSH1 1 PUSH1 0 PUSH1 0 PUSH20 precompile GAS CALL
+        code.extend(hex::decode("60026020600160006000").unwrap());
+        code.extend(push_address(&precompile));
+        code.extend([0x5A, 0xF1]);
+        // RETURNDATASIZE PUSH1 0x20 MLOAD
+        code.extend(hex::decode("3D602051").unwrap());
+
+        let result = with_env(&code, vec![], |env| {
+            let echo_twice: Precompile = std::rc::Rc::new(|input| (true, input.repeat(2)));
+            env.set_precompiles(HashMap::from([(precompile.clone(), echo_twice)]));
+            transaction().process(env)
+        });
+        assert!(result.success);
+        assert_eq!(
+            result.stack.as_ref(),
+            &[U256::from(0xABAB) << 240, U256::from(2), U256::from(1)]
+        );
+    }
+
+    #[test]
+    fn call_should_fail_with_failing_custom_precompile() {
+        let precompile: Address = uint!(0x0000000000000000000000000000000000000100_U160).into();
+        // PUSH1 0 DUP1 DUP1 DUP1 DUP1 PUSH20 precompile GAS CALL RETURNDATASIZE
+        let mut code = hex::decode("600080808080").unwrap();
+        code.extend(push_address(&precompile));
+        code.extend([0x5A, 0xF1, 0x3D]);
+
+        let result = with_env(&code, vec![], |env| {
+            let fail: Precompile = std::rc::Rc::new(|_| (false, vec![0x01]));
+            env.set_precompiles(HashMap::from([(precompile.clone(), fail)]));
+            transaction().process(env)
+        });
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::from(1), U256::ZERO]);
+    }
+
+    #[test]
+    fn return_data_opcodes_should_read_the_precompile_output() {
+        let identity: Address = uint!(0x0000000000000000000000000000000000000004_U160).into();
+        // PUSH1 0x2A PUSH1 0 MSTORE
+        let mut code = hex::decode("602A600052").unwrap();
+        // PUSH1 0 PUSH1 0 PUSH1 0x20 PUSH1 0 PUSH1 0 PUSH20 identity GAS CALL
+        code.extend(hex::decode("60006000602060006000").unwrap());
+        code.extend(push_address(&identity));
+        code.extend([0x5A, 0xF1]);
+        // RETURNDATASIZE PUSH1 0x20 PUSH1 0 PUSH1 0x20 RETURNDATACOPY PUSH1 0x20 MLOAD
+        code.extend(hex::decode("3D6020600060203E602051").unwrap());
+
+        let result = with_env(&code, vec![], |env| {
+            let precompile: Precompile = std::rc::Rc::new(|input| (true, input.to_vec()));
+            env.set_precompiles(HashMap::from([(identity.clone(), precompile)]));
+            transaction().process(env)
+        });
+        assert!(result.success);
+        assert_eq!(
+            result.stack.as_ref(),
+            &[U256::from(0x2A), U256::from(0x20), U256::from(1)]
+        );
+    }
+
+    fn other() -> Address {
+        uint!(0x000000000000000000000000000000000000beef_U160).into()
+    }
+
+    /// `CALL`-like opcode to `address` with no arguments and a 32 bytes return at 0.
+    fn call_other(opcode: u8, address: &Address) -> Vec<u8> {
+        // PUSH1 0x20 PUSH1 0 PUSH1 0 PUSH1 0
+        let mut code = hex::decode("6020600060006000").unwrap();
+        if opcode == 0xF1 {
+            // PUSH1 0 (value)
+            code.extend([0x60, 0x00]);
+        }
+        code.extend(push_address(address));
+        // GAS opcode
+        code.extend([0x5A, opcode]);
+        code
+    }
+
+    #[test]
+    fn transaction_should_return_the_returned_memory() {
+        // PUSH2 0x1234 PUSH1 0 MSTORE PUSH1 0x20 PUSH1 0 RETURN
+        let result = run(&hex::decode("61123460005260206000F3").unwrap());
+        assert!(result.success);
+        assert!(result.stack.is_empty());
+        assert_eq!(
+            U256::from(0x1234).to_be_bytes::<0x20>().as_slice(),
+            result.return_data.as_ref()
+        );
+
+        // PUSH2 0x1234 PUSH1 0 MSTORE PUSH1 2 PUSH1 0x1E RETURN
+        let result = run(&hex::decode("6112346000526002601EF3").unwrap());
+        assert_eq!(&[0x12, 0x34], result.return_data.as_ref());
+    }
+
+    #[test]
+    fn empty_code_should_stop_successfully() {
+        let result = run(&[]);
+        assert!(result.success);
+        assert!(result.stack.is_empty());
+        assert!(result.return_data.is_empty());
+
+        // CALL an account without code.
+        let result = run(&call_other(0xF1, &other()));
+        assert!(result.success);
+        assert_eq!(&[U256::from(1)], result.stack.as_ref());
+    }
+
+    #[test]
+    fn tstore_should_use_the_delegatecall_storage_context() {
+        // ADDRESS PUSH1 0 TSTORE
+        let other_code = hex::decode("3060005D").unwrap();
+        let mut code = call_other(0xF4, &other());
+        // PUSH1 0 TLOAD
+        code.extend(hex::decode("60005C").unwrap());
+
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        let result = with_env(&code, accounts, |env| transaction().process(env));
+        assert!(result.success);
+        assert_eq!(
+            result.stack.as_ref(),
+            &[<U256 as From<&Address>>::from(&contract()), U256::from(1)]
+        );
+    }
+
+    #[test]
+    fn tstore_should_use_a_namespace_per_called_contract() {
+        // Increment the counter at slot 0 and return it.
+        // PUSH1 0 TLOAD PUSH1 1 ADD DUP1 PUSH1 0 TSTORE PUSH1 0 MSTORE PUSH1 0x20 PUSH1 0 RETURN
+        let other_code = hex::decode("60005C6001018060005D60005260206000F3").unwrap();
+        // PUSH1 0x2A PUSH1 0 TSTORE
+        let mut code = hex::decode("602A60005D").unwrap();
+        for _ in 0..2 {
+            code.extend(call_other(0xF1, &other()));
+            // POP
+            code.push(0x50);
+        }
+        // PUSH1 0 MLOAD PUSH1 0 TLOAD
+        code.extend(hex::decode("60005160005C").unwrap());
+
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        let result = with_env(&code, accounts, |env| transaction().process(env));
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::from(0x2A), U256::from(2)]);
+    }
+
+    #[test]
+    fn tstore_should_be_discarded_after_the_transaction() {
+        // PUSH1 0 TLOAD PUSH1 1 PUSH1 0 TSTORE
+        let code = hex::decode("60005C600160005D").unwrap();
+        with_env(&code, vec![], |env| {
+            assert_eq!(transaction().process(env).stack.as_ref(), &[U256::ZERO]);
+            assert_eq!(transaction().process(env).stack.as_ref(), &[U256::ZERO]);
+        });
+    }
+
+    #[test]
+    fn tstore_should_fail_in_staticcall() {
+        // PUSH1 1 PUSH1 0 TSTORE
+        let other_code = hex::decode("600160005D").unwrap();
+        let code = call_other(0xFA, &other());
+
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        let result = with_env(&code, accounts, |env| transaction().process(env));
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::ZERO]);
+    }
+
+    #[test]
+    fn staticcall_should_return_data_but_not_write_state() {
+        // PUSH1 0x2A PUSH1 0 MSTORE PUSH1 0x20 PUSH1 0 RETURN
+        let other_code = hex::decode("602A60005260206000F3").unwrap();
+        let mut code = call_other(0xFA, &other());
+        // RETURNDATASIZE PUSH1 0x20 PUSH1 0 PUSH1 0x40 RETURNDATACOPY PUSH1 0x40 MLOAD
+        code.extend(hex::decode("3D6020600060403E604051").unwrap());
+
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        let result = with_env(&code, accounts, |env| transaction().process(env));
+        assert!(result.success);
+        assert_eq!(
+            result.stack.as_ref(),
+            &[U256::from(0x2A), U256::from(0x20), U256::from(1)]
+        );
+
+        // PUSH1 1 PUSH1 0 SSTORE
+        let other_code = hex::decode("6001600055").unwrap();
+        let code = call_other(0xFA, &other());
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        with_env(&code, accounts, |env| {
+            let result = transaction().process(env);
+            assert!(result.success);
+            assert_eq!(result.stack.as_ref(), &[U256::ZERO]);
+            assert_eq!(
+                &U256::ZERO,
+                env.state().get_account(&other()).load(&U256::ZERO)
+            );
+        });
+    }
+
+    #[test]
+    fn create_should_fail_in_staticcall() {
+        // PUSH1 0 PUSH1 0 PUSH1 0 CREATE
+        let other_code = hex::decode("600060006000F0").unwrap();
+        let code = call_other(0xFA, &other());
+
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        with_env(&code, accounts, |env| {
+            let result = transaction().process(env);
+            assert!(result.success);
+            assert_eq!(result.stack.as_ref(), &[U256::ZERO]);
+
+            let caller = contract();
+            let target = other();
+            let data = Calldata::new(&[]);
+            let message = Message::staticcall(&caller, &target, &U256::MAX, &data);
+            let mut evm = EVM::new(env, &message);
+            assert!(!evm.run_until(|_| false));
+            assert!(matches!(
+                evm.result,
+                Some(Err(EVMError::StateModificationDisallowed))
+            ));
+        });
+    }
+
+    #[test]
+    fn returndatacopy_should_copy_from_the_offset() {
+        // PUSH1 0x2A PUSH1 0 MSTORE PUSH1 0x20 PUSH1 0 RETURN
+        let other_code = hex::decode("602A60005260206000F3").unwrap();
+        let copy = |offset: &[u8]| {
+            let mut code = call_other(0xFA, &other());
+            // PUSH1 1 offset PUSH1 0x40 RETURNDATACOPY PUSH1 0x40 MLOAD
+            code.extend([0x60, 0x01]);
+            code.extend(offset);
+            code.extend(hex::decode("60403E604051").unwrap());
+            let accounts = vec![(other(), Account::new(None, Some(other_code.clone().into())))];
+            with_env(&code, accounts, |env| transaction().process(env))
+        };
+
+        // PUSH1 0x1F
+        let result = copy(&[0x60, 0x1F]);
+        assert!(result.success);
+        assert_eq!(U256::from(0x2A) << 248, result.stack[0]);
+
+        // PUSH32 0xFF..FF
+        let mut offset = vec![0x7F];
+        offset.extend([0xFF; 0x20]);
+        assert!(!copy(&offset).success);
+    }
+
+    #[test]
+    fn extcodehash_should_push_the_empty_code_hash_of_an_account_without_code() {
+        // PUSH20 other EXTCODEHASH
+        let mut code = push_address(&other());
+        code.push(0x3F);
+
+        let hash = |other_account: Account| {
+            let accounts = vec![(other(), other_account)];
+            with_env(&code, accounts, |env| transaction().process(env).stack[0])
+        };
+        assert_eq!(
+            EMPTY_CODE_HASH,
+            hash(Account::new(Some(U256::from(1)), None))
+        );
+        assert_eq!(U256::ZERO, hash(Account::Empty));
+        assert_eq!(
+            keccak256_u256(&[0x00]),
+            hash(Account::new(None, Some(vec![0x00].into())))
+        );
+    }
+
+    #[test]
+    fn extcodecopy_should_zero_fill_past_the_code() {
+        // PUSH32 0xFF..FF PUSH1 0 MSTORE
+        let mut code = vec![0x7F];
+        code.extend([0xFF; 0x20]);
+        code.extend(hex::decode("600052").unwrap());
+        // PUSH1 4 PUSH1 1 PUSH1 0 PUSH20 other EXTCODECOPY PUSH1 0 MLOAD
+        code.extend(hex::decode("600460016000").unwrap());
+        code.extend(push_address(&other()));
+        code.extend(hex::decode("3C600051").unwrap());
+
+        let other_code = vec![0x60, 0x2A, 0x00];
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        let result = with_env(&code, accounts, |env| transaction().process(env));
+        assert!(result.success);
+        // The 2 bytes past the code are zero, the rest of the word is untouched.
+        let mut word = [0xFF; 0x20];
+        word[..4].copy_from_slice(&[0x2A, 0x00, 0x00, 0x00]);
+        assert_eq!(&[U256::from_be_bytes(word)], result.stack.as_ref());
+    }
+
+    #[test]
+    fn call_should_charge_the_identity_precompile_from_the_forwarded_gas() {
+        let call_identity = |gas: u8| {
+            // PUSH1 0x2A PUSH1 0 MSTORE PUSH1 0x20 PUSH1 0x20 PUSH1 0x20 PUSH1 0 PUSH1 0 PUSH1 4
+            let mut code = hex::decode("602A600052602060206020600060006004").unwrap();
+            // PUSH1 gas CALL RETURNDATASIZE PUSH1 0x20 MLOAD
+            code.extend([0x60, gas]);
+            code.extend(hex::decode("F13D602051").unwrap());
+            execute(&code, U256::from(100_000))
+        };
+
+        // 15 + 3 per word.
+        let result = call_identity(18);
+        assert!(result.status());
+        let stack: Box<[U256]> = result.stack().into();
+        assert_eq!(
+            &[U256::from(0x2A), U256::from(0x20), U256::from(1)],
+            stack.as_ref()
+        );
+
+        // The output is not copied.
+        let result = call_identity(17);
+        assert!(result.status());
+        let stack: Box<[U256]> = result.stack().into();
+        assert_eq!(&[U256::ZERO; 3], stack.as_ref());
+    }
+
+    #[test]
+    fn call_should_cap_the_requested_gas() {
+        // GAS PUSH1 0 MSTORE PUSH1 0x20 PUSH1 0 RETURN
+        let other_code = hex::decode("5A60005260206000F3").unwrap();
+        // PUSH1 0x20 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0
+        let mut code = hex::decode("60206000600060006000").unwrap();
+        code.extend(push_address(&other()));
+        // PUSH32 0xFF..FF CALL
+        code.push(0x7F);
+        code.extend([0xFF; 0x20]);
+        code.push(0xF1);
+        // POP PUSH1 0 MLOAD
+        code.extend(hex::decode("50600051").unwrap());
+
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        let result = with_env(&code, accounts, |env| {
+            let caller = Address::default();
+            let target = contract();
+            let data = Calldata::new(&[]);
+            let gas = U256::from(1_000_000);
+            let message = Message::call(&caller, &target, &gas, &U256::ZERO, &data);
+            Message::process(message, env)
+        });
+        let TestResult { success, stack, .. } = result.into();
+        assert!(success);
+        // The parent pushes the CALL arguments, the child keeps one 64th then pays for GAS.
+        let available = 1_000_000 - 7 * 3;
+        let forwarded = available - available / 64;
+        assert_eq!(U256::from(forwarded - 2), stack[0]);
+    }
+
+    #[test]
+    fn call_should_lose_the_gas_of_a_child_halting_exceptionally() {
+        let gas = U256::from(1_000_000);
+        let call = |other_code: &str| {
+            let account = Account::new(None, Some(hex::decode(other_code).unwrap().into()));
+            with_env(
+                &call_other(0xF1, &other()),
+                vec![(other(), account)],
+                |env| {
+                    let caller = Address::default();
+                    let target = contract();
+                    let data = Calldata::new(&[]);
+                    let message = Message::call(&caller, &target, &gas, &U256::ZERO, &data);
+                    let result = Message::process(message, env);
+                    (Box::<[U256]>::from(result.stack()), result.gas_used())
+                },
+            )
+        };
+
+        // STOP
+        let (stack, parent_gas) = call("00");
+        assert_eq!(&[U256::from(1)], stack.as_ref());
+        // PUSH1 0 JUMP
+        let (stack, gas_used) = call("600056");
+        assert_eq!(&[U256::ZERO], stack.as_ref());
+        let available = 1_000_000 - parent_gas;
+        assert_eq!(parent_gas + available - available / 64, gas_used);
+    }
+
+    #[test]
+    fn call_and_create_should_fail_beyond_the_maximum_depth() {
+        // PUSH1 1 PUSH1 0 SSTORE
+        let other_code = hex::decode("6001600055").unwrap();
+        let mut code = call_other(0xF1, &other());
+        // PUSH1 0 PUSH1 0 PUSH1 0 CREATE RETURNDATASIZE
+        code.extend(hex::decode("600060006000F03D").unwrap());
+        let execute = |depth: usize| {
+            let account = Account::new(None, Some(other_code.clone().into()));
+            with_env(&code, vec![(other(), account)], |env| {
+                env.set_depth(depth);
+                let result = transaction().process(env);
+                assert!(result.success);
+                assert_eq!(depth, env.depth());
+                let stored = *env.state().get_account(&other()).load(&U256::ZERO);
+                (result.stack, stored)
+            })
+        };
+
+        let (stack, stored) = execute(CALL_DEPTH_LIMIT - 1);
+        assert_eq!(U256::from(1), stack[2]);
+        assert_ne!(U256::ZERO, stack[1]);
+        assert_eq!(U256::from(1), stored);
+
+        let (stack, stored) = execute(CALL_DEPTH_LIMIT);
+        assert_eq!(&[U256::ZERO; 3], stack.as_ref());
+        assert_eq!(U256::ZERO, stored);
+    }
+
+    #[test]
+    fn should_serialize_test_result() {
+        // PUSH1 2 PUSH1 0x2A PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 LOG0 PUSH1 1 PUSH1 0 RETURN
+        let result = run(&hex::decode("6002602A60005360016000A060016000F3").unwrap());
+        let mut json = serde_json::to_value(&result).unwrap();
+        json.as_object_mut().unwrap().remove("gas_used");
+        assert_eq!(
+            serde_json::json!({
+                "stack": ["0x0000000000000000000000000000000000000000000000000000000000000002"],
+                "logs": [{
+                    "address": "0x000000000000000000000000000000000000dead",
+                    "topics": [],
+                    "data": "2a",
+                }],
+                "success": true,
+                "return": "2a",
+            }),
+            json
+        );
+    }
+
+    #[test]
+    fn should_format_the_result_like_geth() {
+        // PUSH1 0 PUSH1 1 PUSH1 2 PUSH1 0x2A PUSH1 0 MSTORE PUSH1 1 PUSH1 0x1F RETURN
+        let result = run(&hex::decode("600060016002602A6000526001601FF3").unwrap());
+        assert_eq!(
+            serde_json::json!({
+                "stack": ["0x0", "0x1", "0x2"],
+                "memory": "0x000000000000000000000000000000000000000000000000000000000000002a",
+                "return": "0x2a",
+            }),
+            result.to_geth_json()
+        );
+
+        let result = run(&[]);
+        assert_eq!(
+            serde_json::json!({"stack": [], "memory": "0x", "return": "0x"}),
+            result.to_geth_json()
+        );
+    }
+
+    #[test]
+    fn should_report_the_halt_reason() {
+        let halt_reason = |code: &str| run(&hex::decode(code).unwrap()).halt_reason;
+        // STOP
+        assert_eq!(HaltReason::Stop, halt_reason("00"));
+        // PUSH1 0
+        assert_eq!(HaltReason::Stop, halt_reason("6000"));
+        // PUSH1 0 PUSH1 0 RETURN
+        assert_eq!(HaltReason::Return, halt_reason("60006000F3"));
+        // PUSH1 0 PUSH1 0 REVERT
+        assert_eq!(HaltReason::Revert, halt_reason("60006000FD"));
+        // POP
+        assert_eq!(HaltReason::Error, halt_reason("50"));
+        // INVALID
+        assert_eq!(HaltReason::Error, halt_reason("FE"));
+        // Unlike a REVERT, INVALID consumes all the gas.
+        let gas = U256::from(1000);
+        assert_eq!(1000, execute(&[0xFE], gas).gas_used());
+        assert_eq!(
+            6,
+            execute(&hex::decode("60006000FD").unwrap(), gas).gas_used()
+        );
+    }
+
+    #[test]
+    fn breakpoint_should_only_pause_the_stepping_in_debug_mode() {
+        // PUSH1 1 BREAKPOINT PUSH1 2
+        let code = hex::decode("6001B06002").unwrap();
+        with_env(&code, vec![], |env| {
+            env.set_debug(true);
+            let caller = Address::default();
+            let target = contract();
+            let data = Calldata::new(&[]);
+            let message = Message::call(&caller, &target, &U256::MAX, &U256::ZERO, &data);
+            let mut evm = EVM::new(env, &message);
+
+            assert!(evm.run_until(|_| false));
+            assert!(evm.result.is_none());
+            assert_eq!(3, evm.code.peek().0);
+            assert_eq!(1, evm.stack.len());
+            // Resume after the breakpoint.
+            assert!(!evm.run_until(|_| false));
+            assert!(matches!(evm.result, Some(Ok(_))));
+            assert_eq!(2, evm.stack.len());
+        });
+
+        let run_with_debug = |debug: bool| {
+            with_env(&code, vec![], |env| {
+                env.set_debug(debug);
+                transaction().process(env)
+            })
+        };
+        // A full execution does not stop at the breakpoint.
+        let result = run_with_debug(true);
+        assert!(result.success);
+        assert_eq!(HaltReason::Stop, result.halt_reason);
+        assert_eq!(&[U256::from(2), U256::from(1)], result.stack.as_ref());
+
+        let result = run_with_debug(false);
+        assert!(!result.success);
+    }
+
+    /// Calls a contract with `other_code` and `gas`, then pushes RETURNDATASIZE on the call status.
+    fn call_returndatasize(other_code: &str, gas: u8) -> Box<[U256]> {
+        // PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0
+        let mut code = hex::decode("60006000600060006000").unwrap();
+        code.extend(push_address(&other()));
+        // PUSH1 gas CALL RETURNDATASIZE
+        code.extend([0x60, gas, 0xF1, 0x3D]);
+
+        let other_code = hex::decode(other_code).unwrap();
+        let accounts = vec![(other(), Account::new(None, Some(other_code.into())))];
+        let result: TestResult = with_env(&code, accounts, |env| {
+            let caller = Address::default();
+            let target = contract();
+            let data = Calldata::new(&[]);
+            let gas = U256::from(1_000_000);
+            let message = Message::call(&caller, &target, &gas, &U256::ZERO, &data);
+            Message::process(message, env)
+        })
+        .into();
+        assert!(result.success);
+        result.stack
+    }
+
+    #[test]
+    fn call_should_only_keep_return_data_of_return_and_revert() {
+        // PUSH1 2 PUSH1 0 RETURN
+        assert_eq!(
+            call_returndatasize("60026000F3", 0xFF).as_ref(),
+            &[U256::from(2), U256::from(1)]
+        );
+        // PUSH1 2 PUSH1 0 REVERT
+        assert_eq!(
+            call_returndatasize("60026000FD", 0xFF).as_ref(),
+            &[U256::from(2), U256::ZERO]
+        );
+        // PUSH1 2 PUSH1 0 INVALID
+        assert_eq!(
+            call_returndatasize("60026000FE", 0xFF).as_ref(),
+            &[U256::ZERO, U256::ZERO]
+        );
+        // PUSH1 2 REVERT
+        assert_eq!(
+            call_returndatasize("6002FD", 0xFF).as_ref(),
+            &[U256::ZERO, U256::ZERO]
+        );
+        // PUSH1 2 PUSH1 0 LOG0 with not enough gas
+        assert_eq!(
+            call_returndatasize("60026000A0", 0xFF).as_ref(),
+            &[U256::ZERO, U256::ZERO]
+        );
+    }
+
+    #[test]
+    fn returndata_should_come_from_the_direct_callee_across_nested_reverts() {
+        let middle: Address = uint!(0x000000000000000000000000000000000000cafe_U160).into();
+        // PUSH1 0xCC PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 REVERT
+        let inner_code = hex::decode("60CC60005360016000FD").unwrap();
+        // PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH20 other GAS CALL POP
+        let mut middle_code = hex::decode("60006000600060006000").unwrap();
+        middle_code.extend(push_address(&other()));
+        middle_code.extend([0x5A, 0xF1, 0x50]);
+        // PUSH1 0xBB PUSH1 0 MSTORE8 PUSH1 0xBB PUSH1 1 MSTORE8 PUSH1 2 PUSH1 0 REVERT
+        middle_code.extend(hex::decode("60BB60005360BB60015360026000FD").unwrap());
+        // PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH20 middle GAS CALL
+        let mut code = hex::decode("60006000600060006000").unwrap();
+        code.extend(push_address(&middle));
+        code.extend([0x5A, 0xF1]);
+        // RETURNDATASIZE RETURNDATASIZE PUSH1 0 PUSH1 0 RETURNDATACOPY PUSH1 0 MLOAD
+        code.extend(hex::decode("3D3D600060003E600051").unwrap());
+
+        let accounts = vec![
+            (other(), Account::new(None, Some(inner_code.into()))),
+            (middle, Account::new(None, Some(middle_code.into()))),
+        ];
+        let result = with_env(&code, accounts, |env| transaction().process(env));
+        assert!(result.success);
+        let mut data = [0x00; 0x20];
+        data[..2].copy_from_slice(&[0xBB, 0xBB]);
+        assert_eq!(
+            &[U256::from_be_bytes(data), U256::from(2), U256::ZERO],
+            result.stack.as_ref()
+        );
+    }
+
+    /// Runs 10 nested calls, the frame `i` reverts if `reverts[i]`.
+    /// Each frame stores 1 in slot 0, logs its level, calls the next one and stores its status in slot 1.
+    fn check_nested_calls(reverts: [bool; 10]) {
+        let level = |i: usize| -> Address {
+            match i {
+                0 => contract(),
+                i => U256::from(0x1000 + i).into(),
+            }
+        };
+        let code = |i: usize| {
+            // PUSH1 1 PUSH1 0 SSTORE PUSH1 i PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 LOG0
+            let mut code = hex::decode("6001600055").unwrap();
+            code.extend([
+                0x60, i as u8, 0x60, 0x00, 0x53, 0x60, 0x01, 0x60, 0x00, 0xA0,
+            ]);
+            if i < 9 {
+                // PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH20 child GAS CALL PUSH1 1 SSTORE
+                code.extend(hex::decode("60006000600060006000").unwrap());
+                code.extend(push_address(&level(i + 1)));
+                code.extend(hex::decode("5AF1600155").unwrap());
+            }
+            match reverts[i] {
+                // PUSH1 0 PUSH1 0 REVERT
+                true => code.extend(hex::decode("60006000FD").unwrap()),
+                // STOP
+                false => code.push(0x00),
+            }
+            code
+        };
+
+        let accounts = (1..10)
+            .map(|i| (level(i), Account::new(None, Some(code(i).into()))))
+            .collect();
+        with_env(&code(0), accounts, |env| {
+            let result = transaction().process(env);
+            assert_eq!(!reverts[0], result.success);
+
+            // A frame is kept if neither it nor its callers reverted.
+            let kept = (0..10).take_while(|i| !reverts[*i]).count();
+            for i in 0..10 {
+                let account = env.state().get_account(&level(i));
+                let slot = |key: u64| *account.load(&U256::from(key));
+                if i < kept {
+                    assert_eq!(U256::from(1), slot(0), "level {}", i);
+                    let status = i < 9 && !reverts[i + 1];
+                    assert_eq!(U256::from(status as u8), slot(1), "level {}", i);
+                } else {
+                    assert_eq!(Some(&BTreeMap::new()), account.storage(), "level {}", i);
+                }
+            }
+            let logs = (0..kept)
+                .map(|i| LogResult::from(Log::log0(level(i), vec![i as u8])))
+                .collect::<Vec<_>>();
+            assert_eq!(logs.as_slice(), result.logs.as_ref());
+        });
+    }
+
+    #[test]
+    fn nested_calls_should_only_revert_their_own_frames() {
+        let alternating = std::array::from_fn(|i| i % 2 == 1);
+        check_nested_calls(alternating);
+        check_nested_calls([false; 10]);
+        let mut deepest = [false; 10];
+        deepest[9] = true;
+        check_nested_calls(deepest);
+        let mut middle = [false; 10];
+        middle[5] = true;
+        middle[8] = true;
+        check_nested_calls(middle);
+        let mut all_but_root = [true; 10];
+        all_but_root[0] = false;
+        check_nested_calls(all_but_root);
+    }
+
+    #[test]
+    fn reverted_child_should_not_clobber_the_parent_storage() {
+        // PUSH1 0xBB PUSH1 1 SSTORE PUSH1 0 PUSH1 0 REVERT
+        let child = hex::decode("60BB60015560006000FD").unwrap();
+        // PUSH1 0xAA PUSH1 1 SSTORE
+        let mut code = hex::decode("60AA600155").unwrap();
+        // The child runs in the parent storage.
+        code.extend(call_other(0xF4, &other()));
+        // PUSH1 2 SSTORE
+        code.extend([0x60, 0x02, 0x55]);
+
+        let accounts = vec![(other(), Account::new(None, Some(child.into())))];
+        with_env(&code, accounts, |env| {
+            let result = transaction().process(env);
+            assert!(result.success);
+            let account = env.state().get_account(&contract());
+            assert_eq!(&U256::from(0xAA), account.load(&U256::from(1)));
+            // The child failed.
+            assert_eq!(&U256::ZERO, account.load(&U256::from(2)));
+        });
+    }
+
+    #[test]
+    fn origin_should_be_constant_and_caller_should_change_across_calls() {
+        let sender: Address = uint!(0x0000000000000000000000000000000000001337_U160).into();
+        // ORIGIN CALLER PUSH1 0 PUSH1 0 LOG2
+        let log_code = hex::decode("323360006000A2").unwrap();
+        let mut code = log_code.clone();
+        code.extend(call_other(0xF1, &other()));
+
+        let accounts = vec![(other(), Account::new(None, Some(log_code.into())))];
+        let result = with_env(&code, accounts, |env| {
+            let transaction = Transaction::new(
+                U256::ZERO,
+                U256::MAX,
+                sender.clone(),
+                Some(contract()),
+                U256::ZERO,
+                vec![],
+            );
+            transaction.process(env)
+        });
+        assert!(result.success);
+
+        let origin = <U256 as From<&Address>>::from(&sender);
+        let topics = |caller: &Address| [<U256 as From<&Address>>::from(caller), origin];
+        assert_eq!(
+            result.logs.as_ref(),
+            &[
+                LogResult::from(Log::log2(contract(), topics(&sender), vec![])),
+                LogResult::from(Log::log2(other(), topics(&contract()), vec![])),
+            ]
+        );
+    }
+
+    #[test]
+    fn extcodesize_should_cost_more_than_codesize() {
+        let gas = U256::from(1_000_000);
+        // CODESIZE
+        assert_eq!(gas::G_BASE, execute(&[0x38], gas).gas_used());
+        // ADDRESS EXTCODESIZE
+        assert_eq!(gas::G_BASE + 2600, execute(&[0x30, 0x3B], gas).gas_used());
+    }
+
+    #[test]
+    fn should_stop_an_infinite_loop_at_the_step_limit() {
+        // JUMPDEST PUSH1 0 JUMP
+        let code = hex::decode("5B600056").unwrap();
+        with_env(&code, vec![], |env| {
+            env.set_step_limit(Some(1000));
+            let caller = Address::default();
+            let target = contract();
+            let data = Calldata::new(&[]);
+            let message = Message::call(&caller, &target, &U256::MAX, &U256::ZERO, &data);
+            let mut evm = EVM::new(env, &message);
+            (&mut evm).for_each(drop);
+            assert!(matches!(evm.result, Some(Err(EVMError::StepLimitExceeded))));
+            assert_eq!(Some(0), evm.env.steps());
+        });
+
+        // PUSH1 1 STOP
+        let code = hex::decode("600100").unwrap();
+        let result = with_env(&code, vec![], |env| {
+            env.set_step_limit(Some(2));
+            transaction().process(env)
+        });
+        assert!(result.success);
+    }
+
+    #[test]
+    fn should_share_the_step_limit_between_the_frames() {
+        // PUSH1 0 DUP1 DUP1 DUP1 DUP1 ADDRESS GAS CALL, calling itself.
+        let code = hex::decode("600080808080305AF1").unwrap();
+        let result = with_env(&code, vec![], |env| {
+            env.set_step_limit(Some(100));
+            let result = transaction().process(env);
+            assert_eq!(Some(0), env.steps());
+            result
+        });
+        assert!(!result.success);
+        assert_eq!(HaltReason::Error, result.halt_reason);
+
+        // The limit applies to each transaction.
+        let result = with_env(&[0x00], vec![], |env| {
+            env.set_step_limit(Some(1));
+            transaction().process(env);
+            transaction().process(env)
+        });
+        assert!(result.success);
+    }
+
+    #[test]
+    fn staticcall_frames_should_not_get_a_mutable_state() {
+        with_env(&[], vec![], |env| {
+            let caller = Address::default();
+            let target = contract();
+            let data = Calldata::new(&[]);
+            let message = Message::staticcall(&caller, &target, &U256::MAX, &data);
+            let mut evm = EVM::new(env, &message);
+            assert!(matches!(evm.env, FrameEnv::Static(_)));
+            assert!(matches!(
+                evm.state_mut(),
+                Err(EVMError::StateModificationDisallowed)
+            ));
+            assert!(evm.state().get_account(&target).code().is_empty());
+
+            let message = Message::call(&caller, &target, &U256::MAX, &U256::ZERO, &data);
+            assert!(EVM::new(env, &message).state_mut().is_ok());
+        });
+    }
+
+    #[test]
+    fn should_step_the_frame_of_a_transaction() {
+        // PUSH1 0x2A PUSH1 6 JUMP INVALID JUMPDEST PUSH1 1 STOP
+        let code = hex::decode("602A600656FE5B600100").unwrap();
+        let mut paused = vec![];
+        let result = with_env(&code, vec![], |env| {
+            transaction().process_stepping(env, |mut stepper| {
+                let mut record = |step: &Step| {
+                    paused.push(step.clone());
+                    true
+                };
+                assert!(stepper.run_until(|step| step.opcode == Opcode::JUMPDEST && record(step)));
+                assert!(stepper.run_until(|step| step.pc > 6 && record(step)));
+            })
+        });
+        let expected = vec![
+            Step {
+                pc: 6,
+                opcode: Opcode::JUMPDEST,
+                stack_depth: 1,
+            },
+            Step {
+                pc: 7,
+                opcode: Opcode::PUSH(1, U256::from(1)),
+                stack_depth: 1,
+            },
+        ];
+        assert_eq!(expected, paused);
+        // The frame is executed to its end.
+        assert!(result.success);
+        assert_eq!(&[U256::from(1), U256::from(0x2A)], result.stack.as_ref());
+    }
+
+    #[test]
+    fn should_run_until_the_predicate_is_true() {
+        // PUSH1 0x2A PUSH1 6 JUMP INVALID JUMPDEST PUSH1 1 STOP
+        let code = hex::decode("602A600656FE5B600100").unwrap();
+        with_env(&code, vec![], |env| {
+            let caller = Address::default();
+            let target = contract();
+            let data = Calldata::new(&[]);
+            let message = Message::call(&caller, &target, &U256::MAX, &U256::ZERO, &data);
+            let mut evm = EVM::new(env, &message);
+
+            assert!(evm.run_until(|step| step.stack_depth > 1));
+            assert_eq!(4, evm.code.peek().0);
+            assert!(evm.run_until(|step| step.opcode == Opcode::JUMPDEST));
+            assert_eq!(6, evm.code.peek().0);
+            assert_eq!(1, evm.stack.len());
+            // The paused opcode is checked again when resuming.
+            assert!(evm.run_until(|step| step.pc == 6));
+            // JUMPDEST is never executed again.
+            assert!(!evm.run_until(|step| step.pc == 5));
+            assert!(matches!(evm.result, Some(Ok(_))));
+            assert_eq!(2, evm.stack.len());
+        });
+    }
+
+    #[test]
+    fn should_report_the_gas_used_on_stop() {
+        // PUSH1 1 POP STOP
+        let code = hex::decode("60015000").unwrap();
+        let result = with_env(&code, vec![], |env| {
+            let transaction = Transaction::new(
+                U256::ZERO,
+                U256::from(100_000),
+                Address::default(),
+                Some(contract()),
+                U256::ZERO,
+                vec![],
+            );
+            transaction.process(env)
+        });
+        assert!(result.success);
+        assert_eq!(21000 + 3 + 2, result.gas_used);
+    }
+
+    #[test]
+    fn selfdestruct_should_be_refunded_before_london() {
+        // PUSH1 0 SELFDESTRUCT
+        let code = hex::decode("6000FF").unwrap();
+        let gas_used = |spec: Spec| {
+            with_env(&code, vec![], |env| {
+                env.set_spec(spec);
+                let transaction = Transaction::new(
+                    U256::ZERO,
+                    U256::from(100_000),
+                    Address::default(),
+                    Some(contract()),
+                    U256::ZERO,
+                    vec![],
+                );
+                let result = transaction.process(env);
+                assert!(result.success);
+                result.gas_used
+            })
+        };
+        // The refund is capped to half of the gas used.
+        let gas = 21000 + 3 + 5000 + 2600;
+        assert_eq!(gas - gas / 2, gas_used(Spec::Berlin));
+        assert_eq!(gas, gas_used(Spec::London));
+    }
+
+    #[test]
+    fn selfdestruct_should_charge_the_creation_of_a_new_beneficiary() {
+        // PUSH20 other SELFDESTRUCT
+        let mut code = push_address(&other());
+        code.push(0xFF);
+
+        let gas_used = |other_account: Account| {
+            let accounts = vec![
+                (other(), other_account),
+                (contract(), Account::new(Some(U256::from(1)), None)),
+            ];
+            with_env(&code, accounts, |env| {
+                env.set_spec(Spec::London);
+                let transaction = Transaction::new(
+                    U256::ZERO,
+                    U256::from(1_000_000),
+                    Address::default(),
+                    Some(contract()),
+                    U256::ZERO,
+                    vec![],
+                );
+                let result = transaction.process(env);
+                assert!(result.success);
+                result.gas_used
+            })
+        };
+
+        let existing = gas_used(Account::new(Some(U256::from(1)), None));
+        assert_eq!(21000 + 3 + 5000 + 2600, existing);
+        assert_eq!(existing + 25000, gas_used(Account::Empty));
+    }
+
+    #[test]
+    fn parent_should_reclaim_the_gas_and_refunds_of_a_child() {
+        let call = |child_code: &str, spec: Spec| {
+            let mut child = Account::new(None, Some(hex::decode(child_code).unwrap().into()));
+            child.store(U256::ZERO, U256::from(1));
+            with_env(&call_other(0xF1, &other()), vec![(other(), child)], |env| {
+                env.set_spec(spec);
+                let caller = Address::default();
+                let target = contract();
+                let gas = U256::from(100_000);
+                let data = Calldata::new(&[]);
+                let message = Message::call(&caller, &target, &gas, &U256::ZERO, &data);
+                Message::process(message, env)
+            })
+        };
+        // The parent pushes the CALL arguments then GAS.
+        let parent_gas = 6 * 3 + 2;
+        // Clearing a cold slot.
+        let sstore_gas = 5000;
+
+        // PUSH1 0 PUSH1 0 SSTORE
+        let result = call("6000600055", Spec::Berlin);
+        assert_eq!(parent_gas + 6 + sstore_gas, result.gas_used());
+        assert_eq!(15000, result.gas_refund());
+        let result = call("6000600055", Spec::London);
+        assert_eq!(4800, result.gas_refund());
+
+        // PUSH1 0 PUSH1 0 SSTORE PUSH1 0 PUSH1 0 REVERT
+        let result = call("600060005560006000FD", Spec::Berlin);
+        assert!(result.status());
+        assert_eq!(parent_gas + 12 + sstore_gas, result.gas_used());
+        assert_eq!(0, result.gas_refund());
+    }
+
+    #[test]
+    fn sstore_should_take_back_the_refund_of_a_cleared_slot() {
+        // PUSH1 0 PUSH1 0 SSTORE PUSH1 1 PUSH1 0 SSTORE, 4 times.
+        let code = hex::decode("60006000556001600055".repeat(4)).unwrap();
+        let mut account = Account::new(None, Some(code.into()));
+        account.store(U256::ZERO, U256::from(1));
+        let result = with_env(&[], vec![(other(), account)], |env| {
+            env.set_spec(Spec::London);
+            let caller = Address::default();
+            let target = other();
+            let gas = U256::from(100_000);
+            let data = Calldata::new(&[]);
+            let message = Message::call(&caller, &target, &gas, &U256::ZERO, &data);
+            Message::process(message, env)
+        });
+        assert!(result.status());
+        // Each clearing refund is taken back, only restoring the original value is refunded
+        // 2900 - 100 for a cold slot.
+        assert_eq!(4 * 2800, result.gas_refund());
+        // 16 PUSH and 4 times a write of the clean slot then a dirty write.
+        assert_eq!(16 * 3 + 4 * (5000 + 2200), result.gas_used());
+    }
+
+    #[test]
+    fn sstore_should_not_spend_the_call_stipend() {
+        // PUSH1 0 PUSH1 0 SSTORE, a no-op costing 2200 gas which the stipend would cover.
+        let code = hex::decode("6000600055").unwrap();
+        let execute = |gas: u64| execute(&code, U256::from(gas));
+        assert!(!execute(6 + 2300).status());
+        assert!(execute(6 + 2301).status());
+    }
+
+    #[test]
+    fn should_simulate_a_transaction_with_overridden_state() {
+        // PUSH1 0 SLOAD PUSH1 1 PUSH1 0 SSTORE
+        let code = hex::decode("6000546001600055").unwrap();
+        with_env(&code, vec![], |env| {
+            // PUSH1 0x2A
+            let overrides = State::builder().deploy(contract(), vec![0x60, 0x2A]);
+            let result = transaction().simulate(env, overrides);
+            assert!(result.success);
+            assert_eq!(&[U256::from(0x2A)], result.stack.as_ref());
+
+            // The code is kept when only the storage is overridden.
+            let overrides = State::builder().set_storage(contract(), U256::ZERO, U256::from(7));
+            let result = transaction().simulate(env, overrides);
+            assert_eq!(&[U256::from(7)], result.stack.as_ref());
+
+            // Neither the overrides nor the simulated writes are kept.
+            let state = env.state();
+            assert_eq!(code.as_slice(), state.get_account(&contract()).code());
+            assert_eq!(
+                &U256::ZERO,
+                state.get_account(&contract()).load(&U256::ZERO)
+            );
+            assert_eq!(&[U256::ZERO], transaction().process(env).stack.as_ref());
+        });
+    }
+
+    #[test]
+    fn should_chain_transactions_with_the_returned_state() {
+        // PUSH1 0 SLOAD PUSH1 1 ADD DUP1 PUSH1 0 SSTORE STOP
+        let code = hex::decode("6000546001018060005500").unwrap();
+        let (result, state) = with_env(&code, vec![], |env| transaction().process_with_state(env));
+        assert_eq!(&[U256::from(1)], result.stack.as_ref());
+
+        let (result, state) = with_env(&[], vec![], |env| {
+            env.set_state(state);
+            transaction().process_with_state(env)
+        });
+        assert_eq!(&[U256::from(2)], result.stack.as_ref());
+        assert_eq!(
+            &U256::from(2),
+            state.get_account(&contract()).load(&U256::ZERO)
+        );
+    }
+
+    #[test]
+    fn should_fail_below_the_intrinsic_gas() {
+        let result = with_env(&[0x00], vec![], |env| {
+            let transaction = Transaction::new(
+                U256::ZERO,
+                U256::from(20_999),
+                Address::default(),
+                Some(contract()),
+                U256::ZERO,
+                vec![],
+            );
+            transaction.process(env)
+        });
+        assert!(!result.success);
+    }
+
+    #[test]
+    fn should_pay_the_fees_to_the_coinbase() {
+        let sender = Address::default();
+        let coinbase = other();
+        let accounts = HashMap::from([
+            (
+                sender.clone(),
+                Account::new(Some(U256::from(1_000_000)), None),
+            ),
+            (contract(), Account::new(None, Some(vec![0x00].into()))),
+        ]);
+        let zero = U256::ZERO;
+        let base_fee = U256::from(4);
+        let mut env = Environment::new(
+            &sender,
+            &[],
+            &coinbase,
+            &zero,
+            &base_fee,
+            &zero,
+            &zero,
+            &zero,
+            &zero,
+            State::new(accounts),
+            &zero,
+        );
+        let transaction = Transaction::new(
+            U256::from(10),
+            U256::from(100_000),
+            sender.clone(),
+            Some(contract()),
+            U256::ZERO,
+            vec![],
+        );
+
+        let result = transaction.process(&mut env);
+        assert!(result.success);
+        assert_eq!(21000, result.gas_used);
+        // The coinbase only receives the priority fee, the base fee is burnt.
+        let state = env.state();
+        assert_eq!(
+            &U256::from(21000 * 6),
+            state.get_account(&coinbase).balance()
+        );
+        assert_eq!(
+            &U256::from(1_000_000 - 21000 * 10),
+            state.get_account(&sender).balance()
+        );
+    }
+
+    #[test]
+    fn should_fail_when_the_sender_cannot_pay_the_gas_upfront() {
+        // PUSH1 1 PUSH1 0 SSTORE
+        let code = hex::decode("6001600055").unwrap();
+        let accounts = vec![(
+            Address::default(),
+            Account::new(Some(U256::from(100_000 * 10 - 1)), None),
+        )];
+        with_env(&code, accounts, |env| {
+            let transaction = Transaction::new(
+                U256::from(10),
+                U256::from(100_000),
+                Address::default(),
+                Some(contract()),
+                U256::ZERO,
+                vec![],
+            );
+            let result = transaction.process(env);
+            assert!(!result.success);
+            assert_eq!(0, result.gas_used);
+            // No code was executed.
+            let state = env.state();
+            assert_eq!(
+                &U256::ZERO,
+                state.get_account(&contract()).load(&U256::ZERO)
+            );
+            assert_eq!(
+                &U256::from(100_000 * 10 - 1),
+                state.get_account(&Address::default()).balance()
+            );
+        });
+    }
+
+    #[test]
+    fn should_undo_an_unmetered_transaction_whose_fees_cannot_be_paid() {
+        // PUSH1 1 PUSH1 0 SSTORE
+        let code = hex::decode("6001600055").unwrap();
+        with_env(&code, vec![], |env| {
+            let transaction = Transaction::new(
+                U256::from(10),
+                U256::MAX,
+                Address::default(),
+                Some(contract()),
+                U256::ZERO,
+                vec![],
+            );
+            let result = transaction.process(env);
+            assert!(!result.success);
+            assert_eq!(0, result.gas_used);
+            assert_eq!(
+                &U256::ZERO,
+                env.state().get_account(&contract()).load(&U256::ZERO)
+            );
+        });
+    }
+
+    #[test]
+    fn should_enumerate_the_stored_slots() {
+        // PUSH1 1 PUSH1 0 SSTORE PUSH1 2 PUSH1 5 SSTORE PUSH1 3 PUSH1 5 SSTORE
+        // PUSH1 0 PUSH1 7 SSTORE
+        let code = hex::decode("6001600055600260055560036005556000600755").unwrap();
+        with_env(&code, vec![], |env| {
+            assert!(transaction().process(env).success);
+            let storage = env.state().get_account(&contract()).storage().cloned();
+            assert_eq!(
+                Some(BTreeMap::from([
+                    (U256::ZERO, U256::from(1)),
+                    (U256::from(5), U256::from(3)),
+                ])),
+                storage
+            );
+            assert_eq!(None, env.state().get_account(&other()).storage());
+        });
+    }
+
+    #[test]
+    fn smod_should_return_zero_for_min_negative_by_negative_one() {
+        // PUSH32 0xFF..FF PUSH32 0x80..00 SMOD
+        let mut code = vec![0x7F];
+        code.extend([0xFF; 0x20]);
+        code.push(0x7F);
+        code.push(0x80);
+        code.extend([0x00; 0x1F]);
+        code.push(0x07);
+        let result = run(&code);
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::ZERO]);
+    }
+
+    #[test]
+    fn mstore_should_fail_near_the_maximum_offset() {
+        // PUSH1 1 PUSH32 0xFF..F0 MSTORE
+        let mut code = hex::decode("60017F").unwrap();
+        code.extend([0xFF; 0x1F]);
+        code.extend([0xF0, 0x52]);
+        assert!(!run(&code).success);
+
+        // PUSH1 0x20 PUSH1 0 PUSH32 0xFF..FF CALLDATACOPY
+        let mut code = hex::decode("602060007F").unwrap();
+        code.extend([0xFF; 0x20]);
+        code.push(0x37);
+        assert!(!run(&code).success);
+    }
+
+    #[test]
+    fn create_should_deploy_the_returned_runtime_code() {
+        // PUSH10 init_code PUSH1 0 MSTORE
+        // with init_code: PUSH1 0 PUSH1 0 MSTORE8 PUSH1 1 PUSH1 0 RETURN
+        let mut code = hex::decode("69600060005360016000F3600052").unwrap();
+        // PUSH1 10 PUSH1 22 PUSH1 0 CREATE
+        code.extend(hex::decode("600A60166000F0").unwrap());
+        // DUP1 EXTCODESIZE SWAP1
+        code.extend(hex::decode("803B90").unwrap());
+        // PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 DUP6 GAS CALL SWAP1 POP
+        code.extend(hex::decode("60006000600060006000855AF19050").unwrap());
+
+        let result = run(&code);
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::from(1), U256::from(1)]);
+    }
+
+    #[test]
+    fn create_should_fail_without_gas_for_the_code_deposit() {
+        // PUSH1 100 PUSH1 0 RETURN
+        let init_code = hex::decode("60646000F3").unwrap();
+        let create = |gas: u64| {
+            with_env(&[], vec![], |env| {
+                let caller = contract();
+                let gas = U256::from(gas);
+                let data = Calldata::new(&init_code);
+                let message = Message::create(&caller, &0, &gas, &U256::ZERO, &data);
+                let target = message.target().clone();
+                let result = Message::process(message, env);
+                let code_size = env.state().get_account(&target).code().len();
+                (result, code_size)
+            })
+        };
+
+        let (result, code_size) = create(1_000_000);
+        assert!(result.status());
+        assert_eq!(100, code_size);
+        // The 2 PUSH, 4 words of memory and the deposit of the 100 bytes.
+        let gas_used = result.gas_used();
+        assert_eq!(3 + 3 + 12 + 200 * 100, gas_used);
+
+        let (result, code_size) = create(gas_used);
+        assert!(result.status());
+        assert_eq!(100, code_size);
+
+        let (result, code_size) = create(gas_used - 1);
+        assert!(!result.status());
+        assert_eq!(gas_used - 1, result.gas_used());
+        assert_eq!(0, code_size);
+    }
+
+    #[test]
+    fn create_should_discard_its_refunds_without_gas_for_the_code_deposit() {
+        // PUSH1 1 PUSH1 0 SSTORE PUSH1 0 PUSH1 0 SSTORE PUSH1 100 PUSH1 0 RETURN
+        let init_code = hex::decode("6001600055600060005560646000F3").unwrap();
+        let create = |gas: u64| {
+            with_env(&[], vec![], |env| {
+                let caller = contract();
+                let gas = U256::from(gas);
+                let data = Calldata::new(&init_code);
+                let message = Message::create(&caller, &0, &gas, &U256::ZERO, &data);
+                Message::process(message, env)
+            })
+        };
+
+        let result = create(1_000_000);
+        assert!(result.status());
+        // Restoring the original zero of the slot refunds its set.
+        assert_eq!(20000 - 100, result.gas_refund());
+
+        let result = create(result.gas_used() - 1);
+        assert!(!result.status());
+        assert_eq!(0, result.gas_refund());
+    }
+
+    #[test]
+    fn create_transaction_should_return_the_created_address() {
+        // PUSH1 0 PUSH1 0 RETURN
+        let init_code = hex::decode("60006000F3").unwrap();
+        let sender = other();
+        let accounts = vec![(sender.clone(), Account::new(Some(U256::ZERO), None))];
+        let result = with_env(&[], accounts, |env| {
+            let transaction = Transaction::new(
+                U256::ZERO,
+                U256::MAX,
+                sender.clone(),
+                None,
+                U256::ZERO,
+                init_code,
+            );
+            transaction.process(env)
+        });
+        assert!(result.success);
+
+        // keccak256(rlp([sender, nonce]))[12..]
+        let hash = keccak256(&rlp::encode_list::<U256, _>(&[
+            <U256 as From<&Address>>::from(&sender),
+            U256::ZERO,
+        ]));
+        let expected = Address::from(<[u8; 0x14]>::try_from(&hash[0x0C..]).unwrap());
+        assert_eq!(Some(expected), result.created_address);
+
+        // A call does not create anything.
+        assert_eq!(None, run(&[0x00]).created_address);
+    }
+
+    #[test]
+    fn call_should_fail_when_the_value_exceeds_the_balance() {
+        // PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 2
+        let mut code = hex::decode("60006000600060006002").unwrap();
+        code.extend(push_address(&other()));
+        // GAS CALL SELFBALANCE
+        code.extend([0x5A, 0xF1, 0x47]);
+
+        let run_with_balance = |balance: u64| {
+            let accounts = vec![
+                (other(), Account::new(None, Some(vec![0x00].into()))),
+                (contract(), Account::new(Some(U256::from(balance)), None)),
+            ];
+            with_env(&code, accounts, |env| transaction().process(env))
+        };
+
+        let result = run_with_balance(2);
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::ZERO, U256::from(1)]);
+
+        let result = run_with_balance(1);
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::from(1), U256::ZERO]);
+    }
+
+    #[test]
+    fn call_should_charge_the_creation_of_a_new_account() {
+        let gas_used = |value: u8, spec: Spec, other_account: Account| {
+            // PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 0 PUSH1 value
+            let mut code = hex::decode("6000600060006000").unwrap();
+            code.extend([0x60, value]);
+            code.extend(push_address(&other()));
+            // GAS CALL
+            code.extend([0x5A, 0xF1]);
+            let accounts = vec![
+                (other(), other_account),
+                (contract(), Account::new(Some(U256::from(1)), None)),
+            ];
+            with_env(&code, accounts, |env| {
+                env.set_spec(spec);
+                let transaction = Transaction::new(
+                    U256::ZERO,
+                    U256::from(1_000_000),
+                    Address::default(),
+                    Some(contract()),
+                    U256::ZERO,
+                    vec![],
+                );
+                let result = transaction.process(env);
+                assert!(result.success);
+                assert_eq!(&[U256::from(1)], result.stack.as_ref());
+                result.gas_used
+            })
+        };
+        let existing = |value, spec| gas_used(value, spec, Account::new(Some(U256::from(1)), None));
+        let new = |value, spec| gas_used(value, spec, Account::Empty);
+
+        assert_eq!(existing(1, Spec::Cancun) + 25000, new(1, Spec::Cancun));
+        // Since EIP-161, a call without value does not create the account.
+        assert_eq!(existing(0, Spec::Cancun), new(0, Spec::Cancun));
+        assert_eq!(
+            existing(0, Spec::TangerineWhistle) + 25000,
+            new(0, Spec::TangerineWhistle)
+        );
+    }
+
+    #[test]
+    fn should_record_the_executed_opcodes() {
+        // PUSH1 2 JUMPDEST PUSH1 1 SWAP1 SUB DUP1 PUSH1 2 JUMPI
+        let code = hex::decode("60025B600190038060025700").unwrap();
+        let result = with_env(&code, vec![], |env| {
+            env.set_record_trace(true);
+            transaction().process(env)
+        });
+        assert!(result.success);
+
+        let jumps = result.trace.iter().filter(|(_, op)| *op == Opcode::JUMPI);
+        assert_eq!(2, jumps.count());
+        assert_eq!(
+            &result.trace[..3],
+            &[
+                (0, Opcode::PUSH(1, U256::from(2))),
+                (2, Opcode::JUMPDEST),
+                (3, Opcode::PUSH(1, U256::from(1))),
+            ]
+        );
+        assert_eq!(Some(&(11, Opcode::STOP)), result.trace.last());
+
+        // Not recorded by default.
+        assert!(run(&code).trace.is_empty());
+    }
+
+    #[test]
+    fn calldataload_should_push_zero_at_overflowing_offset() {
+        // PUSH32 0xFF..FF CALLDATALOAD
+        let mut code = vec![0x7F];
+        code.extend([0xFF; 0x20]);
+        code.push(0x35);
+        let result = run(&code);
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::ZERO]);
+    }
+
+    #[test]
+    fn calldatacopy_should_copy_zeros_from_overflowing_offset() {
+        // PUSH1 0x20 PUSH32 0xFF..FF PUSH1 0 CALLDATACOPY PUSH1 0 MLOAD
+        let mut code = hex::decode("60207F").unwrap();
+        code.extend([0xFF; 0x20]);
+        code.extend(hex::decode("600037600051").unwrap());
+
+        let transaction = Transaction::new(
+            U256::ZERO,
+            U256::MAX,
+            Address::default(),
+            Some(contract()),
+            U256::ZERO,
+            vec![0xFF; 0x20],
+        );
+        let result = with_env(&code, vec![], |env| transaction.process(env));
+        assert!(result.success);
+        assert_eq!(result.stack.as_ref(), &[U256::ZERO]);
+    }
 }
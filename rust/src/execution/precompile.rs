@@ -0,0 +1,35 @@
+use super::gas;
+use crate::types::{Address, Precompile};
+use ruint::aliases::U256;
+use std::rc::Rc;
+
+/// The precompiled contract of the chain at `address` called with `size` bytes, with its cost.
+/// Only IDENTITY is implemented yet, the other addresses are empty accounts.
+pub(super) fn builtin(address: &Address, size: usize) -> Option<(Precompile, u64)> {
+    if !address.is_precompile() {
+        return None;
+    }
+    match <U256 as From<&Address>>::from(address).to::<u8>() {
+        0x04 => Some((Rc::new(identity), gas::identity_cost(size))),
+        _ => None,
+    }
+}
+
+/// Return the input.
+fn identity(input: &[u8]) -> (bool, Vec<u8>) {
+    (true, input.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_only_run_the_implemented_precompiles() {
+        let (identity, cost) = builtin(&U256::from(4).into(), 0x21).unwrap();
+        assert_eq!((true, vec![0x2A]), identity(&[0x2A]));
+        assert_eq!(15 + 3 * 2, cost);
+        assert!(builtin(&U256::from(1).into(), 0).is_none());
+        assert!(builtin(&U256::from(0x0104).into(), 0).is_none());
+    }
+}
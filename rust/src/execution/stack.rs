@@ -3,9 +3,10 @@ use thiserror::Error;
 
 #[derive(Debug)]
 pub(crate) struct Stack {
-    /// The index of the stack's top.
-    top: Option<usize>,
-    arr: [U256; 1024],
+    /// The items from the bottom, allocated as they are pushed.
+    arr: Vec<U256>,
+    /// The maximum number of items.
+    limit: usize,
 }
 
 #[derive(Error, Debug, Clone)]
@@ -26,87 +27,96 @@ impl std::fmt::Display for StackError {
 }
 
 impl Stack {
-    pub(super) fn new() -> Self {
+    pub(super) fn new(limit: usize) -> Self {
         Self {
-            top: None,
-            arr: [U256::default(); 1024],
+            // A usual frame never holds more than the default limit of 1024 items.
+            arr: Vec::with_capacity(limit.min(1024)),
+            limit,
         }
     }
 
+    /// The index of the stack's top.
+    fn top(&self) -> Option<usize> {
+        self.arr.len().checked_sub(1)
+    }
+
+    /// The number of items on the stack.
+    pub(super) fn len(&self) -> usize {
+        self.arr.len()
+    }
+
+    /// The items from the top of the stack.
+    #[cfg(feature = "trace-steps")]
+    pub(super) fn iter(&self) -> impl Iterator<Item = &U256> {
+        self.arr.iter().rev()
+    }
+
     pub(super) fn push<T>(&mut self, n: T) -> Result<()>
     where
         U256: UintTryFrom<T>,
     {
         let n = U256::saturating_from(n);
-        log::trace!(
-            "push(n={:02X?}): top={:02X?}, arr={:02X?}",
-            n,
-            self.top,
-            &self.arr[..=self.top.unwrap_or_default()]
-        );
+        log::trace!("push(n={:02X?}): arr={:02X?}", n, self.arr);
 
-        let top = self.top.map_or(0, |t| t + 1);
-
-        let res = if top >= 1024 {
+        let res = if self.arr.len() >= self.limit {
             Err(StackError::StackOverflow)
         } else {
-            self.arr[top] = n;
-            self.top = Some(top);
+            self.arr.push(n);
             Ok(())
         };
 
-        log::trace!(
-            "result: top={:?}, arr={:02X?}",
-            self.top,
-            &self.arr[..=self.top.unwrap_or_default()]
-        );
+        log::trace!("result: arr={:02X?}", self.arr);
         res
     }
 
+    /// Push `values` from the bottom, nothing is pushed if they do not all fit.
+    #[allow(dead_code)] // Only used to set up the tests yet.
+    pub(super) fn push_many(&mut self, values: &[U256]) -> Result<()> {
+        log::trace!("push_many(values={:02X?}): top={:?}", values, self.top());
+
+        if self.arr.len() + values.len() > self.limit {
+            return Err(StackError::StackOverflow);
+        }
+        self.arr.extend_from_slice(values);
+        Ok(())
+    }
+
     pub(super) fn pop(&mut self) -> Result<U256> {
-        log::trace!(
-            "pop(): top={:?}, arr={:02X?}",
-            self.top,
-            &self.arr[..=self.top.unwrap_or_default()]
-        );
+        log::trace!("pop(): arr={:02X?}", self.arr);
 
-        let res = match self.top {
-            None => Err(StackError::NotEnoughValuesOnStack),
-            Some(top) => {
-                let value = self.arr[top];
-                self.top = if top > 0 { Some(top - 1) } else { None };
-                Ok(value)
-            }
-        };
+        let res = self.arr.pop().ok_or(StackError::NotEnoughValuesOnStack);
 
-        log::trace!(
-            "result: top={:?}, arr={:02X?}, res={:02X?}",
-            self.top,
-            &self.arr[..=self.top.unwrap_or_default()],
-            res
-        );
+        log::trace!("result: arr={:02X?}, res={:02X?}", self.arr, res);
         res
     }
 
+    /// Push a copy of the `n`th item from the top, starting at 1.
     pub(super) fn dup(&mut self, n: usize) -> Result<()> {
-        let index_to_dup = n - 1;
-        if self.top.is_none() || self.top.expect("safe") < index_to_dup {
-            Err(StackError::NotEnoughValuesOnStack)
-        } else {
-            let value = self.arr[self.top.expect("safe") - index_to_dup];
-            self.push(value)
+        match n.checked_sub(1).filter(|i| *i < self.len()) {
+            None => Err(StackError::NotEnoughValuesOnStack),
+            Some(i) => self.push(self.arr[self.len() - 1 - i]),
         }
     }
 
+    /// Exchange the `n`th item below the top with the `m`th item below it, starting at 1.
+    pub(super) fn exchange(&mut self, n: usize, m: usize) -> Result<()> {
+        match (self.top(), n.checked_add(m)) {
+            (Some(top), Some(depth)) if n > 0 && m > 0 && depth <= top => {
+                self.arr.swap(top - n, top - depth);
+                Ok(())
+            }
+            _ => Err(StackError::NotEnoughValuesOnStack),
+        }
+    }
+
+    /// Exchange the top item with the `n`th item below it, starting at 1.
     pub(super) fn swap(&mut self, n: usize) -> Result<()> {
-        if self.top.is_none() || self.top.expect("safe") < n {
-            Err(StackError::NotEnoughValuesOnStack)
-        } else {
-            let top = self.top.expect("safe");
-            let temp = self.arr[top - n];
-            self.arr[top - n] = self.arr[top];
-            self.arr[top] = temp;
-            Ok(())
+        match self.top() {
+            Some(top) if n > 0 && n <= top => {
+                self.arr.swap(top - n, top);
+                Ok(())
+            }
+            _ => Err(StackError::NotEnoughValuesOnStack),
         }
     }
 }
@@ -115,13 +125,13 @@ impl Stack {
 pub(crate) struct StackResult {
     /// The index of the stack's top.
     top: Option<usize>,
-    arr: [U256; 1024],
+    arr: Vec<U256>,
 }
 
 impl From<Stack> for StackResult {
     fn from(stack: Stack) -> Self {
         Self {
-            top: stack.top,
+            top: stack.top(),
             arr: stack.arr,
         }
     }
@@ -139,7 +149,8 @@ impl From<&StackResult> for Box<[U256]> {
             None => Box::default(),
             Some(top) => s
                 .arr
-                .into_iter()
+                .iter()
+                .copied()
                 .take(top + 1)
                 .rev()
                 .collect::<Vec<_>>()
@@ -147,3 +158,117 @@ impl From<&StackResult> for Box<[U256]> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_push_up_to_1024_values() {
+        let mut stack = Stack::new(1024);
+        for n in 0..1024 {
+            assert!(stack.push(U256::from(n)).is_ok());
+        }
+        assert_eq!(Some(1023), stack.top());
+        assert!(matches!(
+            stack.push(U256::from(1024)),
+            Err(StackError::StackOverflow)
+        ));
+        // The stack is left untouched.
+        assert_eq!(Some(1023), stack.top());
+        assert_eq!(U256::from(1023), stack.pop().unwrap());
+    }
+
+    #[test]
+    fn should_overflow_when_dup_on_full_stack() {
+        let mut stack = Stack::new(1024);
+        for n in 0..1024 {
+            stack.push(U256::from(n)).unwrap();
+        }
+        assert!(matches!(stack.dup(1), Err(StackError::StackOverflow)));
+        assert!(stack.swap(1).is_ok());
+    }
+
+    #[test]
+    fn should_reject_dup_and_swap_beyond_the_stack() {
+        let mut stack = Stack::new(1024);
+        stack
+            .push_many(&[U256::from(1), U256::from(2), U256::from(3)])
+            .unwrap();
+        for n in [0, 4, 20, usize::MAX] {
+            assert!(matches!(
+                stack.dup(n),
+                Err(StackError::NotEnoughValuesOnStack)
+            ));
+        }
+        for n in [0, 3, 20, usize::MAX] {
+            assert!(matches!(
+                stack.swap(n),
+                Err(StackError::NotEnoughValuesOnStack)
+            ));
+        }
+        for (n, m) in [(0, 1), (1, 0), (1, 2), (usize::MAX, 1)] {
+            assert!(matches!(
+                stack.exchange(n, m),
+                Err(StackError::NotEnoughValuesOnStack)
+            ));
+        }
+        // The stack is left untouched.
+        assert_eq!(Some(2), stack.top());
+
+        stack.dup(3).unwrap();
+        stack.swap(2).unwrap();
+        assert_eq!(U256::from(2), stack.pop().unwrap());
+        assert_eq!(U256::from(3), stack.pop().unwrap());
+        assert_eq!(U256::from(1), stack.pop().unwrap());
+        assert_eq!(U256::from(1), stack.pop().unwrap());
+    }
+
+    #[test]
+    fn should_push_many_values_atomically() {
+        let mut stack = Stack::new(1024);
+        let values = (0..1020).map(U256::from).collect::<Vec<_>>();
+        assert!(stack.push_many(&values).is_ok());
+        assert_eq!(Some(1019), stack.top());
+        assert_eq!(U256::from(1019), stack.pop().unwrap());
+        stack.push(U256::from(1019)).unwrap();
+
+        let values = (1020..1030).map(U256::from).collect::<Vec<_>>();
+        assert!(matches!(
+            stack.push_many(&values),
+            Err(StackError::StackOverflow)
+        ));
+        // The stack is left untouched.
+        assert_eq!(Some(1019), stack.top());
+        assert_eq!(U256::from(1019), stack.pop().unwrap());
+
+        // An empty slice on an empty stack.
+        let mut stack = Stack::new(1024);
+        assert!(stack.push_many(&[]).is_ok());
+        assert_eq!(None, stack.top());
+    }
+
+    #[test]
+    fn should_overflow_at_a_custom_limit() {
+        let mut stack = Stack::new(16);
+        for n in 0..16 {
+            assert!(stack.push(U256::from(n)).is_ok());
+        }
+        assert!(matches!(
+            stack.push(U256::from(16)),
+            Err(StackError::StackOverflow)
+        ));
+        assert_eq!(Some(15), stack.top());
+    }
+
+    #[test]
+    fn should_allocate_the_items_as_they_are_pushed() {
+        let mut stack = Stack::new(usize::MAX);
+        assert!(stack.arr.capacity() <= 1024);
+        for n in 0..2000 {
+            stack.push(U256::from(n)).unwrap();
+        }
+        assert_eq!(2000, stack.len());
+        assert_eq!(U256::from(1999), stack.pop().unwrap());
+    }
+}
@@ -0,0 +1,194 @@
+use super::code::{Code, Opcode};
+use crate::types::Spec;
+use ruint::aliases::U256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An issue found in a bytecode without executing it.
+pub enum Warning {
+    /// The PUSH immediate data is cut by the end of the code, `actual` of its `expected` bytes
+    /// are present.
+    TruncatedPush {
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// A JUMP or JUMPI to a constant target which is not a JUMPDEST.
+    InvalidJumpTarget { offset: usize, target: U256 },
+    /// An undefined or the designated INVALID opcode.
+    InvalidOpcode { offset: usize, byte: u8 },
+}
+
+impl Warning {
+    /// The offset of the faulty opcode in the bytecode.
+    pub fn offset(&self) -> usize {
+        match self {
+            Warning::TruncatedPush { offset, .. }
+            | Warning::InvalidJumpTarget { offset, .. }
+            | Warning::InvalidOpcode { offset, .. } => *offset,
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::TruncatedPush {
+                offset,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "{:#06X}: PUSH expects {} bytes, found {}",
+                    offset, expected, actual
+                )
+            }
+            Warning::InvalidJumpTarget { offset, target } => {
+                write!(f, "{:#06X}: jump to non JUMPDEST {:#X}", offset, target)
+            }
+            Warning::InvalidOpcode { offset, byte } => {
+                write!(f, "{:#06X}: invalid opcode {:#04X}", offset, byte)
+            }
+        }
+    }
+}
+
+/// Statically checks the bytecode for the default spec and returns the issues ordered by offset.
+pub fn validate(bytecode: &[u8]) -> Vec<Warning> {
+    let opcodes = Code::opcodes(bytecode, Spec::default(), false);
+    let is_jumpdest = |target: &U256| {
+        usize::try_from(*target)
+            .ok()
+            .and_then(|t| opcodes.get(t))
+            .is_some_and(|o| matches!(o, Some(Opcode::JUMPDEST)))
+    };
+
+    let mut warnings = vec![];
+    // The value of the previous opcode if it was a PUSH.
+    let mut pushed = None;
+    for (offset, opcode) in opcodes.iter().enumerate() {
+        let Some(opcode) = opcode else {
+            continue;
+        };
+        match opcode {
+            Opcode::PUSH(n, _) if offset + n >= bytecode.len() => {
+                warnings.push(Warning::TruncatedPush {
+                    offset,
+                    expected: *n,
+                    actual: bytecode.len() - offset - 1,
+                });
+            }
+            Opcode::JUMP | Opcode::JUMPI => match pushed {
+                Some(target) if !is_jumpdest(&target) => {
+                    warnings.push(Warning::InvalidJumpTarget { offset, target });
+                }
+                _ => {}
+            },
+            Opcode::INVALID => {
+                warnings.push(Warning::InvalidOpcode {
+                    offset,
+                    byte: bytecode[offset],
+                });
+            }
+            _ => {}
+        }
+        pushed = match opcode {
+            Opcode::PUSH(_, value) => Some(*value),
+            _ => None,
+        };
+    }
+    warnings
+}
+
+/// The offsets of the valid JUMPDEST for the default spec, the ones in PUSH data excluded.
+pub fn jump_destinations(bytecode: &[u8]) -> Vec<usize> {
+    Code::opcodes(bytecode, Spec::default(), false)
+        .iter()
+        .enumerate()
+        .filter(|(_, o)| matches!(o, Some(Opcode::JUMPDEST)))
+        .map(|(offset, _)| offset)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_accept_valid_code() {
+        // PUSH1 4 JUMP INVALID JUMPDEST STOP
+        let raw = hex::decode("600456FE5B00").unwrap();
+        assert_eq!(
+            vec![Warning::InvalidOpcode {
+                offset: 3,
+                byte: 0xFE
+            }],
+            validate(&raw)
+        );
+        // PUSH1 3 JUMP JUMPDEST
+        assert!(validate(&hex::decode("6003565B").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn should_report_issues() {
+        // PUSH1 0 PUSH1 3 JUMPI 0x0C PUSH2 0x01
+        let raw = hex::decode("60006003570C6101").unwrap();
+        assert_eq!(
+            vec![
+                Warning::InvalidJumpTarget {
+                    offset: 4,
+                    target: U256::from(3)
+                },
+                Warning::InvalidOpcode {
+                    offset: 5,
+                    byte: 0x0C
+                },
+                Warning::TruncatedPush {
+                    offset: 6,
+                    expected: 2,
+                    actual: 1
+                },
+            ],
+            validate(&raw)
+        );
+    }
+
+    #[test]
+    fn should_report_a_truncated_push32() {
+        // PUSH32 0xAABBCC
+        let raw = hex::decode("7FAABBCC").unwrap();
+        assert_eq!(
+            vec![Warning::TruncatedPush {
+                offset: 0,
+                expected: 32,
+                actual: 3
+            }],
+            validate(&raw)
+        );
+        assert_eq!(
+            "0x0000: PUSH expects 32 bytes, found 3",
+            validate(&raw)[0].to_string()
+        );
+    }
+
+    #[test]
+    fn should_not_jump_into_push_data() {
+        // PUSH1 0x5B PUSH1 1 JUMP
+        let raw = hex::decode("605B600156").unwrap();
+        assert_eq!(
+            vec![Warning::InvalidJumpTarget {
+                offset: 4,
+                target: U256::from(1)
+            }],
+            validate(&raw)
+        );
+    }
+
+    #[test]
+    fn should_list_the_jump_destinations() {
+        // JUMPDEST PUSH2 0x5B5B JUMPDEST PUSH1 0x5B 0x5B
+        let raw = hex::decode("5B615B5B5B605B5B").unwrap();
+        assert_eq!(vec![0, 4, 7], jump_destinations(&raw));
+        assert!(jump_destinations(&[]).is_empty());
+    }
+}
@@ -1,14 +1,32 @@
 use ruint::aliases::U256;
+use serde::Serialize;
 
 mod execution;
 pub mod types;
 use execution::*;
+pub use execution::{jump_destinations, validate, HaltReason, Opcode, Step, Stepper, Warning};
 use types::*;
 
+#[derive(Debug, Serialize)]
 pub struct TestResult {
     pub stack: Box<[U256]>,
     pub logs: Box<[LogResult]>,
     pub success: bool,
+    #[serde(rename = "return", with = "hex::serde")]
+    pub return_data: Box<[u8]>,
+    /// The memory of the transaction frame when it halted.
+    #[serde(skip)]
+    pub memory: Box<[u8]>,
+    pub gas_used: u64,
+    /// The executed opcodes with their pc, when recorded.
+    #[serde(skip)]
+    pub trace: Box<[(usize, Opcode)]>,
+    /// Tells an explicit STOP from an empty RETURN.
+    #[serde(skip)]
+    pub halt_reason: HaltReason,
+    /// The address of the contract deployed by a creation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_address: Option<Address>,
 }
 
 impl<'a> From<EVMResult> for TestResult {
@@ -17,14 +35,167 @@ impl<'a> From<EVMResult> for TestResult {
             stack: result.stack().into(),
             logs: result.logs().to_owned(),
             success: result.status(),
+            return_data: result.return_data().clone(),
+            memory: result.memory().into(),
+            gas_used: result.gas_used(),
+            trace: result.trace().into(),
+            halt_reason: result.halt_reason(),
+            created_address: result.created_address().cloned(),
         }
     }
 }
 
+impl TestResult {
+    /// The result of an invalid transaction, which does not execute any code.
+    fn invalid() -> Self {
+        Self {
+            stack: Box::new([]),
+            logs: Box::new([]),
+            success: false,
+            return_data: Box::new([]),
+            memory: Box::new([]),
+            gas_used: 0,
+            trace: Box::new([]),
+            halt_reason: HaltReason::Error,
+            created_address: None,
+        }
+    }
+
+    /// The stack, the memory and the return data formatted like the `evm run --json` tool of
+    /// geth, to compare the results of both implementations.
+    /// The stack is listed from its bottom and its values are not padded.
+    pub fn to_geth_json(&self) -> serde_json::Value {
+        let stack = self.stack.iter().rev().map(|v| {
+            // The hex formatting of `U256` is always padded to 64 digits.
+            let digits = format!("{:x}", v);
+            match digits.trim_start_matches('0') {
+                "" => "0x0".to_string(),
+                digits => format!("0x{}", digits),
+            }
+        });
+        serde_json::json!({
+            "stack": stack.collect::<Vec<_>>(),
+            "memory": format!("0x{}", hex::encode(&self.memory)),
+            "return": format!("0x{}", hex::encode(&self.return_data)),
+        })
+    }
+}
+
 impl Transaction {
-    pub fn process<'a>(&'a self, env: &'a mut Environment<'a>) -> TestResult {
+    pub fn process(&self, env: &mut Environment) -> TestResult {
+        self.process_message(env, None)
+    }
+
+    /// Like `process`, the frame of the transaction is given to `step` before executing its
+    /// code, to run it until a chosen step then inspect or resume it.
+    /// The frame is executed to its end once `step` returns.
+    pub fn process_stepping(
+        &self,
+        env: &mut Environment,
+        mut step: impl FnMut(Stepper<'_>),
+    ) -> TestResult {
+        self.process_message(env, Some(&mut step))
+    }
+
+    fn process_message(
+        &self,
+        env: &mut Environment,
+        step: Option<&mut dyn FnMut(Stepper<'_>)>,
+    ) -> TestResult {
+        let intrinsic_gas = self.intrinsic_gas();
+        // A `U256::MAX` gas limit is not metered.
+        let gas = match *self.gas() {
+            U256::MAX => U256::MAX,
+            gas => match gas.checked_sub(U256::from(intrinsic_gas)) {
+                Some(gas) => gas,
+                // The transaction cannot pay for itself.
+                None => return TestResult::invalid(),
+            },
+        };
+        // The sender must afford the whole gas limit and the value upfront.
+        if self.upfront_cost() > *env.state().get_account(self.from()).balance() {
+            return TestResult::invalid();
+        }
+
+        // The whole transaction is undone if the sender cannot pay the fees.
+        let checkpoint = env.state_mut().checkpoint();
+        // The step limit is shared by all the frames of the transaction.
+        env.reset_steps();
+        // ORIGIN is the sender of the transaction in every frame.
+        env.set_caller(self.from().clone());
         let data = Calldata::new(self.data());
-        let message = Message::new(self.from(), self.to(), self.gas(), self.value(), &data);
-        Message::process(message, env).into()
+        let nonce = *env.state().get_account(self.from()).nonce();
+        let message = Message::new(self.from(), &nonce, self.to(), &gas, self.value(), &data);
+        let result = Message::process_stepping(message, env, step);
+        let gas_refund = result.gas_refund();
+        let mut result: TestResult = result.into();
+        result.gas_used += intrinsic_gas;
+        let destroyed = env.state_mut().take_destroyed();
+        result.gas_used -=
+            execution::refund(destroyed.len(), gas_refund, result.gas_used, env.spec());
+        let result = match self.pay_fees(env, result.gas_used) {
+            Ok(()) => {
+                env.state_mut().commit(checkpoint).expect("safe");
+                result
+            }
+            Err(e) => {
+                log::warn!("process(): the sender cannot pay the fees: {}", e);
+                env.state_mut().revert_to(checkpoint).expect("safe");
+                TestResult::invalid()
+            }
+        };
+        env.state_mut().clear_transient_storage();
+        env.state_mut().clear_original_storage();
+        result
+    }
+
+    /// Like `process`, also returning a copy of the state after the transaction.
+    /// It can be given to the environment of the next transaction with `Environment::set_state`.
+    pub fn process_with_state(&self, env: &mut Environment) -> (TestResult, State) {
+        let result = self.process(env);
+        (result, env.state().clone())
+    }
+
+    /// Process the transaction like an `eth_call`, on the state with `overrides` applied.
+    /// The state is left untouched.
+    pub fn simulate(&self, env: &mut Environment, overrides: StateBuilder) -> TestResult {
+        let checkpoint = env.state_mut().checkpoint();
+        overrides.apply(env.state_mut());
+        let result = self.process(env);
+        env.state_mut().revert_to(checkpoint).expect("safe");
+        result
+    }
+
+    /// The maximum the sender can pay, `gas * max_fee + value`.
+    fn upfront_cost(&self) -> U256 {
+        let gas_cost = match *self.gas() {
+            U256::MAX => U256::ZERO,
+            gas => gas.saturating_mul(*self.gas_price()),
+        };
+        gas_cost.saturating_add(*self.value())
+    }
+
+    /// The sender pays the gas used, the coinbase receives the priority fee and
+    /// the base fee is burnt (EIP-1559).
+    /// It can only fail for an unmetered transaction, whose gas is not checked upfront.
+    fn pay_fees(&self, env: &mut Environment, gas_used: u64) -> Result<(), StateError> {
+        let gas_used = U256::from(gas_used);
+        let price = self.effective_gas_price(env.base_fee_per_gas());
+        let base_fee = match SpecConfig::for_fork(env.spec()).base_fee {
+            true => env.base_fee_per_gas_value().min(price),
+            false => U256::ZERO,
+        };
+        let coinbase = env.coinbase().clone();
+        let state = env.state_mut();
+        // Free transactions do not touch the sender nor the coinbase.
+        let priority_fee = gas_used.saturating_mul(price - base_fee);
+        if priority_fee != U256::ZERO {
+            state.send_eth(self.from(), &coinbase, &priority_fee)?;
+        }
+        let burnt = gas_used.saturating_mul(base_fee);
+        if burnt != U256::ZERO {
+            state.burn_eth(self.from(), &burnt)?;
+        }
+        Ok(())
     }
 }
@@ -13,10 +13,13 @@
  * to Rust, implement EVM in another programming language first.
  */
 
-use evm::types::{Account, Address, Environment, LogResult, State, Transaction};
+use evm::types::{Address, Environment, LogResult, State, Transaction};
 use ruint::{aliases::U256, uint};
 use serde::{Deserialize, Deserializer};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug, Deserialize)]
 struct Evmtest {
@@ -34,19 +37,27 @@ struct Evmtest {
 
 #[derive(Debug, Deserialize, Clone, Default)]
 struct Block {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_u256")]
     basefee: U256,
     #[serde(default)]
     coinbase: Address,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_u256")]
     chainid: U256,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_u256")]
     gaslimit: U256,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_u256")]
     difficulty: U256,
-    #[serde(default)]
+    #[serde(
+        default,
+        alias = "mixHash",
+        deserialize_with = "deserialize_u256_option"
+    )]
+    prevrandao: Option<U256>,
+    #[serde(default, deserialize_with = "deserialize_u256")]
+    blobbasefee: U256,
+    #[serde(default, deserialize_with = "deserialize_u256")]
     number: U256,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_u256")]
     timestamp: U256,
 }
 
@@ -58,16 +69,17 @@ struct Tx {
     origin: Address,
     #[serde(default, with = "::serde_with::rust::double_option")]
     to: Option<Option<Address>>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_u256")]
     value: U256,
     #[serde(with = "hex::serde", default)]
     data: Vec<u8>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_u256")]
     gasprice: U256,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 struct AccountTest {
+    #[serde(default, deserialize_with = "deserialize_u256_option")]
     balance: Option<U256>,
     code: Option<Code>,
 }
@@ -89,26 +101,136 @@ where
     Ok(opt.unwrap_or_default())
 }
 
+/// A number written as a `0x` hexadecimal or decimal string, or as a JSON integer.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Number {
+    String(String),
+    Integer(u64),
+}
+
+impl TryFrom<Number> for U256 {
+    type Error = String;
+
+    fn try_from(number: Number) -> Result<Self, Self::Error> {
+        match number {
+            // Parses `0x` hexadecimal and decimal strings.
+            Number::String(s) => s.parse().map_err(|e| format!("invalid number {s}: {e}")),
+            Number::Integer(n) => Ok(U256::from(n)),
+        }
+    }
+}
+
+fn deserialize_u256<'de, D>(deserializer: D) -> Result<U256, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    U256::try_from(Number::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_u256_option<'de, D>(deserializer: D) -> Result<Option<U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<Number>::deserialize(deserializer)?
+        .map(U256::try_from)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_u256_vec<'de, D>(deserializer: D) -> Result<Vec<U256>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<Number>::deserialize(deserializer)?
+        .into_iter()
+        .map(U256::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(serde::de::Error::custom)
+}
+
 #[derive(Debug, Deserialize)]
 struct Expect {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_u256_vec")]
     stack: Vec<U256>,
     #[serde(default)]
     logs: Vec<LogResult>,
     success: bool,
-    // #[serde(rename = "return")]
-    // ret: Option<String>,
+    #[serde(rename = "return")]
+    ret: Option<String>,
+}
+
+/// A field of a test result different from the expected one.
+struct Mismatch {
+    field: &'static str,
+    expected: String,
+    actual: String,
+}
+
+/// A failed test and all its mismatching fields.
+struct FailureReport {
+    name: String,
+    asm: String,
+    hint: String,
+    mismatches: Vec<Mismatch>,
+}
+
+impl std::fmt::Display for FailureReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Failed: {}", self.name)?;
+        writeln!(f, "Instructions: \n{}\n", self.asm)?;
+        for mismatch in &self.mismatches {
+            writeln!(f, "Expected {}: {}", mismatch.field, mismatch.expected)?;
+            writeln!(f, "Actual {}: {}\n", mismatch.field, mismatch.actual)?;
+        }
+        writeln!(f, "Hint: {}", self.hint)
+    }
+}
+
+/// The course tests followed by the tests specific to this implementation.
+const FIXTURES: [&str; 2] = ["../evm.json", "fixtures/harness.json"];
+
+/// The `*.json` fixture files of `path`, a file or a directory searched recursively.
+fn fixture_files(path: &Path) -> Vec<PathBuf> {
+    if !path.is_dir() {
+        return vec![path.to_owned()];
+    }
+    let mut entries = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    entries
+        .into_iter()
+        .filter(|entry| entry.is_dir() || entry.extension().is_some_and(|ext| ext == "json"))
+        .flat_map(|entry| fixture_files(&entry))
+        .collect()
 }
 
 fn main() {
     env_logger::init();
 
-    let text = std::fs::read_to_string("../evm.json").unwrap();
-    let deserializer = &mut serde_json::Deserializer::from_str(&text);
-    let res: Result<Vec<Evmtest>, _> = serde_path_to_error::deserialize(deserializer);
-    let data = res.unwrap();
+    // Run the given fixture files or directories, or the default fixtures.
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let paths = if args.is_empty() {
+        FIXTURES.iter().map(|path| path.to_string()).collect()
+    } else {
+        args
+    };
+
+    let data = paths
+        .iter()
+        .flat_map(|path| fixture_files(Path::new(path)))
+        .flat_map(|path| {
+            let text = std::fs::read_to_string(path).unwrap();
+            let deserializer = &mut serde_json::Deserializer::from_str(&text);
+            let res: Result<Vec<Evmtest>, _> = serde_path_to_error::deserialize(deserializer);
+            res.unwrap()
+        })
+        .collect::<Vec<_>>();
 
     let total = data.len();
+    let mut failures = vec![];
 
     let default_origin: Address = uint!(0x1E79B045DC29EAE9FDC69673C9DCD7C53E5E159D_U160).into();
     let default_caller: Address = uint!(0x0000000000000000000000000000000000001337_U160).into();
@@ -131,7 +253,8 @@ fn main() {
         };
         let transaction = Transaction::new(
             test.tx.gasprice,
-            U256::default(),
+            // The tests do not support gas yet.
+            U256::MAX,
             from.clone(),
             to.clone(),
             test.tx.value.clone(),
@@ -139,30 +262,28 @@ fn main() {
         );
 
         // Setup the chain state.
-        let mut accounts = test
+        let mut builder = State::builder();
+        for (address, account) in test.state.clone() {
+            if let Some(balance) = account.balance {
+                builder = builder.fund(address.clone(), balance);
+            }
+            if let Some(code) = account.code {
+                builder = builder.deploy(address, code.bin);
+            }
+        }
+        // Give from the ETH it sends, on top of its balance in the test.
+        let balance = test
             .state
-            .clone()
-            .into_iter()
-            .map(|(k, v)| {
-                (
-                    k.clone(),
-                    Account::new(v.balance, v.code.map(|c| c.bin.into_boxed_slice())),
-                )
-            })
-            .collect::<HashMap<Address, Account>>();
-        // Give from ETH.
-        accounts.insert(from, Account::new(Some(test.tx.value), None));
-        // Code to execute should be the to account code.
-        accounts.insert(
-            to.clone().expect("safe"),
-            Account::new(
-                accounts
-                    .get(&to.expect("safe"))
-                    .map(|a| a.balance().clone()),
-                Some(test.code.bin.clone().into_boxed_slice()),
-            ),
-        );
-        let state = State::new(accounts);
+            .get(&from)
+            .and_then(|account| account.balance)
+            .unwrap_or_default();
+        builder = builder.fund(from, balance.saturating_add(test.tx.value));
+        // Code to execute should be the to account code, unless it is only defined by the state.
+        let to = to.expect("safe");
+        if !test.code.bin.is_empty() || !test.state.contains_key(&to) {
+            builder = builder.deploy(to.clone(), test.code.bin.clone());
+        }
+        let state = builder.build();
         // Setup the chain environment.
         let mut env = Environment::new(
             &caller,
@@ -178,47 +299,73 @@ fn main() {
             &test.block.chainid,
         );
 
-        let result = transaction.process(&mut env);
+        env.set_prevrandao(test.block.prevrandao);
+        env.set_blob_base_fee(test.block.blobbasefee);
 
-        let is_expected_status = result.success == test.expect.success;
-
-        let is_expected_stack = test.expect.stack == result.stack.to_vec();
-        let is_expected_logs = test.expect.logs == result.logs.to_vec();
+        let result = transaction.process(&mut env);
 
-        let test_passed = is_expected_status && is_expected_stack && is_expected_logs;
+        let format_stack = |stack: &[U256]| {
+            let values = stack.iter().map(|v| format!("  {:#X},\n", v));
+            format!("[\n{}]", values.collect::<String>())
+        };
+        let format_logs = |logs: &[LogResult]| {
+            let values = logs.iter().map(|v| format!("  {:?},\n", v));
+            format!("[\n{}]", values.collect::<String>())
+        };
 
-        if !test_passed {
-            println!("Instructions: \n{}\n", test.code.asm);
+        let mut mismatches = vec![];
+        if result.success != test.expect.success {
+            mismatches.push(Mismatch {
+                field: "success",
+                expected: format!("{:?}", test.expect.success),
+                actual: format!("{:?}", result.success),
+            });
+        }
+        if test.expect.stack != result.stack.to_vec() {
+            mismatches.push(Mismatch {
+                field: "stack",
+                expected: format_stack(&test.expect.stack),
+                actual: format_stack(&result.stack),
+            });
+        }
+        if test.expect.logs != result.logs.to_vec() {
+            mismatches.push(Mismatch {
+                field: "logs",
+                expected: format_logs(&test.expect.logs),
+                actual: format_logs(&result.logs),
+            });
+        }
+        // Only check the return data when the test expects one.
+        let return_data = hex::encode(&result.return_data);
+        match &test.expect.ret {
+            Some(ret) if ret.to_lowercase() != return_data => mismatches.push(Mismatch {
+                field: "return",
+                expected: ret.clone(),
+                actual: return_data,
+            }),
+            _ => {}
+        }
 
-            println!("Expected success: {:?}", test.expect.success);
-            println!("Expected stack: [");
-            for v in &test.expect.stack {
-                println!("  {:#X},", v);
-            }
-            println!("]\n");
-            println!("Expected logs: [");
-            for v in &test.expect.logs {
-                println!("  {:?},", v);
-            }
-            println!("]\n");
+        if mismatches.is_empty() {
+            println!("PASS");
+        } else {
+            println!("FAIL");
+            failures.push(FailureReport {
+                name: test.name.clone(),
+                asm: test.code.asm.clone(),
+                hint: test.hint.clone(),
+                mismatches,
+            });
+        }
+    }
 
-            println!("Actual success: {:?}", result.success);
-            println!("Actual stack: [");
-            for v in result.stack.as_ref() {
-                println!("  {:#X},", v);
-            }
-            println!("]\n");
-            println!("Actual logs: [");
-            for v in result.logs.as_ref() {
-                println!("  {:?},", v);
-            }
-            println!("]\n");
+    for failure in &failures {
+        println!("\n{}", failure);
+    }
 
-            println!("\nHint: {}\n", test.hint);
-            println!("Progress: {}/{}\n\n", index, total);
-            panic!("Test failed");
-        }
-        println!("PASS");
+    println!("Passed: {}/{}", total - failures.len(), total);
+    if !failures.is_empty() {
+        std::process::exit(1);
     }
     println!("Congratulations!");
 }
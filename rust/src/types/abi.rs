@@ -0,0 +1,144 @@
+use ruint::aliases::U256;
+
+use super::{keccak256, Address};
+
+/// The selector of `Error(string)`.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xC3, 0x79, 0xA0];
+
+#[derive(Debug, Clone, PartialEq)]
+/// A function parameter value.
+pub enum Token {
+    Address(Address),
+    Uint(U256),
+    Bool(bool),
+    Bytes(Vec<u8>),
+    String(String),
+}
+
+/// The first 4 bytes of the hash of a function signature, like `transfer(address,uint256)`.
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    <[u8; 4]>::try_from(&hash[..4]).expect("safe")
+}
+
+/// Encode the parameters of a function call.
+/// The static values are in the head, the dynamic ones in the tail after their offset.
+pub fn encode(tokens: &[Token]) -> Vec<u8> {
+    let mut head = vec![];
+    let mut tail = vec![];
+    // Append a dynamic value to the tail and return its offset.
+    let mut append_tail = |bytes: &[u8]| {
+        let offset = tokens.len() * 0x20 + tail.len();
+        tail.extend(encode_bytes(bytes));
+        U256::from(offset)
+    };
+    for token in tokens {
+        let word = match token {
+            Token::Address(address) => <U256 as From<&Address>>::from(address),
+            Token::Uint(value) => *value,
+            Token::Bool(value) => U256::from(*value as u8),
+            Token::Bytes(bytes) => append_tail(bytes),
+            Token::String(s) => append_tail(s.as_bytes()),
+        };
+        head.extend(word.to_be_bytes::<0x20>());
+    }
+    [head, tail].concat()
+}
+
+/// The revert data of `Error(msg)`, as returned by a failing `require(condition, msg)`.
+pub fn encode_revert(msg: &str) -> Vec<u8> {
+    [
+        &ERROR_SELECTOR[..],
+        &encode(&[Token::String(msg.to_owned())]),
+    ]
+    .concat()
+}
+
+/// The message of an `Error(string)` revert data, `None` for any other data.
+pub fn decode_revert_reason(data: &[u8]) -> Option<String> {
+    let encoded = data.strip_prefix(&ERROR_SELECTOR[..])?;
+    let offset = decode_usize(encoded, 0)?;
+    let size = decode_usize(encoded, offset)?;
+    let start = offset.checked_add(0x20)?;
+    let bytes = encoded.get(start..start.checked_add(size)?)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+/// Encode dynamic bytes: their size and their content padded to words.
+fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![];
+    encoded.extend(U256::from(bytes.len()).to_be_bytes::<0x20>());
+    encoded.extend(bytes);
+    encoded.resize(encoded.len().next_multiple_of(0x20), 0x00);
+    encoded
+}
+
+/// Decode the word at `offset` as an usize.
+fn decode_usize(encoded: &[u8], offset: usize) -> Option<usize> {
+    let word = encoded.get(offset..offset.checked_add(0x20)?)?;
+    usize::try_from(U256::try_from_be_slice(word)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_a_revert_reason() {
+        let data = encode_revert("insufficient balance");
+        assert_eq!(4 + 3 * 0x20, data.len());
+        assert_eq!(
+            hex::decode(concat!(
+                "08c379a0",
+                "0000000000000000000000000000000000000000000000000000000000000020",
+                "0000000000000000000000000000000000000000000000000000000000000014",
+                "696e73756666696369656e742062616c616e6365000000000000000000000000",
+            ))
+            .unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn should_round_trip_revert_reasons() {
+        for msg in ["", "insufficient balance", &"x".repeat(0x21)] {
+            assert_eq!(
+                Some(msg.to_owned()),
+                decode_revert_reason(&encode_revert(msg))
+            );
+        }
+        assert_eq!(None, decode_revert_reason(&[]));
+        assert_eq!(None, decode_revert_reason(&ERROR_SELECTOR));
+        // A panic code is not a reason.
+        assert_eq!(
+            None,
+            decode_revert_reason(&hex::decode("4e487b71").unwrap())
+        );
+    }
+
+    #[test]
+    fn should_compute_function_selectors() {
+        assert_eq!(
+            [0xA9, 0x05, 0x9C, 0xBB],
+            selector("transfer(address,uint256)")
+        );
+        assert_eq!(ERROR_SELECTOR, selector("Error(string)"));
+    }
+
+    #[test]
+    fn should_encode_static_and_dynamic_values() {
+        let encoded = encode(&[
+            Token::Uint(U256::from(1)),
+            Token::Bytes(vec![0xAA; 0x21]),
+            Token::Bool(true),
+        ]);
+        let word = |n: usize| U256::try_from_be_slice(&encoded[n * 0x20..(n + 1) * 0x20]).unwrap();
+        assert_eq!(6 * 0x20, encoded.len());
+        assert_eq!(U256::from(1), word(0));
+        // The bytes are after the 3 head words.
+        assert_eq!(U256::from(0x60), word(1));
+        assert_eq!(U256::from(1), word(2));
+        assert_eq!(U256::from(0x21), word(3));
+        assert_eq!(&[0xAA; 0x21], &encoded[4 * 0x20..4 * 0x20 + 0x21]);
+    }
+}
@@ -1,11 +1,12 @@
-use super::U256_DEFAULT;
-use ruint::{aliases::U256, uint};
-use sha3::Digest;
-use std::collections::HashMap;
+use super::{keccak256_u256, EMPTY_CODE_HASH, U256_DEFAULT};
+use ruint::aliases::U256;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use thiserror::Error;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 /// State associated with an address.
+/// The storage slots are sorted, for a reproducible serialization.
 pub enum Account {
     Empty,
     ExternallyOwned {
@@ -15,8 +16,9 @@ pub enum Account {
     Contract {
         nonce: usize,
         balance: U256,
+        #[serde(with = "hex::serde")]
         code: Box<[u8]>,
-        storage: HashMap<U256, U256>,
+        storage: BTreeMap<U256, U256>,
     },
 }
 
@@ -36,7 +38,7 @@ impl Account {
                 nonce: 0,
                 balance: balance.unwrap_or_default(),
                 code: c,
-                storage: HashMap::new(),
+                storage: BTreeMap::new(),
             },
         };
 
@@ -113,6 +115,25 @@ impl Account {
         }
     }
 
+    /// Replace the balance, an empty account becomes externally owned.
+    pub(crate) fn set_balance(self, balance: U256) -> Self {
+        match self {
+            Account::Empty => Self::ExternallyOwned { nonce: 0, balance },
+            Account::ExternallyOwned { nonce, .. } => Self::ExternallyOwned { nonce, balance },
+            Account::Contract {
+                nonce,
+                code,
+                storage,
+                ..
+            } => Self::Contract {
+                nonce,
+                balance,
+                code,
+                storage,
+            },
+        }
+    }
+
     pub fn code(&self) -> &[u8] {
         match self {
             Account::Empty | Account::ExternallyOwned { .. } => &[],
@@ -126,13 +147,13 @@ impl Account {
                 nonce: 0,
                 balance: U256::ZERO,
                 code,
-                storage: HashMap::new(),
+                storage: BTreeMap::new(),
             }),
             Account::ExternallyOwned { nonce, balance } => Ok(Self::Contract {
                 nonce,
                 balance,
                 code,
-                storage: HashMap::new(),
+                storage: BTreeMap::new(),
             }),
             Account::Contract {
                 nonce,
@@ -151,15 +172,16 @@ impl Account {
     pub fn code_hash(&self) -> U256 {
         match self {
             Account::Empty => U256::ZERO,
-            Account::ExternallyOwned { .. } => {
-                uint!(0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470_U256)
-            }
-            Account::Contract { code, .. } => {
-                let mut hasher = sha3::Keccak256::new();
-                hasher.update(code);
-                let hash = hasher.finalize();
-                U256::try_from_be_slice(&hash[..]).expect("safe")
-            }
+            Account::ExternallyOwned { .. } => EMPTY_CODE_HASH,
+            Account::Contract { code, .. } => keccak256_u256(code),
+        }
+    }
+
+    /// The non-zero storage slots of a contract, `None` for other accounts.
+    pub fn storage(&self) -> Option<&BTreeMap<U256, U256>> {
+        match self {
+            Account::Contract { storage, .. } => Some(storage),
+            _ => None,
         }
     }
 
@@ -213,3 +235,30 @@ impl std::fmt::Display for AccountError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_serialize_the_storage_in_slot_order() {
+        let contract = |slots: &[u64]| {
+            let mut account = Account::new(Some(U256::from(1)), Some(vec![0x00].into()));
+            for &slot in slots {
+                account.store(U256::from(slot), U256::from(slot + 1));
+            }
+            serde_json::to_string(&account).unwrap()
+        };
+
+        let json = contract(&[0x20, 1, 0x300, 5]);
+        assert_eq!(json, contract(&[5, 0x300, 1, 0x20]));
+        // The slots are serialized as 32 bytes hexadecimal strings.
+        let position = |slot: u64| {
+            json.find(&format!("\"{:#066x}\":", U256::from(slot)))
+                .unwrap()
+        };
+        assert!(position(1) < position(5));
+        assert!(position(5) < position(0x20));
+        assert!(position(0x20) < position(0x300));
+    }
+}
@@ -1,12 +1,52 @@
 use std::fmt::Debug;
 
 use ruint::aliases::{U160, U256};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-#[derive(Deserialize, Clone, Hash, PartialEq, Eq)]
-#[serde(from = "U160")]
+use super::{keccak256, keccak256_u256};
+
+#[derive(Deserialize, Serialize, Clone, Hash, PartialEq, Eq)]
+#[serde(from = "U160", into = "U160")]
 pub struct Address(#[serde(default)] [u8; 0x14]);
 
+impl Address {
+    pub(crate) const ZERO: Address = Address([0x00; 0x14]);
+
+    /// Like `From<U256>`, but rejects a word whose top 12 bytes are not zero
+    /// instead of masking them.
+    pub fn try_from_word(word: &U256) -> Result<Self> {
+        if word.bit_len() > 160 {
+            return Err(AddressError::DirtyHighBytes(*word));
+        }
+        Ok(Self::from(*word))
+    }
+
+    /// Whether the address is one of the precompiled contracts, from 0x01 to 0x09.
+    pub fn is_precompile(&self) -> bool {
+        self.0[..0x13] == [0x00; 0x13] && (0x01..=0x09).contains(&self.0[0x13])
+    }
+}
+
+/// The address of a contract created by CREATE2 with `salt` and `init_code` (EIP-1014).
+pub fn create2_address(sender: &Address, salt: U256, init_code: &[u8]) -> Address {
+    create2_address_from_hash(sender, salt, keccak256_u256(init_code))
+}
+
+/// Like `create2_address`, with the hash of the initialization code.
+pub fn create2_address_from_hash(sender: &Address, salt: U256, init_code_hash: U256) -> Address {
+    let hash = keccak256(
+        &[
+            &[0xFF][..],
+            &sender.0,
+            &salt.to_be_bytes::<0x20>(),
+            &init_code_hash.to_be_bytes::<0x20>(),
+        ]
+        .concat(),
+    );
+    <[u8; 0x14]>::try_from(&hash[0x0C..]).expect("safe").into()
+}
+
 impl From<[u8; 0x14]> for Address {
     fn from(b: [u8; 0x14]) -> Self {
         Self(b)
@@ -19,6 +59,12 @@ impl From<U160> for Address {
     }
 }
 
+impl From<Address> for U160 {
+    fn from(a: Address) -> Self {
+        U160::from_be_bytes(a.0)
+    }
+}
+
 impl From<U256> for Address {
     fn from(u: U256) -> Self {
         u.wrapping_to::<U160>().into()
@@ -47,3 +93,121 @@ impl Debug for Address {
         )
     }
 }
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AddressError {
+    DirtyHighBytes(U256),
+}
+
+pub(super) type Result<T> = std::result::Result<T, AddressError>;
+
+impl std::fmt::Display for AddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressError::DirtyHighBytes(word) => {
+                write!(f, "the top 12 bytes of {:#X} are not zero", word)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_round_trip_through_u256() {
+        // Pseudo random addresses (xorshift64).
+        let mut seed = 0x2545F4914F6CDD1D_u64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..1000 {
+            let mut bytes = [0x00; 0x14];
+            bytes
+                .iter_mut()
+                .zip((0..3).flat_map(|_| next().to_be_bytes()))
+                .for_each(|(b, r)| *b = r);
+            let address = Address::from(bytes);
+            let value = <U256 as From<&Address>>::from(&address);
+            assert_eq!(address, Address::from(value));
+        }
+    }
+
+    #[test]
+    fn should_mask_the_high_bytes() {
+        let value = U256::MAX;
+        assert_eq!(Address::from([0xFF; 0x14]), Address::from(value));
+
+        let value = U256::from_be_bytes([0xAA; 0x20]);
+        assert_eq!(Address::from([0xAA; 0x14]), Address::from(value));
+        assert_eq!(
+            value & (U256::MAX >> 96),
+            <U256 as From<&Address>>::from(&Address::from(value))
+        );
+    }
+
+    #[test]
+    fn should_recognize_the_precompiles() {
+        let address = |n: u64| Address::from(U256::from(n));
+        assert!(address(0x01).is_precompile());
+        assert!(address(0x04).is_precompile());
+        assert!(address(0x09).is_precompile());
+        assert!(!Address::ZERO.is_precompile());
+        assert!(!address(0x0A).is_precompile());
+        assert!(!address(0x0104).is_precompile());
+    }
+
+    #[test]
+    fn should_compute_the_create2_addresses() {
+        use ruint::uint;
+
+        // The examples of EIP-1014.
+        let zero = Address::ZERO;
+        let deadbeef: Address = uint!(0xdeadbeef00000000000000000000000000000000_U160).into();
+        let short: Address = uint!(0x00000000000000000000000000000000deadbeef_U160).into();
+        let feed = uint!(0xfeed000000000000000000000000000000000000_U256);
+        let cafebabe = uint!(0xcafebabe_U256);
+        let examples: [(&Address, U256, &str, U160); 7] = [
+            (&zero, U256::ZERO, "00", uint!(0x4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38_U160)),
+            (&deadbeef, U256::ZERO, "00", uint!(0xB928f69Bb1D91Cd65274e3c79d8986362984fDA3_U160)),
+            (&deadbeef, feed, "00", uint!(0xD04116cDd17beBE565EB2422F2497E06cC1C9833_U160)),
+            (&zero, U256::ZERO, "deadbeef", uint!(0x70f2b2914A2a4b783FaEFb75f459A580616Fcb5e_U160)),
+            (&short, cafebabe, "deadbeef", uint!(0x60f3f640a8508fC6a86d45DF051962668E1e8AC7_U160)),
+            (
+                &short,
+                cafebabe,
+                "deadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeef",
+                uint!(0x1d8bfDC5D46DC4f61D6b6115972536eBE6A8854C_U160),
+            ),
+            (&zero, U256::ZERO, "", uint!(0xE33C0C7F7df4809055C3ebA6c09CFe4BaF1BD9e0_U160)),
+        ];
+        for (sender, salt, init_code, expected) in examples {
+            let init_code = hex::decode(init_code).unwrap();
+            let expected = Address::from(expected);
+            assert_eq!(expected, create2_address(sender, salt, &init_code));
+            let hash = keccak256_u256(&init_code);
+            assert_eq!(expected, create2_address_from_hash(sender, salt, hash));
+        }
+    }
+
+    #[test]
+    fn should_reject_dirty_high_bytes_in_strict_mode() {
+        let clean = U256::from_be_bytes([0xAA; 0x20]) >> 96;
+        assert_eq!(
+            Ok(Address::from([0xAA; 0x14])),
+            Address::try_from_word(&clean)
+        );
+
+        let dirty = clean | (U256::from(1) << 160);
+        assert_eq!(
+            Err(AddressError::DirtyHighBytes(dirty)),
+            Address::try_from_word(&dirty)
+        );
+        // The interpreter still masks them.
+        assert_eq!(Address::from([0xAA; 0x14]), Address::from(dirty));
+    }
+}
@@ -91,3 +91,70 @@ impl TryFrom<&U256> for Bytesize {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+/// Borrowed bytes, like the calldata or the code, read as if they were followed by zeros.
+pub(crate) struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self(bytes)
+    }
+
+    /// The `size` bytes at `offset`, the bytes past the end are zero.
+    pub(crate) fn load_padded(&self, offset: usize, size: usize) -> Box<[u8]> {
+        let mut bytes = vec![0x00; size];
+        // Past the end, including an overflowing offset, the bytes are zero.
+        if let Some(available) = self.0.get(offset..) {
+            let len = available.len().min(size);
+            bytes[..len].copy_from_slice(&available[..len]);
+        }
+        bytes.into_boxed_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_load_padded_bytes() {
+        let bytes = Bytes::new(&[0x01, 0x02, 0x03]);
+        assert_eq!(&[0x02, 0x03], &bytes.load_padded(1, 2)[..]);
+        assert_eq!(&[0x02, 0x03, 0x00, 0x00], &bytes.load_padded(1, 4)[..]);
+        assert_eq!(&[0x00; 2], &bytes.load_padded(3, 2)[..]);
+        assert!(bytes.load_padded(1, 0).is_empty());
+        assert!(Bytes::new(&[]).load_padded(0, 0).is_empty());
+    }
+
+    #[test]
+    fn should_load_zeros_at_an_overflowing_offset() {
+        let bytes = Bytes::new(&[0xFF; 0x20]);
+        assert_eq!(
+            &[0x00; 0x10],
+            &bytes.load_padded(usize::MAX - 0x08, 0x10)[..]
+        );
+        assert_eq!(&[0x00; 0x20], &bytes.load_padded(usize::MAX, 0x20)[..]);
+    }
+
+    #[test]
+    fn should_convert_bytesize_up_to_max() {
+        assert_eq!(Bytesize::try_from(&U256::ZERO).unwrap(), Bytesize::MIN);
+        assert_eq!(
+            Bytesize::try_from(&U256::from(0x1F)).unwrap(),
+            Bytesize::MAX
+        );
+    }
+
+    #[test]
+    fn should_fail_to_convert_bytesize_above_max() {
+        assert!(matches!(
+            Bytesize::try_from(&U256::from(0x20)),
+            Err(FromUintError::Overflow(_, _, Bytesize::MAX))
+        ));
+        assert!(matches!(
+            Bytesize::try_from(&U256::MAX),
+            Err(FromUintError::Overflow(_, _, Bytesize::MAX))
+        ));
+    }
+}
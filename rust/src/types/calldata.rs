@@ -1,4 +1,4 @@
-use super::Bytesize;
+use super::Bytes;
 
 #[derive(Debug)]
 pub struct Calldata<'a>(&'a [u8]);
@@ -9,20 +9,12 @@ impl<'a> Calldata<'a> {
     }
 
     pub(crate) fn load(&self, offset: usize, size: usize) -> Box<[u8]> {
-        let mut bytes = vec![0x00; size];
-        for n in 0..size {
-            let b = self.0.get(offset + n).unwrap_or(&0);
-            bytes[n] = *b;
-        }
-        bytes.into_boxed_slice()
+        Bytes::new(self.0).load_padded(offset, size)
     }
 
     pub(crate) fn load_word(&self, offset: usize) -> [u8; 0x20] {
         let mut bytes = [0x00; 0x20];
-        for n in 0..=<usize>::from(Bytesize::MAX) {
-            let b = self.0.get(offset + n).unwrap_or(&0);
-            bytes[n] = *b;
-        }
+        bytes.copy_from_slice(&self.load(offset, 0x20));
         bytes
     }
 
@@ -69,4 +61,19 @@ mod test {
                 .unwrap()[..]
         );
     }
+
+    #[test]
+    fn should_load_zero_word_at_overflowing_offset() {
+        let bytes = [0xFF; 0x20];
+        let cd = Calldata::new(&bytes);
+        assert_eq!([0x00; 0x20], cd.load_word(usize::MAX));
+        assert_eq!([0x00; 0x20], cd.load_word(usize::MAX - 0x10));
+    }
+
+    #[test]
+    fn should_load_zeros_at_overflowing_offset() {
+        let bytes = [0xFF; 0x20];
+        let cd = Calldata::new(&bytes);
+        assert_eq!(&[0x00; 0x10], &cd.load(usize::MAX - 0x08, 0x10)[..]);
+    }
 }
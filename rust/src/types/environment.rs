@@ -1,11 +1,53 @@
-use super::{State, U256_DEFAULT};
+use super::{Spec, State, U256_DEFAULT};
 use crate::types::Address;
-use ruint::aliases::U256;
+use ruint::{aliases::U256, uint};
+use std::{cell::Cell, collections::HashMap, fmt::Debug, rc::Rc};
+
+/// A precompiled contract, returns the call success and output from the call data.
+pub type Precompile = Rc<dyn Fn(&[u8]) -> (bool, Vec<u8>)>;
+
+#[derive(Clone, Default)]
+struct Precompiles(HashMap<Address, Precompile>);
+
+impl Debug for Precompiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Block defaults of a known chain.
+struct ChainPreset {
+    chain_id: U256,
+    coinbase: Address,
+    base_fee_per_gas: U256,
+    gas_limit: U256,
+    difficulty: U256,
+}
+
+static MAINNET: ChainPreset = ChainPreset {
+    chain_id: uint!(1_U256),
+    coinbase: Address::ZERO,
+    // 10 gwei.
+    base_fee_per_gas: uint!(10_000_000_000_U256),
+    gas_limit: uint!(30_000_000_U256),
+    // The difficulty is replaced by PREVRANDAO since the merge.
+    difficulty: U256::ZERO,
+};
+
+static SEPOLIA: ChainPreset = ChainPreset {
+    chain_id: uint!(11155111_U256),
+    coinbase: Address::ZERO,
+    // 1 gwei.
+    base_fee_per_gas: uint!(1_000_000_000_U256),
+    gas_limit: uint!(30_000_000_U256),
+    difficulty: U256::ZERO,
+};
 
 #[derive(Debug, Clone)]
 /// Items external to the virtual machine itself, provided by the environment.
 pub struct Environment<'a> {
-    caller: &'a Address,
+    /// The sender of the transaction, pushed by ORIGIN.
+    caller: Address,
     block_hashes: &'a [U256],
     coinbase: &'a Address,
     number: &'a U256,
@@ -16,6 +58,20 @@ pub struct Environment<'a> {
     difficulty: &'a U256,
     state: State,
     chain_id: &'a U256,
+    spec: Spec,
+    precompiles: Precompiles,
+    step_limit: Option<u64>,
+    /// The opcodes the frames of the transaction can still execute, `None` when unlimited.
+    steps: Cell<Option<u64>>,
+    record_trace: bool,
+    prevrandao: Option<U256>,
+    blob_base_fee: U256,
+    blob_versioned_hashes: Vec<U256>,
+    debug: bool,
+    stack_limit: usize,
+    /// The depth of the executing call frame, 0 for the transaction frame.
+    /// Tracked by the staticcall frames too, which only borrow the environment.
+    depth: Cell<usize>,
 }
 
 impl<'a> Environment<'a> {
@@ -33,7 +89,7 @@ impl<'a> Environment<'a> {
         chain_id: &'a U256,
     ) -> Self {
         Self {
-            caller,
+            caller: caller.clone(),
             block_hashes,
             coinbase,
             number,
@@ -44,13 +100,50 @@ impl<'a> Environment<'a> {
             difficulty,
             state,
             chain_id,
+            spec: Spec::default(),
+            precompiles: Precompiles::default(),
+            step_limit: None,
+            steps: Cell::new(None),
+            record_trace: false,
+            prevrandao: None,
+            blob_base_fee: U256::ZERO,
+            blob_versioned_hashes: vec![],
+            debug: false,
+            stack_limit: 1024,
+            depth: Cell::new(0),
         }
     }
 
+    /// An environment at the genesis of a known chain, `None` for other chains.
+    /// Supports mainnet (1) and sepolia (11155111).
+    pub fn for_chain(chain_id: u64, state: State) -> Option<Environment<'static>> {
+        let preset = [&MAINNET, &SEPOLIA]
+            .into_iter()
+            .find(|preset| preset.chain_id == U256::from(chain_id))?;
+        Some(Environment::new(
+            &Address::ZERO,
+            &[],
+            &preset.coinbase,
+            &U256_DEFAULT,
+            &preset.base_fee_per_gas,
+            &preset.gas_limit,
+            &preset.base_fee_per_gas,
+            &U256_DEFAULT,
+            &preset.difficulty,
+            state,
+            &preset.chain_id,
+        ))
+    }
+
     pub fn caller(&self) -> &Address {
         &self.caller
     }
 
+    /// Set by each processed transaction to its sender.
+    pub fn set_caller(&mut self, caller: Address) {
+        self.caller = caller;
+    }
+
     pub fn block_hash(&self, block_number: usize) -> &U256 {
         &self
             .block_hashes
@@ -66,14 +159,32 @@ impl<'a> Environment<'a> {
         &self.number
     }
 
+    pub fn number_value(&self) -> U256 {
+        *self.number
+    }
+
+    /// The block number, clamped to `u64::MAX`.
+    pub fn number_as_u64(&self) -> u64 {
+        self.number.saturating_to()
+    }
+
     pub fn base_fee_per_gas(&self) -> &U256 {
         &self.base_fee_per_gas
     }
 
+    pub fn base_fee_per_gas_value(&self) -> U256 {
+        *self.base_fee_per_gas
+    }
+
     pub fn gas_limit(&self) -> &U256 {
         &self.gas_limit
     }
 
+    /// The block gas limit, clamped to `u64::MAX`.
+    pub fn gas_limit_as_u64(&self) -> u64 {
+        self.gas_limit.saturating_to()
+    }
+
     pub fn gas_price(&self) -> &U256 {
         &self.gas_price
     }
@@ -82,10 +193,49 @@ impl<'a> Environment<'a> {
         &self.time
     }
 
+    pub fn time_value(&self) -> U256 {
+        *self.time
+    }
+
+    /// The block timestamp, clamped to `u64::MAX`.
+    pub fn time_as_u64(&self) -> u64 {
+        self.time.saturating_to()
+    }
+
     pub fn difficulty(&self) -> &U256 {
         &self.difficulty
     }
 
+    pub fn prevrandao(&self) -> Option<&U256> {
+        self.prevrandao.as_ref()
+    }
+
+    /// The beacon chain randomness read by DIFFICULTY since the merge (EIP-4399).
+    pub fn set_prevrandao(&mut self, prevrandao: Option<U256>) {
+        self.prevrandao = prevrandao;
+    }
+
+    pub fn blob_base_fee(&self) -> &U256 {
+        &self.blob_base_fee
+    }
+
+    /// The price of the blob gas of the block (EIP-7516), zero by default.
+    pub fn set_blob_base_fee(&mut self, blob_base_fee: U256) {
+        self.blob_base_fee = blob_base_fee;
+    }
+
+    /// The versioned hash of the blob `index` of the transaction, zero if there is none.
+    pub fn blob_versioned_hash(&self, index: usize) -> &U256 {
+        self.blob_versioned_hashes
+            .get(index)
+            .unwrap_or(&U256_DEFAULT)
+    }
+
+    /// The versioned hashes of the blobs carried by the transaction (EIP-4844).
+    pub fn set_blob_versioned_hashes(&mut self, hashes: Vec<U256>) {
+        self.blob_versioned_hashes = hashes;
+    }
+
     pub fn state(&self) -> &State {
         &self.state
     }
@@ -101,4 +251,136 @@ impl<'a> Environment<'a> {
     pub fn chain_id(&self) -> &U256 {
         &self.chain_id
     }
+
+    pub fn chain_id_value(&self) -> U256 {
+        *self.chain_id
+    }
+
+    pub fn spec(&self) -> Spec {
+        self.spec
+    }
+
+    pub fn set_spec(&mut self, spec: Spec) {
+        self.spec = spec;
+    }
+
+    /// Custom precompiled contracts, executed instead of the accounts code.
+    pub fn set_precompiles(&mut self, precompiles: HashMap<Address, Precompile>) {
+        self.precompiles = Precompiles(precompiles);
+    }
+
+    pub fn precompile(&self, address: &Address) -> Option<&Precompile> {
+        self.precompiles.0.get(address)
+    }
+
+    pub fn step_limit(&self) -> Option<u64> {
+        self.step_limit
+    }
+
+    /// The maximum number of opcodes executed by each transaction, all its call frames
+    /// included, independently of gas.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.step_limit = limit;
+        self.steps.set(limit);
+    }
+
+    /// Give the whole step limit to the next transaction.
+    pub(crate) fn reset_steps(&self) {
+        self.steps.set(self.step_limit);
+    }
+
+    /// Count an executed opcode, false when the step limit is reached.
+    pub(crate) fn take_step(&self) -> bool {
+        match self.steps.get() {
+            None => true,
+            Some(0) => false,
+            Some(steps) => {
+                self.steps.set(Some(steps - 1));
+                true
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn steps(&self) -> Option<u64> {
+        self.steps.get()
+    }
+
+    pub fn record_trace(&self) -> bool {
+        self.record_trace
+    }
+
+    /// Record the opcodes executed by the transaction frame in its result trace.
+    pub fn set_record_trace(&mut self, record: bool) {
+        self.record_trace = record;
+    }
+
+    pub fn stack_limit(&self) -> usize {
+        self.stack_limit
+    }
+
+    /// The maximum number of stack items of each call frame, 1024 by default.
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.depth.get()
+    }
+
+    pub(crate) fn set_depth(&self, depth: usize) {
+        self.depth.set(depth);
+    }
+
+    pub fn debug(&self) -> bool {
+        self.debug
+    }
+
+    /// Pause the stepping after the BREAKPOINT opcodes, which are INVALID otherwise.
+    pub fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_preset_known_chains() {
+        let env = Environment::for_chain(1, State::default()).unwrap();
+        assert_eq!(&U256::from(1), env.chain_id());
+        assert_eq!(&U256::from(30_000_000), env.gas_limit());
+        assert_eq!(Spec::default(), env.spec());
+
+        let env = Environment::for_chain(11155111, State::default()).unwrap();
+        assert_eq!(&U256::from(11155111), env.chain_id());
+
+        assert!(Environment::for_chain(0x2A, State::default()).is_none());
+    }
+
+    #[test]
+    fn should_clamp_block_values_to_u64() {
+        let address = Address::default();
+        let small = U256::from(0x2A);
+        let large = U256::from(u64::MAX) + U256::from(1);
+        let env = Environment::new(
+            &address,
+            &[],
+            &address,
+            &large,
+            &small,
+            &small,
+            &small,
+            &U256::MAX,
+            &small,
+            State::default(),
+            &small,
+        );
+        assert_eq!(u64::MAX, env.number_as_u64());
+        assert_eq!(u64::MAX, env.time_as_u64());
+        assert_eq!(0x2A, env.gas_limit_as_u64());
+        // The opcodes still push the full value.
+        assert_eq!(large, env.number_value());
+    }
 }
@@ -0,0 +1,27 @@
+use ruint::{aliases::U256, uint};
+use sha3::{Digest, Keccak256};
+
+/// The hash of an empty code, the code hash of the accounts without code.
+pub const EMPTY_CODE_HASH: U256 =
+    uint!(0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470_U256);
+
+/// The Keccak-256 hash of `data`, as used by Ethereum rather than the standard SHA3-256.
+pub fn keccak256(data: &[u8]) -> [u8; 0x20] {
+    Keccak256::digest(data).into()
+}
+
+/// The Keccak-256 hash of `data` as a big-endian number.
+pub fn keccak256_u256(data: &[u8]) -> U256 {
+    U256::from_be_bytes(keccak256(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_hash_the_empty_input() {
+        assert_eq!(EMPTY_CODE_HASH, keccak256_u256(&[]));
+        assert_eq!(EMPTY_CODE_HASH.to_be_bytes::<0x20>(), keccak256(&[]));
+    }
+}
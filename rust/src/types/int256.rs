@@ -157,8 +157,9 @@ impl ops::Rem for Int256 {
             return Int256::zero();
         }
 
+        // The division is exact, its remainder is 0.
         if dividend == Int256::max_negative_value() && divisor == Int256::negative_one() {
-            dividend
+            Int256::zero()
         } else {
             let is_negative = dividend.is_negative();
             let c = dividend.abs() % divisor.abs();
@@ -171,18 +172,12 @@ impl ops::Shr<Bitsize> for Int256 {
     type Output = Self;
 
     fn shr(self, shift: Bitsize) -> Self::Output {
-        match (self.is_negative(), shift == Bitsize::MAX) {
-            (false, true) => Int256::zero(),
-            (true, true) => Int256::negative_one(),
-            (is_negative, _) => {
-                let raw = self.0 >> &shift.clone().into();
-                if is_negative {
-                    let int = IntN::from_raw_u256(raw, shift.into());
-                    int.sign_extend()
-                } else {
-                    Int256(raw)
-                }
-            }
+        let shift = usize::from(shift);
+        if self.is_negative() {
+            // Shift the complement so the vacated bits are ones, a shift by 0 is a no-op.
+            Int256(!(!self.0 >> shift))
+        } else {
+            Int256(self.0 >> shift)
         }
     }
 }
@@ -221,3 +216,74 @@ impl IntN {
         IntN { raw, size }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_positive_value() -> Int256 {
+        Int256::from_raw_u256(U256::MAX >> 1)
+    }
+
+    fn one() -> Int256 {
+        Int256::from_raw_u256(U256::from(1))
+    }
+
+    #[test]
+    fn should_order_signed_boundaries() {
+        assert!(Int256::max_negative_value() < Int256::negative_one());
+        assert!(Int256::negative_one() < Int256::zero());
+        assert!(Int256::zero() < one());
+        assert!(one() < max_positive_value());
+        assert!(!(max_positive_value() < Int256::max_negative_value()));
+
+        assert!(max_positive_value() > Int256::max_negative_value());
+        assert!(Int256::zero() > Int256::negative_one());
+        assert!(!(Int256::max_negative_value() > Int256::max_negative_value()));
+    }
+
+    #[test]
+    fn should_compare_min_negative_with_everything() {
+        let min = Int256::max_negative_value();
+        for other in [
+            Int256::negative_one(),
+            Int256::zero(),
+            one(),
+            max_positive_value(),
+        ] {
+            assert!(min < other);
+            assert!(other > min);
+            assert_eq!(cmp::Ordering::Less, min.cmp(&other));
+        }
+        assert!(min <= Int256::max_negative_value());
+        assert!(min >= Int256::max_negative_value());
+    }
+
+    #[test]
+    fn should_not_overflow_min_negative_by_negative_one() {
+        let min = Int256::max_negative_value();
+        assert!(min.clone() / Int256::negative_one() == min);
+        assert!(min % Int256::negative_one() == Int256::zero());
+    }
+
+    #[test]
+    fn should_shift_right_arithmetically() {
+        let shr = |value: U256, shift: u64| {
+            (Int256::from_raw_u256(value) >> Bitsize::from(U256::from(shift))).to_raw_u256()
+        };
+        let min_plus_one = Int256::max_negative_value().to_raw_u256() | U256::from(1);
+        assert_eq!(min_plus_one, shr(min_plus_one, 0));
+        let value = Int256::max_negative_value().to_raw_u256() | U256::from(0x80);
+        assert_eq!(value, shr(value, 0));
+
+        let min = Int256::max_negative_value().to_raw_u256();
+        assert_eq!(U256::MAX << 251, shr(min, 4));
+        assert_eq!(U256::MAX << 246, shr(min, 9));
+        assert_eq!(U256::MAX, shr(min, 255));
+        assert_eq!(U256::MAX, shr(min, 1000));
+        assert_eq!(U256::MAX, shr(U256::MAX, 1));
+
+        assert_eq!(U256::from(0x0F), shr(U256::from(0xF0), 4));
+        assert_eq!(U256::ZERO, shr(U256::MAX >> 1, 255));
+    }
+}
@@ -1,5 +1,5 @@
 use ruint::aliases::U256;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::Address;
 
@@ -69,7 +69,7 @@ impl Log {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct LogResult {
     address: Address,
     topics: Vec<U256>,
@@ -138,3 +138,38 @@ impl From<LogResult> for Log {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_serialize_log_result_as_its_fixture_form() {
+        let log: LogResult = Log::log1(
+            ruint::uint!(0x1000000000000000000000000000000000000001_U160).into(),
+            [U256::from(0x2A)],
+            vec![0xAA],
+        )
+        .into();
+        assert_eq!(
+            serde_json::json!({
+                "address": "0x1000000000000000000000000000000000000001",
+                "topics": ["0x000000000000000000000000000000000000000000000000000000000000002a"],
+                "data": "aa",
+            }),
+            serde_json::to_value(&log).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_round_trip_log_result() {
+        let fixture = r#"{
+            "address": "0x1000000000000000000000000000000000000001",
+            "data": "aabb",
+            "topics": ["0x2a", "0x1"]
+        }"#;
+        let log: LogResult = serde_json::from_str(fixture).unwrap();
+        let json = serde_json::to_string(&log).unwrap();
+        assert_eq!(log, serde_json::from_str(&json).unwrap());
+    }
+}
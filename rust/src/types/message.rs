@@ -1,7 +1,6 @@
 use super::Calldata;
-use crate::types::{Address, U256_DEFAULT};
+use crate::types::{keccak256, Address, U256_DEFAULT};
 use ruint::aliases::{U160, U256};
-use sha3::Digest;
 
 #[derive(Debug)]
 /// Items that are used by contract creation or message call.
@@ -43,8 +42,10 @@ impl<'a, 'b> Message<'a, 'b>
 where
     'a: 'b,
 {
+    /// A call, or a creation without `target`.
     pub(crate) fn new(
         caller: &'a Address,
+        caller_nonce: &usize,
         target: &'a Option<Address>,
         gas: &'a U256,
         value: &'a U256,
@@ -53,7 +54,7 @@ where
         if let Some(target) = target {
             Self::call(caller, target, gas, value, data)
         } else {
-            todo!()
+            Self::create(caller, caller_nonce, gas, value, data)
         }
     }
 
@@ -111,12 +112,10 @@ where
         data: &'b Calldata<'a>,
     ) -> Self {
         // Calculate the deployment address.
-        let mut hasher = sha3::Keccak256::new();
-        hasher.update(rlp::encode_list(&[
+        let hash = keccak256(&rlp::encode_list(&[
             caller.into(),
             U256::from(*caller_nonce),
         ]));
-        let hash = hasher.finalize();
         let target = U160::try_from_be_slice(&hash[0x0C..]).expect("safe").into();
 
         Self::Create {
@@ -148,6 +147,14 @@ where
         }
     }
 
+    /// The address of the executed code.
+    pub(crate) fn code_address(&self) -> &Address {
+        match self {
+            Message::Delegatecall { delegate, .. } => delegate,
+            _ => self.target(),
+        }
+    }
+
     pub(crate) fn value(&self) -> &U256 {
         use Message::*;
         match self {
@@ -1,23 +1,30 @@
+mod abi;
 mod account;
 mod address;
 mod bytes;
 mod calldata;
 mod environment;
+mod hash;
 mod int256;
 mod log;
 mod message;
+mod secp256k1;
+mod spec;
 mod state;
 mod transaction;
 
 pub use self::log::*;
+pub use abi::*;
 pub use account::*;
 pub use address::*;
 pub use bytes::*;
 pub use calldata::*;
 pub use environment::*;
+pub use hash::*;
 pub use int256::*;
 pub use message::*;
 use ruint::aliases::U256;
+pub use spec::*;
 pub use state::*;
 pub use transaction::*;
 
@@ -0,0 +1,190 @@
+use ruint::{aliases::U256, uint};
+
+use super::{keccak256, Address};
+
+/// The field prime.
+const P: U256 = uint!(0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F_U256);
+/// The group order.
+const N: U256 = uint!(0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141_U256);
+/// The generator point.
+const G: (U256, U256) = (
+    uint!(0x79BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798_U256),
+    uint!(0x483ADA7726A3C4655DA4FBFC0E1108A8FD17B448A68554199C47D08FFB10D4B8_U256),
+);
+
+/// Recover the address which signed `hash` with the `(recovery_id, r, s)` signature.
+/// Returns `None` for an invalid signature.
+pub(crate) fn recover(hash: &[u8; 0x20], recovery_id: u8, r: U256, s: U256) -> Option<Address> {
+    if recovery_id > 1 || r == U256::ZERO || r >= N || s == U256::ZERO || s >= N {
+        return None;
+    }
+
+    // The point R from its x coordinate and the parity of y.
+    let x = r;
+    let y2 = add(mul(mul(x, x), x), U256::from(7));
+    // P = 3 mod 4, so the square root is y2^((P + 1) / 4).
+    let y = y2.pow_mod((P + U256::from(1)) >> 2, P);
+    if mul(y, y) != y2 {
+        return None;
+    }
+    let y = if y.bit(0) == (recovery_id == 1) {
+        y
+    } else {
+        P - y
+    };
+
+    // Q = r⁻¹ (sR - eG)
+    let e = U256::from_be_bytes(*hash).reduce_mod(N);
+    let r_inv = r.inv_mod(N)?;
+    let u1 = (N - e).reduce_mod(N).mul_mod(r_inv, N);
+    let u2 = s.mul_mod(r_inv, N);
+    let q = Point::from(G)
+        .multiply(u1)
+        .add(&Point::from((x, y)).multiply(u2));
+    let (qx, qy) = q.to_affine()?;
+
+    // The address is the end of the public key hash.
+    let hash = keccak256(&[qx.to_be_bytes::<0x20>(), qy.to_be_bytes::<0x20>()].concat());
+    Some(<[u8; 0x14]>::try_from(&hash[0x0C..]).expect("safe").into())
+}
+
+fn add(a: U256, b: U256) -> U256 {
+    a.add_mod(b, P)
+}
+
+fn sub(a: U256, b: U256) -> U256 {
+    a.add_mod(P - b, P)
+}
+
+fn mul(a: U256, b: U256) -> U256 {
+    a.mul_mod(b, P)
+}
+
+#[derive(Debug, Clone)]
+/// A curve point in jacobian coordinates, the infinity has `z = 0`.
+struct Point {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+impl From<(U256, U256)> for Point {
+    fn from((x, y): (U256, U256)) -> Self {
+        Self {
+            x,
+            y,
+            z: U256::from(1),
+        }
+    }
+}
+
+impl Point {
+    fn infinity() -> Self {
+        Self {
+            x: U256::ZERO,
+            y: U256::from(1),
+            z: U256::ZERO,
+        }
+    }
+
+    fn is_infinity(&self) -> bool {
+        self.z == U256::ZERO
+    }
+
+    fn to_affine(&self) -> Option<(U256, U256)> {
+        let z_inv = self.z.inv_mod(P)?;
+        let z_inv2 = mul(z_inv, z_inv);
+        Some((mul(self.x, z_inv2), mul(self.y, mul(z_inv2, z_inv))))
+    }
+
+    fn double(&self) -> Self {
+        if self.is_infinity() || self.y == U256::ZERO {
+            return Self::infinity();
+        }
+        let a = mul(self.x, self.x);
+        let b = mul(self.y, self.y);
+        let c = mul(b, b);
+        let xb = add(self.x, b);
+        let d = mul(U256::from(2), sub(sub(mul(xb, xb), a), c));
+        let e = mul(U256::from(3), a);
+        let f = mul(e, e);
+        let x = sub(f, mul(U256::from(2), d));
+        let y = sub(mul(e, sub(d, x)), mul(U256::from(8), c));
+        let z = mul(U256::from(2), mul(self.y, self.z));
+        Self { x, y, z }
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        if self.is_infinity() {
+            return other.clone();
+        }
+        if other.is_infinity() {
+            return self.clone();
+        }
+        let z1z1 = mul(self.z, self.z);
+        let z2z2 = mul(other.z, other.z);
+        let u1 = mul(self.x, z2z2);
+        let u2 = mul(other.x, z1z1);
+        let s1 = mul(self.y, mul(other.z, z2z2));
+        let s2 = mul(other.y, mul(self.z, z1z1));
+        let h = sub(u2, u1);
+        let r = sub(s2, s1);
+        if h == U256::ZERO {
+            return if r == U256::ZERO {
+                self.double()
+            } else {
+                Self::infinity()
+            };
+        }
+        let hh = mul(h, h);
+        let hhh = mul(h, hh);
+        let v = mul(u1, hh);
+        let x = sub(sub(mul(r, r), hhh), mul(U256::from(2), v));
+        let y = sub(mul(r, sub(v, x)), mul(s1, hhh));
+        let z = mul(mul(self.z, other.z), h);
+        Self { x, y, z }
+    }
+
+    /// Double and add, from the most significant bit.
+    fn multiply(&self, scalar: U256) -> Self {
+        (0..scalar.bit_len())
+            .rev()
+            .fold(Self::infinity(), |acc, i| {
+                let acc = acc.double();
+                if scalar.bit(i) {
+                    acc.add(self)
+                } else {
+                    acc
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_multiply_the_generator() {
+        let g = Point::from(G);
+        assert_eq!(Some(G), g.multiply(U256::from(1)).to_affine());
+        assert_eq!(
+            g.double().to_affine(),
+            g.multiply(U256::from(2)).to_affine()
+        );
+        assert_eq!(
+            g.double().add(&g).to_affine(),
+            g.multiply(U256::from(3)).to_affine()
+        );
+        // The order of the group.
+        assert!(g.multiply(N).is_infinity());
+    }
+
+    #[test]
+    fn should_reject_invalid_signatures() {
+        let hash = [0x01; 0x20];
+        assert_eq!(None, recover(&hash, 2, U256::from(1), U256::from(1)));
+        assert_eq!(None, recover(&hash, 0, U256::ZERO, U256::from(1)));
+        assert_eq!(None, recover(&hash, 0, U256::from(1), N));
+    }
+}
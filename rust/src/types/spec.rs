@@ -0,0 +1,136 @@
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Ethereum hard forks, in activation order.
+pub enum Spec {
+    Frontier,
+    Homestead,
+    TangerineWhistle,
+    SpuriousDragon,
+    Byzantium,
+    Constantinople,
+    Petersburg,
+    Istanbul,
+    Berlin,
+    London,
+    Paris,
+    Shanghai,
+    #[default]
+    Cancun,
+    Prague,
+}
+
+impl Spec {
+    /// Check if the fork `other` is activated in this spec.
+    pub fn is_enabled(&self, other: Spec) -> bool {
+        *self >= other
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The fork dependent behaviors, to gate them in one place.
+pub struct SpecConfig {
+    /// EIP-7, DELEGATECALL.
+    pub delegatecall: bool,
+    /// EIP-150, a call forwards at most all but one 64th of the remaining gas.
+    pub all_but_one_64th: bool,
+    /// EIP-150, the opcodes reading another account are repriced.
+    pub io_repricing: bool,
+    /// EIP-161, SELFDESTRUCT only creates its beneficiary when sending a balance.
+    pub state_clearing: bool,
+    /// EIP-160, the EXP exponent bytes cost 50 instead of 10.
+    pub exp_repricing: bool,
+    /// EIP-140, EIP-211 and EIP-214, REVERT, the return data opcodes and STATICCALL.
+    pub byzantium_opcodes: bool,
+    /// EIP-145, the bitwise shifts SHL, SHR and SAR.
+    pub bitwise_shifts: bool,
+    /// EIP-1052, EXTCODEHASH.
+    pub extcodehash: bool,
+    /// EIP-1344, CHAINID.
+    pub chain_id: bool,
+    /// EIP-1884, the trie reading opcodes are repriced and SELFBALANCE is introduced.
+    pub trie_repricing: bool,
+    /// EIP-2200, SSTORE is charged from the value of the slot at the start of the transaction.
+    pub net_gas_metering: bool,
+    /// EIP-2929, the accessed accounts are cold or warm.
+    pub access_lists: bool,
+    /// EIP-1559 and EIP-3198, the base fee of the block is burnt and pushed by BASEFEE.
+    pub base_fee: bool,
+    /// EIP-3529, the gas refunds are reduced.
+    pub eip3529_refunds: bool,
+    /// EIP-4399, DIFFICULTY returns the prevrandao of the beacon chain.
+    pub prevrandao: bool,
+    /// EIP-3855.
+    pub push0: bool,
+    /// EIP-3651, the coinbase is warm at the start of the transaction.
+    pub warm_coinbase: bool,
+    /// EIP-1153, TLOAD and TSTORE.
+    pub transient_storage: bool,
+    /// EIP-5656, MCOPY.
+    pub mcopy: bool,
+    /// EIP-4844 and EIP-7516, BLOBHASH and BLOBBASEFEE.
+    pub blobs: bool,
+    /// EIP-3540 and EIP-663, the EOF containers are decoded with their DUPN, SWAPN and EXCHANGE.
+    pub eof: bool,
+}
+
+impl SpecConfig {
+    pub fn for_fork(spec: Spec) -> Self {
+        Self {
+            delegatecall: spec.is_enabled(Spec::Homestead),
+            all_but_one_64th: spec.is_enabled(Spec::TangerineWhistle),
+            io_repricing: spec.is_enabled(Spec::TangerineWhistle),
+            state_clearing: spec.is_enabled(Spec::SpuriousDragon),
+            exp_repricing: spec.is_enabled(Spec::SpuriousDragon),
+            byzantium_opcodes: spec.is_enabled(Spec::Byzantium),
+            bitwise_shifts: spec.is_enabled(Spec::Constantinople),
+            extcodehash: spec.is_enabled(Spec::Constantinople),
+            chain_id: spec.is_enabled(Spec::Istanbul),
+            trie_repricing: spec.is_enabled(Spec::Istanbul),
+            net_gas_metering: spec.is_enabled(Spec::Istanbul),
+            access_lists: spec.is_enabled(Spec::Berlin),
+            base_fee: spec.is_enabled(Spec::London),
+            eip3529_refunds: spec.is_enabled(Spec::London),
+            prevrandao: spec.is_enabled(Spec::Paris),
+            push0: spec.is_enabled(Spec::Shanghai),
+            warm_coinbase: spec.is_enabled(Spec::Shanghai),
+            transient_storage: spec.is_enabled(Spec::Cancun),
+            mcopy: spec.is_enabled(Spec::Cancun),
+            blobs: spec.is_enabled(Spec::Cancun),
+            eof: spec.is_enabled(Spec::Prague),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_configure_the_forks() {
+        let shanghai = SpecConfig::for_fork(Spec::Shanghai);
+        assert!(shanghai.push0);
+        assert!(shanghai.warm_coinbase);
+        assert!(shanghai.prevrandao);
+        assert!(!shanghai.eof);
+
+        let paris = SpecConfig::for_fork(Spec::Paris);
+        assert!(!paris.push0);
+        assert!(!paris.warm_coinbase);
+        assert!(paris.prevrandao);
+
+        let cancun = SpecConfig::for_fork(Spec::Cancun);
+        assert!(cancun.transient_storage && cancun.mcopy && cancun.blobs);
+        assert!(!shanghai.transient_storage);
+
+        assert!(SpecConfig::for_fork(Spec::Istanbul).net_gas_metering);
+        assert!(SpecConfig::for_fork(Spec::Istanbul).trie_repricing);
+        assert!(!SpecConfig::for_fork(Spec::Petersburg).net_gas_metering);
+
+        let frontier = SpecConfig::for_fork(Spec::Frontier);
+        assert!(!frontier.delegatecall);
+        assert!(!frontier.all_but_one_64th);
+        assert!(!frontier.io_repricing);
+        assert!(!frontier.byzantium_opcodes);
+        assert!(!frontier.exp_repricing);
+        assert!(!frontier.access_lists);
+    }
+}
@@ -1,18 +1,63 @@
 use crate::types::{Account, AccountError, Address, EMPTY_ACCOUNT};
 use ruint::aliases::U256;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A savepoint of the state, see [`State::checkpoint`].
+/// Each checkpoint taken from a state has its own id.
+pub struct CheckpointId(usize);
+
+#[derive(Debug, Clone)]
+/// A change of the state, undone when reverting to a checkpoint taken before it.
+enum JournalEntry {
+    /// The account was not in the state when `None`.
+    Account(Address, Option<Account>),
+    /// A storage slot of a contract and its previous value.
+    Storage(Address, U256, U256),
+    /// A transient storage slot, zero when `None`.
+    TransientStorage((Address, U256), Option<U256>),
+    /// An account added to the destroyed accounts.
+    Destroyed(Address),
+    /// The destroyed accounts taken at the end of a transaction.
+    DestroyedTaken(HashSet<Address>),
+}
+
 #[derive(Debug, Clone)]
 /// Contains all information that is preserved between transactions.
 pub struct State {
     accounts: HashMap<Address, Account>,
+    /// Storage discarded at the end of each transaction (EIP-1153).
+    transient_storage: HashMap<(Address, U256), U256>,
+    /// The accounts deleted by SELFDESTRUCT during the transaction.
+    destroyed: HashSet<Address>,
+    /// The values of the storage slots written during the transaction, at its start.
+    original_storage: HashMap<(Address, U256), U256>,
+    /// The changes made since the oldest open checkpoint, empty without one.
+    journal: Vec<JournalEntry>,
+    /// The open checkpoints from the oldest, with the length of the journal when taken.
+    checkpoints: Vec<(CheckpointId, usize)>,
+    /// The id of the next checkpoint.
+    next_checkpoint: usize,
 }
 
 impl<'a> State {
     pub fn new(accounts: HashMap<Address, Account>) -> Self {
         log::trace!("new(): accounts={:?}", accounts);
-        Self { accounts }
+        Self {
+            accounts,
+            transient_storage: HashMap::default(),
+            destroyed: HashSet::default(),
+            original_storage: HashMap::default(),
+            journal: vec![],
+            checkpoints: vec![],
+            next_checkpoint: 0,
+        }
+    }
+
+    /// A state built account by account, see [`StateBuilder`].
+    pub fn builder() -> StateBuilder {
+        StateBuilder::default()
     }
 
     pub(crate) fn get_account(&self, addr: &Address) -> &Account {
@@ -27,17 +72,164 @@ impl<'a> State {
         log::trace!("update_account(): account={:?}", self.get_account(&addr));
 
         let updated = f(self.get_account(addr).clone())?;
-        self.accounts.insert(addr.clone(), updated);
+        self.set_account(addr, updated);
 
         log::trace!("result: account={:?}", self);
         Ok(())
     }
 
+    fn set_account(&mut self, addr: &Address, account: Account) {
+        let previous = self.accounts.insert(addr.clone(), account);
+        self.record(JournalEntry::Account(addr.clone(), previous));
+    }
+
     pub(crate) fn delete_account(&mut self, addr: &Address) -> Result<()> {
         log::trace!("delete_account(): address={:?}", addr);
+        if self.destroyed.insert(addr.clone()) {
+            self.record(JournalEntry::Destroyed(addr.clone()));
+        }
         self.update_account(addr, |_| Ok(Account::Empty))
     }
 
+    /// The accounts deleted during the transaction, forgotten for the next one.
+    pub(crate) fn take_destroyed(&mut self) -> HashSet<Address> {
+        let destroyed = std::mem::take(&mut self.destroyed);
+        if !self.checkpoints.is_empty() {
+            self.record(JournalEntry::DestroyedTaken(destroyed.clone()));
+        }
+        destroyed
+    }
+
+    /// Store `value` in the slot `key` of `addr`, keeping the value of the slot at the start of
+    /// the transaction.
+    pub(crate) fn store(&mut self, addr: &Address, key: U256, value: U256) {
+        let current = *self.get_account(addr).load(&key);
+        self.original_storage
+            .entry((addr.clone(), key))
+            .or_insert(current);
+        // Only the slot is journaled, not the whole account.
+        if let Some(account) = self.accounts.get_mut(addr) {
+            account.store(key, value);
+            self.record(JournalEntry::Storage(addr.clone(), key, current));
+        }
+    }
+
+    /// The value of the slot `key` of `addr` at the start of the transaction (EIP-2200).
+    pub(crate) fn original_load(&self, addr: &Address, key: &U256) -> U256 {
+        match self.original_storage.get(&(addr.clone(), *key)) {
+            Some(value) => *value,
+            None => *self.get_account(addr).load(key),
+        }
+    }
+
+    /// Forget the original values of the slots, at the end of the transaction.
+    pub(crate) fn clear_original_storage(&mut self) {
+        self.original_storage.clear();
+    }
+
+    pub(crate) fn transient_load(&self, addr: &Address, key: &U256) -> U256 {
+        self.transient_storage
+            .get(&(addr.clone(), *key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn transient_store(&mut self, addr: &Address, key: U256, value: U256) {
+        log::trace!(
+            "transient_store(): address={:?}, key={:?}, value={:?}",
+            addr,
+            key,
+            value
+        );
+        let key = (addr.clone(), key);
+        let previous = if value == U256::ZERO {
+            self.transient_storage.remove(&key)
+        } else {
+            self.transient_storage.insert(key.clone(), value)
+        };
+        self.record(JournalEntry::TransientStorage(key, previous));
+    }
+
+    pub(crate) fn clear_transient_storage(&mut self) {
+        let transient_storage = std::mem::take(&mut self.transient_storage);
+        if !self.checkpoints.is_empty() {
+            for (key, value) in transient_storage {
+                self.record(JournalEntry::TransientStorage(key, Some(value)));
+            }
+        }
+    }
+
+    /// Start recording the changes of the state, to revert them or commit them later.
+    pub fn checkpoint(&mut self) -> CheckpointId {
+        let checkpoint = CheckpointId(self.next_checkpoint);
+        self.next_checkpoint += 1;
+        self.checkpoints.push((checkpoint, self.journal.len()));
+        checkpoint
+    }
+
+    /// Undo the changes made since `checkpoint`.
+    /// The checkpoints taken after it are discarded.
+    pub fn revert_to(&mut self, checkpoint: CheckpointId) -> Result<()> {
+        let length = self.close(checkpoint)?;
+        while self.journal.len() > length {
+            match self.journal.pop().expect("safe") {
+                JournalEntry::Account(addr, Some(account)) => {
+                    self.accounts.insert(addr, account);
+                }
+                JournalEntry::Account(addr, None) => {
+                    self.accounts.remove(&addr);
+                }
+                JournalEntry::Storage(addr, key, value) => {
+                    if let Some(account) = self.accounts.get_mut(&addr) {
+                        account.store(key, value);
+                    }
+                }
+                JournalEntry::TransientStorage(key, Some(value)) => {
+                    self.transient_storage.insert(key, value);
+                }
+                JournalEntry::TransientStorage(key, None) => {
+                    self.transient_storage.remove(&key);
+                }
+                JournalEntry::Destroyed(addr) => {
+                    self.destroyed.remove(&addr);
+                }
+                JournalEntry::DestroyedTaken(destroyed) => self.destroyed = destroyed,
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the changes made since `checkpoint`.
+    /// The checkpoints taken after it are committed too.
+    pub fn commit(&mut self, checkpoint: CheckpointId) -> Result<()> {
+        self.close(checkpoint)?;
+        if self.checkpoints.is_empty() {
+            // Nothing can be reverted anymore.
+            self.journal.clear();
+        }
+        Ok(())
+    }
+
+    /// Close `checkpoint` and the checkpoints taken after it.
+    /// Returns the length of the journal when `checkpoint` was taken.
+    fn close(&mut self, checkpoint: CheckpointId) -> Result<usize> {
+        let index = self
+            .checkpoints
+            .iter()
+            .position(|(c, _)| *c == checkpoint)
+            .ok_or(StateError::UnknownCheckpoint)?;
+        let length = self.checkpoints[index].1;
+        self.checkpoints.truncate(index);
+        Ok(length)
+    }
+
+    /// Journal a change, only while a checkpoint can revert it.
+    fn record(&mut self, entry: JournalEntry) {
+        if !self.checkpoints.is_empty() {
+            self.journal.push(entry);
+        }
+    }
+
     pub(crate) fn send_eth(&mut self, from: &Address, to: &Address, amount: &U256) -> Result<()> {
         log::trace!(
             "send_eth(): from={:?}, to={:?}, amount={:02X?}",
@@ -46,19 +238,55 @@ impl<'a> State {
             amount
         );
 
-        // ⚠️ Do not check the sender amount because of the invalid state data.
-        //self.update_account(from, |from_account| {
-        //    from_account
-        //        .decrease_balance(amount)
-        //        .map_err(StateError::AccountError)
-        //})
-        //.and_then(|_| {
-        self.update_account(to, |to_account| {
-            to_account
-                .increase_balance(amount)
+        // Both sides are computed before updating any, a failed transfer changes nothing.
+        let from_account = self.get_account(from).clone().decrease_balance(amount)?;
+        let to_account = match from == to {
+            true => from_account.clone(),
+            false => self.get_account(to).clone(),
+        }
+        .increase_balance(amount)?;
+
+        if from != to {
+            self.set_account(from, from_account);
+        }
+        self.set_account(to, to_account);
+        log::trace!("result: state={:?}", self);
+        Ok(())
+    }
+
+    /// Remove `amount` from the balance of `from`, like the base fee (EIP-1559).
+    pub(crate) fn burn_eth(&mut self, from: &Address, amount: &U256) -> Result<()> {
+        log::trace!("burn_eth(): from={:?}, amount={:02X?}", from, amount);
+
+        self.update_account(from, |from_account| {
+            from_account
+                .decrease_balance(amount)
                 .map_err(StateError::AccountError)
         })
-        //})
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A view of the state which cannot modify it, like for the staticcall frames.
+pub struct ReadOnlyState<'a>(&'a State);
+
+impl<'a> ReadOnlyState<'a> {
+    pub(crate) fn get_account(&self, addr: &Address) -> &'a Account {
+        self.0.get_account(addr)
+    }
+
+    pub(crate) fn transient_load(&self, addr: &Address, key: &U256) -> U256 {
+        self.0.transient_load(addr, key)
+    }
+
+    pub(crate) fn original_load(&self, addr: &Address, key: &U256) -> U256 {
+        self.0.original_load(addr, key)
+    }
+}
+
+impl<'a> From<&'a State> for ReadOnlyState<'a> {
+    fn from(state: &'a State) -> Self {
+        Self(state)
     }
 }
 
@@ -66,6 +294,90 @@ impl Default for State {
     fn default() -> Self {
         Self {
             accounts: HashMap::default(),
+            transient_storage: HashMap::default(),
+            destroyed: HashSet::default(),
+            original_storage: HashMap::default(),
+            journal: vec![],
+            checkpoints: vec![],
+            next_checkpoint: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Builds a genesis [`State`], the accounts not mentioned are empty.
+/// It can also override some accounts of an existing state, see [`StateBuilder::apply`].
+pub struct StateBuilder {
+    balances: HashMap<Address, U256>,
+    codes: HashMap<Address, Box<[u8]>>,
+    storages: HashMap<Address, HashMap<U256, U256>>,
+}
+
+impl StateBuilder {
+    /// Set the balance of `address`.
+    pub fn fund(mut self, address: Address, balance: U256) -> Self {
+        self.balances.insert(address, balance);
+        self
+    }
+
+    /// Set the code of `address`, making it a contract.
+    pub fn deploy(mut self, address: Address, code: impl Into<Box<[u8]>>) -> Self {
+        self.codes.insert(address, code.into());
+        self
+    }
+
+    /// Set a storage slot of `address`, which is a contract without code if not deployed.
+    pub fn set_storage(mut self, address: Address, slot: U256, value: U256) -> Self {
+        self.storages
+            .entry(address)
+            .or_default()
+            .insert(slot, value);
+        self
+    }
+
+    pub fn build(self) -> State {
+        let mut state = State::default();
+        self.apply(&mut state);
+        state
+    }
+
+    /// Override the balances, the codes and the storage slots set on the accounts of `state`.
+    /// The other fields and storage slots of these accounts are kept.
+    pub fn apply(mut self, state: &mut State) {
+        let mut addresses = self.balances.keys().cloned().collect::<Vec<_>>();
+        addresses.extend(self.codes.keys().cloned());
+        addresses.extend(self.storages.keys().cloned());
+
+        for address in addresses {
+            let storage = self.storages.remove(&address).unwrap_or_default();
+            let code = self.codes.remove(&address);
+            let balance = self.balances.remove(&address);
+            if code.is_none() && balance.is_none() && storage.is_empty() {
+                // Already applied.
+                continue;
+            }
+            state
+                .update_account(&address, |mut account| {
+                    let code = match code {
+                        None if !storage.is_empty()
+                            && !matches!(account, Account::Contract { .. }) =>
+                        {
+                            Some(Box::default())
+                        }
+                        code => code,
+                    };
+                    if let Some(code) = code {
+                        account = account.set_code(code)?;
+                    }
+                    if let Some(balance) = balance {
+                        account = account.set_balance(balance);
+                    }
+                    for (slot, value) in storage {
+                        account.store(slot, value);
+                    }
+                    Ok(account)
+                })
+                .expect("safe");
         }
     }
 }
@@ -74,6 +386,8 @@ impl Default for State {
 pub enum StateError {
     #[error(transparent)]
     AccountError(#[from] AccountError),
+    #[error("unknown or closed checkpoint")]
+    UnknownCheckpoint,
 }
 
 pub type Result<T> = std::result::Result<T, StateError>;
@@ -85,3 +399,252 @@ pub type Result<T> = std::result::Result<T, StateError>;
 //        }
 //    }
 //}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(n: u8) -> Address {
+        [n; 0x14].into()
+    }
+
+    fn balance(state: &State, n: u8) -> U256 {
+        *state.get_account(&address(n)).balance()
+    }
+
+    /// A state where `address(0)` can send ETH.
+    fn state() -> State {
+        State::builder().fund(address(0), U256::MAX).build()
+    }
+
+    fn credit(state: &mut State, n: u8) {
+        state
+            .send_eth(&address(0), &address(n), &U256::from(1))
+            .unwrap();
+    }
+
+    #[test]
+    fn should_build_a_genesis_state() {
+        let state = State::builder()
+            .fund(address(1), U256::from(10))
+            .fund(address(2), U256::from(20))
+            .deploy(address(3), vec![0x00])
+            .set_storage(address(3), U256::from(1), U256::from(0x2A))
+            .fund(address(3), U256::from(30))
+            .build();
+
+        assert_eq!(U256::from(10), balance(&state, 1));
+        assert_eq!(U256::from(20), balance(&state, 2));
+        assert!(state.get_account(&address(1)).code().is_empty());
+
+        let contract = state.get_account(&address(3));
+        assert_eq!(U256::from(30), *contract.balance());
+        assert_eq!(&[0x00], contract.code());
+        assert_eq!(&U256::from(0x2A), contract.load(&U256::from(1)));
+        assert!(matches!(state.get_account(&address(4)), Account::Empty));
+    }
+
+    #[test]
+    fn should_override_only_the_given_fields() {
+        let mut state = State::builder()
+            .fund(address(1), U256::from(10))
+            .deploy(address(2), vec![0x00])
+            .set_storage(address(2), U256::from(1), U256::from(0x2A))
+            .fund(address(2), U256::from(20))
+            .build();
+
+        State::builder()
+            .deploy(address(1), vec![0x01])
+            .set_storage(address(2), U256::from(2), U256::from(0x2B))
+            .fund(address(2), U256::from(21))
+            .apply(&mut state);
+
+        let eoa = state.get_account(&address(1));
+        assert_eq!(U256::from(10), *eoa.balance());
+        assert_eq!(&[0x01], eoa.code());
+        let contract = state.get_account(&address(2));
+        assert_eq!(U256::from(21), *contract.balance());
+        assert_eq!(&[0x00], contract.code());
+        assert_eq!(&U256::from(0x2A), contract.load(&U256::from(1)));
+        assert_eq!(&U256::from(0x2B), contract.load(&U256::from(2)));
+    }
+
+    #[test]
+    fn should_not_debit_when_the_credit_overflows() {
+        let mut state = State::builder()
+            .fund(address(1), U256::from(1))
+            .fund(address(2), U256::MAX)
+            .build();
+        assert!(matches!(
+            state.send_eth(&address(1), &address(2), &U256::from(1)),
+            Err(StateError::AccountError(AccountError::TooMuchMoney))
+        ));
+        assert_eq!(U256::from(1), balance(&state, 1));
+        assert_eq!(U256::MAX, balance(&state, 2));
+
+        state
+            .send_eth(&address(1), &address(1), &U256::from(1))
+            .unwrap();
+        assert_eq!(U256::from(1), balance(&state, 1));
+        state
+            .send_eth(&address(1), &address(3), &U256::from(1))
+            .unwrap();
+        assert_eq!(U256::ZERO, balance(&state, 1));
+        assert_eq!(U256::from(1), balance(&state, 3));
+    }
+
+    #[test]
+    fn should_not_send_more_eth_than_the_balance() {
+        let mut state = State::builder().fund(address(1), U256::from(1)).build();
+
+        assert!(state
+            .send_eth(&address(1), &address(2), &U256::from(2))
+            .is_err());
+        assert!(state.burn_eth(&address(1), &U256::from(2)).is_err());
+        assert_eq!(U256::from(1), balance(&state, 1));
+        assert_eq!(U256::ZERO, balance(&state, 2));
+
+        state
+            .send_eth(&address(1), &address(2), &U256::from(1))
+            .unwrap();
+        assert_eq!(U256::ZERO, balance(&state, 1));
+        assert_eq!(U256::from(1), balance(&state, 2));
+    }
+
+    #[test]
+    fn should_revert_nested_checkpoints() {
+        let mut state = state();
+        credit(&mut state, 1);
+
+        let outer = state.checkpoint();
+        credit(&mut state, 2);
+        let inner = state.checkpoint();
+        credit(&mut state, 3);
+        state.transient_store(&address(3), U256::from(1), U256::from(1));
+
+        state.revert_to(inner).unwrap();
+        assert_eq!(U256::from(1), balance(&state, 2));
+        assert_eq!(U256::ZERO, balance(&state, 3));
+        assert_eq!(
+            U256::ZERO,
+            state.transient_load(&address(3), &U256::from(1))
+        );
+
+        state.revert_to(outer).unwrap();
+        assert_eq!(U256::from(1), balance(&state, 1));
+        assert_eq!(U256::ZERO, balance(&state, 2));
+        assert!(state.checkpoints.is_empty());
+        assert!(state.journal.is_empty());
+    }
+
+    #[test]
+    fn should_revert_storage_and_destroyed_accounts() {
+        let mut state = State::builder()
+            .set_storage(address(1), U256::from(1), U256::from(1))
+            .build();
+        state.transient_store(&address(1), U256::from(1), U256::from(1));
+
+        let checkpoint = state.checkpoint();
+        state.store(&address(1), U256::from(1), U256::from(2));
+        state.store(&address(1), U256::from(2), U256::from(2));
+        state.clear_transient_storage();
+        state.delete_account(&address(1)).unwrap();
+        assert_eq!(1, state.take_destroyed().len());
+
+        state.revert_to(checkpoint).unwrap();
+        let account = state.get_account(&address(1));
+        assert_eq!(&U256::from(1), account.load(&U256::from(1)));
+        assert_eq!(&U256::ZERO, account.load(&U256::from(2)));
+        assert_eq!(
+            U256::from(1),
+            state.transient_load(&address(1), &U256::from(1))
+        );
+        assert!(state.take_destroyed().is_empty());
+    }
+
+    #[test]
+    fn should_close_the_inner_checkpoints_taken_without_changes() {
+        let mut state = state();
+
+        let outer = state.checkpoint();
+        let inner = state.checkpoint();
+        assert_ne!(outer, inner);
+        credit(&mut state, 1);
+
+        // Reverting the outer checkpoint closes the inner one.
+        state.revert_to(outer).unwrap();
+        assert_eq!(U256::ZERO, balance(&state, 1));
+        assert!(state.checkpoints.is_empty());
+        assert!(state.journal.is_empty());
+        assert!(matches!(
+            state.commit(inner),
+            Err(StateError::UnknownCheckpoint)
+        ));
+
+        // A closed inner checkpoint cannot close the outer one.
+        let outer = state.checkpoint();
+        let inner = state.checkpoint();
+        state.commit(inner).unwrap();
+        assert!(matches!(
+            state.revert_to(inner),
+            Err(StateError::UnknownCheckpoint)
+        ));
+        credit(&mut state, 1);
+        state.revert_to(outer).unwrap();
+        assert_eq!(U256::ZERO, balance(&state, 1));
+    }
+
+    #[test]
+    fn should_commit_nested_checkpoints() {
+        let mut state = state();
+
+        let outer = state.checkpoint();
+        credit(&mut state, 1);
+        let inner = state.checkpoint();
+        credit(&mut state, 2);
+        state.commit(inner).unwrap();
+
+        // The committed changes are reverted with the outer checkpoint.
+        state.revert_to(outer).unwrap();
+        assert_eq!(U256::ZERO, balance(&state, 1));
+        assert_eq!(U256::ZERO, balance(&state, 2));
+
+        let outer = state.checkpoint();
+        credit(&mut state, 1);
+        state.checkpoint();
+        credit(&mut state, 2);
+        // Committing the outer checkpoint commits the inner one.
+        state.commit(outer).unwrap();
+        assert!(state.checkpoints.is_empty());
+        assert!(state.journal.is_empty());
+        assert_eq!(U256::from(1), balance(&state, 2));
+
+        // A committed checkpoint cannot be reverted to.
+        assert!(matches!(
+            state.revert_to(outer),
+            Err(StateError::UnknownCheckpoint)
+        ));
+        assert_eq!(U256::from(1), balance(&state, 2));
+    }
+
+    #[test]
+    fn should_keep_the_original_value_of_a_slot() {
+        let mut state = State::builder()
+            .set_storage(address(1), U256::from(1), U256::from(1))
+            .build();
+        let original = |state: &State| state.original_load(&address(1), &U256::from(1));
+
+        state.store(&address(1), U256::from(1), U256::from(2));
+        state.store(&address(1), U256::from(1), U256::from(3));
+        assert_eq!(U256::from(1), original(&state));
+
+        // Even when the write is reverted.
+        let checkpoint = state.checkpoint();
+        state.store(&address(1), U256::from(1), U256::from(4));
+        state.revert_to(checkpoint).unwrap();
+        assert_eq!(U256::from(1), original(&state));
+
+        state.clear_original_storage();
+        assert_eq!(U256::from(3), original(&state));
+    }
+}
@@ -1,16 +1,54 @@
+use rlp::{DecoderError, Rlp, RlpStream};
 use ruint::aliases::U256;
+use thiserror::Error;
 
-use super::Address;
+use super::{abi, keccak256, secp256k1, Address, Token};
+
+const G_TRANSACTION: u64 = 21000;
+const G_TX_CREATE: u64 = 32000;
+const G_TX_DATA_ZERO: u64 = 4;
+const G_TX_DATA_NON_ZERO: u64 = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The transaction envelope types (EIP-2718).
+pub enum TransactionType {
+    #[default]
+    Legacy,
+    /// EIP-2930.
+    AccessList,
+    /// EIP-1559.
+    DynamicFee,
+}
+
+/// The storage keys of an account warmed by a transaction (EIP-2930).
+pub type AccessListItem = (Address, Vec<U256>);
 
 #[derive(Debug)]
-/// Atomic operation performed on the block chain (Legacy).
+/// The signature of a decoded transaction.
+struct Signature {
+    /// The hash of the signed payload.
+    hash: [u8; 0x20],
+    recovery_id: u8,
+    r: U256,
+    s: U256,
+}
+
+#[derive(Debug)]
+/// Atomic operation performed on the block chain.
 pub struct Transaction {
+    transaction_type: TransactionType,
+    /// The max fee per gas of a dynamic fee transaction.
     gas_price: U256,
+    /// `None` unless a dynamic fee transaction.
+    max_priority_fee_per_gas: Option<U256>,
     gas: U256,
     from: Address,
     to: Option<Address>,
     value: U256,
     data: Vec<u8>,
+    access_list: Vec<AccessListItem>,
+    /// `None` unless decoded.
+    signature: Option<Signature>,
 }
 
 impl Transaction {
@@ -23,19 +61,186 @@ impl Transaction {
         data: Vec<u8>,
     ) -> Self {
         Self {
+            transaction_type: TransactionType::Legacy,
             gas_price,
+            max_priority_fee_per_gas: None,
             gas,
             from,
             to,
             value,
             data,
+            access_list: vec![],
+            signature: None,
         }
     }
 
+    /// Decode a legacy or typed transaction envelope (EIP-2718).
+    /// The signature is kept to recover the sender.
+    pub fn decode_enveloped(bytes: &[u8]) -> Result<Self> {
+        match bytes.first() {
+            Some(0x01) => Self::decode_fields(TransactionType::AccessList, &Rlp::new(&bytes[1..])),
+            Some(0x02) => Self::decode_fields(TransactionType::DynamicFee, &Rlp::new(&bytes[1..])),
+            // A legacy transaction is a RLP list.
+            Some(0xC0..) => Self::decode_fields(TransactionType::Legacy, &Rlp::new(bytes)),
+            Some(byte) => Err(TransactionError::InvalidType(*byte)),
+            None => Err(TransactionError::RlpError(DecoderError::RlpIsTooShort)),
+        }
+    }
+
+    fn decode_fields(transaction_type: TransactionType, rlp: &Rlp) -> Result<Self> {
+        use TransactionType::*;
+
+        // The number of fields, and the index of the nonce after the chain id of typed transactions.
+        let (count, first) = match transaction_type {
+            Legacy => (9, 0),
+            AccessList => (11, 1),
+            DynamicFee => (12, 1),
+        };
+        if rlp.item_count()? != count {
+            return Err(TransactionError::RlpError(
+                DecoderError::RlpIncorrectListLen,
+            ));
+        }
+
+        // Skip the nonce.
+        let mut index = first + 1;
+        let mut next = || {
+            index += 1;
+            index - 1
+        };
+        let (gas_price, max_priority_fee_per_gas) = match transaction_type {
+            DynamicFee => {
+                let max_priority_fee_per_gas = rlp.val_at(next())?;
+                (rlp.val_at(next())?, Some(max_priority_fee_per_gas))
+            }
+            _ => (rlp.val_at(next())?, None),
+        };
+        let gas = rlp.val_at(next())?;
+        let to = match rlp.at(next())?.data()? {
+            [] => None,
+            to => Some(decode_address(to)?),
+        };
+        let value = rlp.val_at(next())?;
+        let data = rlp.val_at(next())?;
+        let access_list = match transaction_type {
+            Legacy => vec![],
+            _ => rlp
+                .at(next())?
+                .iter()
+                .map(|item| Ok((decode_address(item.at(0)?.data()?)?, item.list_at(1)?)))
+                .collect::<Result<_>>()?,
+        };
+        let signature = Self::decode_signature(transaction_type, rlp)?;
+
+        Ok(Self {
+            transaction_type,
+            gas_price,
+            max_priority_fee_per_gas,
+            gas,
+            from: Address::default(),
+            to,
+            value,
+            data,
+            access_list,
+            signature: Some(signature),
+        })
+    }
+
+    fn decode_signature(transaction_type: TransactionType, rlp: &Rlp) -> Result<Signature> {
+        let count = rlp.item_count()?;
+        let v: U256 = rlp.val_at(count - 3)?;
+        let r = rlp.val_at(count - 2)?;
+        let s = rlp.val_at(count - 1)?;
+
+        // The signed payload is the transaction without its signature.
+        let append_unsigned = |stream: &mut RlpStream| -> Result<()> {
+            for i in 0..count - 3 {
+                stream.append_raw(rlp.at(i)?.as_raw(), 1);
+            }
+            Ok(())
+        };
+        let mut payload = vec![];
+        let mut stream = RlpStream::new();
+        let recovery_id = match transaction_type {
+            TransactionType::Legacy if v >= U256::from(35) => {
+                // EIP-155 replay protection, with v = chain_id * 2 + 35 + recovery_id.
+                let chain_id = (v - U256::from(35)) >> 1;
+                stream.begin_list(count);
+                append_unsigned(&mut stream)?;
+                stream
+                    .append(&chain_id)
+                    .append(&U256::ZERO)
+                    .append(&U256::ZERO);
+                (v - U256::from(35)).bit(0) as u8
+            }
+            TransactionType::Legacy if v == U256::from(27) || v == U256::from(28) => {
+                stream.begin_list(count - 3);
+                append_unsigned(&mut stream)?;
+                (v - U256::from(27)).saturating_to()
+            }
+            // The v of a typed transaction is the parity of the y coordinate.
+            TransactionType::AccessList | TransactionType::DynamicFee if v <= U256::from(1) => {
+                payload.push(match transaction_type {
+                    TransactionType::AccessList => 0x01,
+                    _ => 0x02,
+                });
+                stream.begin_list(count - 3);
+                append_unsigned(&mut stream)?;
+                v.saturating_to()
+            }
+            _ => return Err(TransactionError::InvalidV(v)),
+        };
+        payload.extend(stream.out());
+
+        Ok(Signature {
+            hash: keccak256(&payload),
+            recovery_id,
+            r,
+            s,
+        })
+    }
+
+    /// Recover the sender from the signature of a decoded transaction.
+    pub fn sender(&self) -> Result<Address> {
+        let signature = self
+            .signature
+            .as_ref()
+            .ok_or(TransactionError::MissingSignature)?;
+        secp256k1::recover(
+            &signature.hash,
+            signature.recovery_id,
+            signature.r,
+            signature.s,
+        )
+        .ok_or(TransactionError::InvalidSignature)
+    }
+
+    pub fn transaction_type(&self) -> TransactionType {
+        self.transaction_type
+    }
+
+    pub fn max_priority_fee_per_gas(&self) -> Option<&U256> {
+        self.max_priority_fee_per_gas.as_ref()
+    }
+
+    pub fn access_list(&self) -> &[AccessListItem] {
+        &self.access_list
+    }
+
     pub fn gas_price(&self) -> &U256 {
         &self.gas_price
     }
 
+    /// The price paid per gas, a dynamic fee transaction pays at most its max fee (EIP-1559).
+    pub fn effective_gas_price(&self, base_fee_per_gas: &U256) -> U256 {
+        match self.max_priority_fee_per_gas {
+            Some(priority_fee) => self
+                .gas_price
+                .min(base_fee_per_gas.saturating_add(priority_fee)),
+            None => self.gas_price,
+        }
+    }
+
     pub fn gas(&self) -> &U256 {
         &self.gas
     }
@@ -55,17 +260,260 @@ impl Transaction {
     pub fn data(&self) -> &[u8] {
         &self.data
     }
+
+    /// Call the function with the `signature`, like `transfer(address,uint256)`, and the `args`.
+    pub fn set_function_call(&mut self, signature: &str, args: &[Token]) {
+        self.data = [&abi::selector(signature)[..], &abi::encode(args)].concat();
+    }
+
+    /// The gas paid before executing any code.
+    pub fn intrinsic_gas(&self) -> u64 {
+        let data = self
+            .data
+            .iter()
+            .map(|b| match b {
+                0 => G_TX_DATA_ZERO,
+                _ => G_TX_DATA_NON_ZERO,
+            })
+            .sum::<u64>();
+        let create = match self.to {
+            Some(_) => 0,
+            None => G_TX_CREATE,
+        };
+        G_TRANSACTION + create + data
+    }
+}
+
+fn decode_address(bytes: &[u8]) -> Result<Address> {
+    <[u8; 0x14]>::try_from(bytes)
+        .map(Address::from)
+        .map_err(|_| TransactionError::InvalidAddress)
 }
 
 impl Default for Transaction {
     fn default() -> Self {
         Self {
-            gas_price: U256::from(10e9),
-            gas: U256::MAX,
+            transaction_type: TransactionType::Legacy,
+            // 10 gwei.
+            gas_price: U256::from(10_000_000_000u64),
+            max_priority_fee_per_gas: None,
+            // The mainnet block gas limit.
+            gas: U256::from(30_000_000),
             from: Address::default(),
             to: Some(Address::default()),
             value: U256::default(),
             data: vec![],
+            access_list: vec![],
+            signature: None,
         }
     }
 }
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TransactionError {
+    InvalidType(u8),
+    InvalidAddress,
+    MissingSignature,
+    InvalidSignature,
+    /// A legacy v other than 27, 28 or `chain_id * 2 + 35 + recovery_id`, or a y parity
+    /// other than 0 or 1.
+    InvalidV(U256),
+    #[error(transparent)]
+    RlpError(#[from] DecoderError),
+}
+
+pub(super) type Result<T> = std::result::Result<T, TransactionError>;
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::InvalidType(t) => write!(f, "invalid transaction type {:#04X}", t),
+            TransactionError::InvalidAddress => write!(f, "invalid address"),
+            TransactionError::MissingSignature => write!(f, "missing signature"),
+            TransactionError::InvalidSignature => write!(f, "invalid signature"),
+            TransactionError::InvalidV(v) => write!(f, "invalid signature v {}", v),
+            TransactionError::RlpError(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_intrinsic_gas() {
+        let call = |to, data| {
+            Transaction::new(
+                U256::ZERO,
+                U256::MAX,
+                Address::default(),
+                to,
+                U256::ZERO,
+                data,
+            )
+        };
+        assert_eq!(
+            21000,
+            call(Some(Address::default()), vec![]).intrinsic_gas()
+        );
+        assert_eq!(
+            21000 + 4 + 16,
+            call(Some(Address::default()), vec![0x00, 0x01]).intrinsic_gas()
+        );
+        assert_eq!(21000 + 32000, call(None, vec![]).intrinsic_gas());
+    }
+
+    fn address(n: u8) -> Address {
+        [n; 0x14].into()
+    }
+
+    fn address_bytes(address: &Address) -> Vec<u8> {
+        <U256 as From<&Address>>::from(address).to_be_bytes::<0x20>()[0x0C..].to_vec()
+    }
+
+    /// Append the fields shared by all transaction types, from the gas limit.
+    fn append_fields(stream: &mut rlp::RlpStream, to: Option<&Address>) {
+        stream.append(&U256::from(21000));
+        match to {
+            Some(to) => stream.append(&address_bytes(to)),
+            None => stream.append_empty_data(),
+        };
+        stream.append(&U256::from(10));
+        stream.append(&vec![0x01_u8, 0x02]);
+    }
+
+    fn append_signature(stream: &mut rlp::RlpStream, v: u64) {
+        stream.append(&U256::from(v));
+        stream.append(&U256::from(2));
+        stream.append(&U256::from(3));
+    }
+
+    #[test]
+    fn should_decode_legacy_transaction() {
+        let mut stream = rlp::RlpStream::new_list(9);
+        stream.append(&U256::ZERO).append(&U256::from(7));
+        append_fields(&mut stream, None);
+        append_signature(&mut stream, 27);
+
+        let tx = Transaction::decode_enveloped(&stream.out()).unwrap();
+        assert_eq!(TransactionType::Legacy, tx.transaction_type());
+        assert_eq!(&U256::from(7), tx.gas_price());
+        assert_eq!(&U256::from(21000), tx.gas());
+        assert_eq!(&None, tx.to());
+        assert_eq!(&U256::from(10), tx.value());
+        assert_eq!(&[0x01, 0x02], tx.data());
+    }
+
+    #[test]
+    fn should_decode_dynamic_fee_transaction() {
+        let mut stream = rlp::RlpStream::new_list(12);
+        stream.append(&U256::from(1)).append(&U256::ZERO);
+        stream.append(&U256::from(2)).append(&U256::from(100));
+        append_fields(&mut stream, Some(&address(0xAA)));
+        stream.begin_list(1).begin_list(2);
+        stream.append(&address_bytes(&address(0xBB)));
+        stream
+            .begin_list(2)
+            .append(&U256::ZERO)
+            .append(&U256::from(1));
+        append_signature(&mut stream, 1);
+
+        let raw = [&[0x02], &stream.out()[..]].concat();
+        let tx = Transaction::decode_enveloped(&raw).unwrap();
+        assert_eq!(TransactionType::DynamicFee, tx.transaction_type());
+        assert_eq!(&U256::from(100), tx.gas_price());
+        assert_eq!(Some(&U256::from(2)), tx.max_priority_fee_per_gas());
+        assert_eq!(&Some(address(0xAA)), tx.to());
+        assert_eq!(
+            &[(address(0xBB), vec![U256::ZERO, U256::from(1)])],
+            tx.access_list()
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_envelopes() {
+        assert_eq!(
+            Err(TransactionError::InvalidType(0x03)),
+            Transaction::decode_enveloped(&[0x03, 0xC0]).map(|_| ())
+        );
+        assert_eq!(
+            Err(TransactionError::RlpError(
+                DecoderError::RlpIncorrectListLen
+            )),
+            Transaction::decode_enveloped(&[0x01, 0xC0]).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn should_reject_an_invalid_v() {
+        let legacy = |v: u64| {
+            let mut stream = rlp::RlpStream::new_list(9);
+            stream.append(&U256::ZERO).append(&U256::from(7));
+            append_fields(&mut stream, None);
+            append_signature(&mut stream, v);
+            Transaction::decode_enveloped(&stream.out()).map(|_| ())
+        };
+        for v in [27, 28, 37, 38] {
+            assert_eq!(Ok(()), legacy(v));
+        }
+        for v in [0, 1, 26, 29, 34] {
+            assert_eq!(Err(TransactionError::InvalidV(U256::from(v))), legacy(v));
+        }
+
+        let access_list = |v: u64| {
+            let mut stream = rlp::RlpStream::new_list(11);
+            stream.append(&U256::from(1)).append(&U256::ZERO);
+            stream.append(&U256::from(7));
+            append_fields(&mut stream, None);
+            stream.begin_list(0);
+            append_signature(&mut stream, v);
+            let raw = [&[0x01], &stream.out()[..]].concat();
+            Transaction::decode_enveloped(&raw).map(|_| ())
+        };
+        assert_eq!(Ok(()), access_list(1));
+        assert_eq!(
+            Err(TransactionError::InvalidV(U256::from(27))),
+            access_list(27)
+        );
+    }
+
+    #[test]
+    fn should_recover_the_sender() {
+        // The EIP-155 example, signed with the 0x4646..46 private key.
+        let raw = hex::decode("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83").unwrap();
+        let tx = Transaction::decode_enveloped(&raw).unwrap();
+        let sender: Address = ruint::uint!(0x9d8A62f656a8d1615C1294fd71e9CFb3E4855A4F_U160).into();
+        assert_eq!(Ok(sender), tx.sender());
+
+        assert_eq!(
+            Err(TransactionError::MissingSignature),
+            Transaction::default().sender()
+        );
+    }
+
+    #[test]
+    fn should_default_to_realistic_gas_values() {
+        let tx = Transaction::default();
+        assert_eq!(&U256::from(10_000_000_000u64), tx.gas_price());
+        assert_eq!(&U256::from(30_000_000), tx.gas());
+        assert_eq!(TransactionType::Legacy, tx.transaction_type());
+    }
+
+    #[test]
+    fn should_call_a_function_by_signature() {
+        let mut tx = Transaction::default();
+        let to = Address::from([0x35; 0x14]);
+        tx.set_function_call(
+            "transfer(address,uint256)",
+            &[Token::Address(to), Token::Uint(U256::from(1000))],
+        );
+        assert_eq!(4 + 2 * 0x20, tx.data().len());
+        assert_eq!(&[0xA9, 0x05, 0x9C, 0xBB], &tx.data()[..4]);
+        assert_eq!(&[0x35; 0x14], &tx.data()[0x10..0x24]);
+        assert_eq!(
+            U256::from(1000),
+            U256::try_from_be_slice(&tx.data()[0x24..]).unwrap()
+        );
+    }
+}